@@ -1,8 +1,10 @@
+use crate::ci;
 use crate::crypto::EncryptionAPI;
 use crate::structs::{AuthResponse, TokenRequest, TokenResponse};
 use colored::Colorize;
 use reqwest::Client;
 use std::error::Error;
+use std::fmt;
 use std::time::Duration;
 
 const CLIENT_ID: &str = "Iv23lig5KG3cqmAJZy4E";
@@ -10,6 +12,18 @@ const SCOPE: &str = "openid profile email offline_access gist repo";
 const DEVICE_CODE_URL: &str = "https://github.com/login/device/code";
 const TOKEN_URL: &str = "https://github.com/login/oauth/access_token";
 
+/// Authentication failed or can't happen, kept apart from other errors for the exit code.
+#[derive(Debug)]
+pub struct AuthError(pub String);
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for AuthError {}
+
 pub async fn request_device_code() -> Result<AuthResponse, Box<dyn Error>> {
     let client = Client::new();
 
@@ -25,8 +39,7 @@ pub async fn request_device_code() -> Result<AuthResponse, Box<dyn Error>> {
         let auth_response = response.json::<AuthResponse>().await?;
         Ok(auth_response)
     } else {
-        Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::Other,
+        Err(Box::new(std::io::Error::other(
             format!("Failed to get device code: Status {}", response.status()),
         )))
     }
@@ -52,14 +65,34 @@ async fn request_token(device_code: &str) -> Result<TokenResponse, Box<dyn std::
 }
 
 pub async fn authenticate_with_caching() -> Result<String, Box<dyn Error>> {
+    authenticate().await.map_err(|e| match e.downcast::<AuthError>() {
+        Ok(e) => e as Box<dyn Error>,
+        Err(e) => Box::new(AuthError(format!("GitHub authentication failed: {}", e))),
+    })
+}
+
+async fn authenticate() -> Result<String, Box<dyn Error>> {
     let mut crypto = EncryptionAPI::new();
 
-    if let Some(cached) = crypto.get_cached_token()? {
-        if !cached.is_expired {
-            println!("{} Using cached authentication token", "ℹ".blue());
-            return Ok(cached.token);
-        }
-        println!("{} Cached token has expired, re-authenticating...", "ℹ".blue());
+    let cached = crypto.get_cached_token()?;
+    if let Some(cached) = &cached
+        && !cached.is_expired
+    {
+        eprintln!("{} Using cached authentication token", "ℹ".blue());
+        return Ok(cached.token.clone());
+    }
+
+    // Checked before an expired token is cleared: CI can't replace it, so it is left alone
+    if ci::enabled() {
+        return Err(Box::new(AuthError(
+            "GitHub login needed, but --ci can't run the device flow: run spinup once \
+             interactively to cache a token, or pass --config with a file, https or git+ source"
+                .to_string(),
+        )));
+    }
+
+    if cached.is_some() {
+        eprintln!("{} Cached token has expired, re-authenticating...", "ℹ".blue());
         crypto.clear_cached_token()?;
     }
 
@@ -97,18 +130,15 @@ pub async fn authenticate_with_caching() -> Result<String, Box<dyn Error>> {
                     println!("  {} Slowing down polling...", "⏳".yellow());
                 }
                 "expired_token" => {
-                    return Err(Box::new(std::io::Error::new(
-                        std::io::ErrorKind::Other,
+                    return Err(Box::new(std::io::Error::other(
                         "Device code expired. Please run spinup again.",
                     )));
                 }
                 "access_denied" => {
-                    println!("{} Authorization cancelled by user", "ℹ".blue());
-                    std::process::exit(0);
+                    return Err(Box::new(AuthError("Authorization cancelled by user".to_string())));
                 }
                 _ => {
-                    return Err(Box::new(std::io::Error::new(
-                        std::io::ErrorKind::Other,
+                    return Err(Box::new(std::io::Error::other(
                         format!("Authentication error: {}", error),
                     )));
                 }
@@ -0,0 +1,319 @@
+use std::process::{Command, Stdio};
+
+/// A system package manager that spinup can query and drive.
+///
+/// Commands are returned as argv vectors so they can be both previewed and
+/// executed without going through a shell.
+pub trait PackageManager: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Lock held while installing or removing, see `schedule::locks`. Managers that
+    /// need sudo and the system package database share `SYSTEM_LOCK`.
+    fn lock(&self) -> &'static str {
+        SYSTEM_LOCK
+    }
+
+    /// Flags that keep it from asking anything. Without one of them it may prompt, so
+    /// it also holds `SYSTEM_LOCK`, which owns the terminal. Sudo managers hold that
+    /// lock anyway, since sudo itself may ask for a password.
+    fn noninteractive_flags(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Asks the package database (not `$PATH`) whether `package` is installed.
+    fn is_installed(&self, package: &str) -> bool;
+
+    fn install_command(&self, packages: &[String], flags: &[String]) -> Vec<String>;
+
+    fn remove_command(&self, packages: &[String], flags: &[String]) -> Vec<String>;
+
+    /// Packages the user asked for explicitly (not pulled in as dependencies), for
+    /// `spinup capture`. `None` when the manager can't tell them apart.
+    fn user_installed(&self) -> Option<Vec<String>>;
+}
+
+/// Lock of the managers that run through sudo: apt, dnf, pacman, zypper and unknown ones.
+/// Every step that may prompt holds it too, so only one at a time uses the terminal.
+pub const SYSTEM_LOCK: &str = "system";
+
+/// Managers with a native backend; anything else uses the generic fallback.
+pub const KNOWN_MANAGERS: &[&str] = &["dnf", "yum", "apt", "apt-get", "pacman", "zypper", "brew", "flatpak"];
+
+/// Returns the backend for a `[os.manager.<name>]` section. Unknown managers get
+/// a generic backend that keeps the old `sudo <name> install` behaviour.
+pub fn backend_for(manager_name: &str) -> Box<dyn PackageManager> {
+    match manager_name {
+        "dnf" | "yum" => Box::new(Dnf { name: manager_name.to_string() }),
+        "apt" | "apt-get" => Box::new(Apt { name: manager_name.to_string() }),
+        "pacman" => Box::new(Pacman),
+        "zypper" => Box::new(Zypper),
+        "brew" => Box::new(Brew),
+        "flatpak" => Box::new(Flatpak),
+        _ => Box::new(Generic { name: manager_name.to_string() }),
+    }
+}
+
+fn command_succeeds(program: &str, args: &[&str]) -> bool {
+    Command::new(program)
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false)
+}
+
+/// Runs a query and returns its non-empty output lines, or `None` if it failed.
+fn command_lines(program: &str, args: &[&str]) -> Option<Vec<String>> {
+    let output = Command::new(program).args(args).stderr(Stdio::null()).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let mut lines: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect();
+    lines.sort();
+    lines.dedup();
+    Some(lines)
+}
+
+fn argv(prefix: &[&str], flags: &[String], packages: &[String]) -> Vec<String> {
+    prefix
+        .iter()
+        .map(|s| s.to_string())
+        .chain(flags.iter().cloned())
+        .chain(packages.iter().cloned())
+        .collect()
+}
+
+struct Dnf {
+    name: String,
+}
+
+impl PackageManager for Dnf {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn is_installed(&self, package: &str) -> bool {
+        // --whatprovides also resolves virtual names such as `vim` -> vim-enhanced
+        command_succeeds("rpm", &["-q", "--whatprovides", package])
+    }
+
+    fn install_command(&self, packages: &[String], flags: &[String]) -> Vec<String> {
+        argv(&["sudo", &self.name, "install"], flags, packages)
+    }
+
+    fn remove_command(&self, packages: &[String], flags: &[String]) -> Vec<String> {
+        argv(&["sudo", &self.name, "remove"], flags, packages)
+    }
+
+    fn user_installed(&self) -> Option<Vec<String>> {
+        // dnf5 needs the explicit newline, dnf4 adds its own (blank lines are dropped)
+        command_lines(&self.name, &["repoquery", "--userinstalled", "--queryformat", "%{name}\n"])
+    }
+}
+
+struct Apt {
+    name: String,
+}
+
+impl PackageManager for Apt {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn is_installed(&self, package: &str) -> bool {
+        Command::new("dpkg-query")
+            .args(["-W", "-f=${Status}", package])
+            .stderr(Stdio::null())
+            .output()
+            .map(|output| {
+                output.status.success()
+                    && String::from_utf8_lossy(&output.stdout).contains("ok installed")
+            })
+            .unwrap_or(false)
+    }
+
+    fn install_command(&self, packages: &[String], flags: &[String]) -> Vec<String> {
+        argv(&["sudo", &self.name, "install"], flags, packages)
+    }
+
+    fn remove_command(&self, packages: &[String], flags: &[String]) -> Vec<String> {
+        argv(&["sudo", &self.name, "remove"], flags, packages)
+    }
+
+    fn user_installed(&self) -> Option<Vec<String>> {
+        command_lines("apt-mark", &["showmanual"])
+    }
+}
+
+struct Pacman;
+
+impl PackageManager for Pacman {
+    fn name(&self) -> &str {
+        "pacman"
+    }
+
+    fn is_installed(&self, package: &str) -> bool {
+        command_succeeds("pacman", &["-Q", package])
+    }
+
+    fn install_command(&self, packages: &[String], flags: &[String]) -> Vec<String> {
+        argv(&["sudo", "pacman", "-S", "--needed"], flags, packages)
+    }
+
+    fn remove_command(&self, packages: &[String], flags: &[String]) -> Vec<String> {
+        argv(&["sudo", "pacman", "-R"], flags, packages)
+    }
+
+    fn user_installed(&self) -> Option<Vec<String>> {
+        command_lines("pacman", &["-Qqe"])
+    }
+}
+
+struct Zypper;
+
+impl PackageManager for Zypper {
+    fn name(&self) -> &str {
+        "zypper"
+    }
+
+    fn is_installed(&self, package: &str) -> bool {
+        command_succeeds("rpm", &["-q", "--whatprovides", package])
+    }
+
+    fn install_command(&self, packages: &[String], flags: &[String]) -> Vec<String> {
+        argv(&["sudo", "zypper", "install"], flags, packages)
+    }
+
+    fn remove_command(&self, packages: &[String], flags: &[String]) -> Vec<String> {
+        argv(&["sudo", "zypper", "remove"], flags, packages)
+    }
+
+    fn user_installed(&self) -> Option<Vec<String>> {
+        // zypper doesn't record why a package was installed
+        None
+    }
+}
+
+struct Brew;
+
+impl PackageManager for Brew {
+    fn name(&self) -> &str {
+        "brew"
+    }
+
+    // No noninteractive flag: casks may still ask for a sudo password
+    fn lock(&self) -> &'static str {
+        "brew"
+    }
+
+    fn is_installed(&self, package: &str) -> bool {
+        // Covers formulae and casks, including tap-qualified names like derailed/k9s/k9s
+        command_succeeds("brew", &["list", "--versions", package])
+            || command_succeeds("brew", &["list", "--cask", package])
+    }
+
+    fn install_command(&self, packages: &[String], flags: &[String]) -> Vec<String> {
+        argv(&["brew", "install"], flags, packages)
+    }
+
+    fn remove_command(&self, packages: &[String], flags: &[String]) -> Vec<String> {
+        argv(&["brew", "uninstall"], flags, packages)
+    }
+
+    fn user_installed(&self) -> Option<Vec<String>> {
+        let mut packages = command_lines("brew", &["leaves", "--installed-on-request"])?;
+        packages.extend(command_lines("brew", &["list", "--cask", "-1"]).unwrap_or_default());
+        Some(packages)
+    }
+}
+
+struct Flatpak;
+
+impl PackageManager for Flatpak {
+    fn name(&self) -> &str {
+        "flatpak"
+    }
+
+    fn lock(&self) -> &'static str {
+        "flatpak"
+    }
+
+    fn noninteractive_flags(&self) -> &'static [&'static str] {
+        &["-y", "--assumeyes", "--noninteractive"]
+    }
+
+    fn is_installed(&self, package: &str) -> bool {
+        command_succeeds("flatpak", &["info", package])
+    }
+
+    fn install_command(&self, packages: &[String], flags: &[String]) -> Vec<String> {
+        argv(&["flatpak", "install"], flags, packages)
+    }
+
+    fn remove_command(&self, packages: &[String], flags: &[String]) -> Vec<String> {
+        argv(&["flatpak", "uninstall"], flags, packages)
+    }
+
+    fn user_installed(&self) -> Option<Vec<String>> {
+        command_lines("flatpak", &["list", "--app", "--columns=application"])
+    }
+}
+
+struct Generic {
+    name: String,
+}
+
+impl PackageManager for Generic {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn is_installed(&self, package: &str) -> bool {
+        command_succeeds("which", &[package])
+    }
+
+    fn install_command(&self, packages: &[String], flags: &[String]) -> Vec<String> {
+        argv(&["sudo", &self.name, "install"], flags, packages)
+    }
+
+    fn remove_command(&self, packages: &[String], flags: &[String]) -> Vec<String> {
+        argv(&["sudo", &self.name, "remove"], flags, packages)
+    }
+
+    fn user_installed(&self) -> Option<Vec<String>> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_install_commands() {
+        let flags = vec!["-y".to_string()];
+        let packages = vec!["git".to_string(), "ripgrep".to_string()];
+
+        assert_eq!(
+            backend_for("dnf").install_command(&packages, &flags).join(" "),
+            "sudo dnf install -y git ripgrep"
+        );
+        assert_eq!(
+            backend_for("pacman").install_command(&packages, &[]).join(" "),
+            "sudo pacman -S --needed git ripgrep"
+        );
+        assert_eq!(
+            backend_for("brew").remove_command(&packages, &[]).join(" "),
+            "brew uninstall git ripgrep"
+        );
+        assert_eq!(
+            backend_for("xbps").install_command(&packages, &flags).join(" "),
+            "sudo xbps install -y git ripgrep"
+        );
+    }
+}
@@ -0,0 +1,90 @@
+use crate::backends::backend_for;
+use crate::helpers::is_app_installed;
+use crate::structs::{Dotfiles, ManagerConfig, OsConfig, Package};
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Command;
+
+/// One manager per package database; aliases such as yum and apt-get are skipped.
+const CAPTURE_MANAGERS: &[&str] = &["dnf", "apt", "pacman", "zypper", "brew", "flatpak"];
+
+/// An OS section describing what is on this machine right now.
+pub struct Capture {
+    pub os_config: OsConfig,
+    /// Managers that are present but can't list user-installed packages
+    pub unsupported: Vec<String>,
+}
+
+/// Inspects the running system. `dotfiles_target` is the checkout directory to look
+/// for relative to $HOME ("dotfiles" when `None`).
+pub fn capture_system(description: &str, dotfiles_target: Option<&str>) -> Capture {
+    let mut manager = HashMap::new();
+    let mut unsupported = Vec::new();
+
+    for name in CAPTURE_MANAGERS {
+        if !is_app_installed(name) {
+            continue;
+        }
+        match backend_for(name).user_installed() {
+            Some(packages) if !packages.is_empty() => {
+                manager.insert(
+                    name.to_string(),
+                    ManagerConfig {
+                        packages: packages.into_iter().map(Package::from).collect(),
+                        flags: default_flags(name),
+                        depends_on: None,
+                    },
+                );
+            }
+            Some(_) => {}
+            None => unsupported.push(name.to_string()),
+        }
+    }
+
+    Capture {
+        os_config: OsConfig {
+            description: Some(description.to_string()),
+            manager,
+            tasks: HashMap::new(),
+            dotfiles: detect_dotfiles(dotfiles_target.unwrap_or("dotfiles")),
+        },
+        unsupported,
+    }
+}
+
+/// Non-interactive flags so a captured config can be applied unattended.
+pub fn default_flags(manager: &str) -> Vec<String> {
+    match manager {
+        "dnf" | "yum" | "apt" | "apt-get" | "zypper" | "flatpak" => vec!["-y".to_string()],
+        "pacman" => vec!["--noconfirm".to_string()],
+        _ => vec![],
+    }
+}
+
+/// A git checkout at `~/<target>`, described by its `origin` remote (or its path
+/// when it has none).
+fn detect_dotfiles(target: &str) -> Option<Dotfiles> {
+    let home = std::env::var("HOME").ok()?;
+    let path = Path::new(&home).join(target);
+    if !path.join(".git").exists() {
+        return None;
+    }
+
+    let origin = Command::new("git")
+        .arg("-C")
+        .arg(&path)
+        .args(["remote", "get-url", "origin"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .filter(|url| !url.is_empty());
+
+    Some(Dotfiles {
+        repository: origin.unwrap_or_else(|| path.display().to_string()),
+        packages: None,
+        target_directory: Some(target.to_string()),
+        dry_run: None,
+        exclude: Vec::new(),
+    })
+}
@@ -0,0 +1,51 @@
+use crate::auth::AuthError;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Everything worked (for status/diff in CI mode: the machine matches the config).
+pub const SUCCESS: i32 = 0;
+/// Any other failure: bad arguments, a command that couldn't run, a prompt refused.
+pub const FAILURE: i32 = 1;
+/// The config couldn't be read or parsed, has errors, or has no section for this machine.
+pub const CONFIG_ERROR: i32 = 2;
+/// GitHub authentication failed, was denied, or needs the device flow in CI mode.
+pub const AUTH_ERROR: i32 = 3;
+/// The config loaded but some install, task, dotfiles or removal steps failed.
+pub const PARTIAL_FAILURE: i32 = 4;
+/// In CI mode, status or diff found the machine differs from the config.
+pub const DRIFT: i32 = 5;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Turns on CI mode, from `--ci`/`--non-interactive`: no prompts, no screen clearing
+/// or banner, no device flow login, and a non-zero exit from status/diff on drift.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// The exit code for `error`: `AUTH_ERROR` when authentication caused it, `otherwise`
+/// for anything else.
+pub fn exit_code(error: &(dyn Error + 'static), otherwise: i32) -> i32 {
+    if error.is::<AuthError>() {
+        AUTH_ERROR
+    } else {
+        otherwise
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auth_errors_get_their_own_exit_code() {
+        let auth: Box<dyn Error> = Box::new(AuthError("Authorization cancelled".to_string()));
+        let other: Box<dyn Error> = Box::new(std::io::Error::other("Invalid TOML"));
+        assert_eq!(exit_code(auth.as_ref(), CONFIG_ERROR), AUTH_ERROR);
+        assert_eq!(exit_code(other.as_ref(), CONFIG_ERROR), CONFIG_ERROR);
+    }
+}
@@ -1,50 +1,429 @@
-#![allow(dead_code, unused_variables)]
-use crate::structs::Config;
-
-pub async fn run_add_app(
-    _config: &mut Config,
-    _package_name: &str,
-    _install_command: &str,
-    _os_name: Option<&str>,
-    _dependencies: Option<Vec<String>>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Add command is temporarily disabled for v7 migration");
+use crate::auth;
+use crate::commands::apply::confirm;
+use crate::config::parse_config;
+use crate::helpers::{self, os_key_to_edit};
+use crate::migrate::parse_install_command;
+use crate::source::{locate_gist, ConfigSource};
+use crate::structs::{Severity, TaskConfig};
+use crate::validate::validate_config;
+use colored::*;
+use std::error::Error;
+use std::io;
+use toml_edit::{value, Array, DocumentMut, Item, Table, Value};
+
+/// What `spinup add` should put into the config.
+pub enum AddItem {
+    Packages {
+        manager: String,
+        packages: Vec<String>,
+        depends_on: Option<String>,
+    },
+    Task {
+        name: String,
+        task: Box<TaskConfig>,
+    },
+    /// An install command, stored as manager packages when it is a plain
+    /// `<manager> install ...` and as a task otherwise
+    App {
+        name: String,
+        command: String,
+        depends_on: Option<String>,
+    },
+    Dotfiles {
+        repository: String,
+        target: Option<String>,
+    },
+}
+
+pub struct AddOptions {
+    pub dry_run: bool,
+    pub assume_yes: bool,
+    /// Gist to push the result to, overriding the one the config came from
+    pub gist_id: Option<String>,
+}
+
+/// Edits the config from `source` in place, keeping comments and ordering, then saves it
+/// back to where it came from.
+pub async fn run_add(source: &ConfigSource, item: AddItem, options: &AddOptions) -> Result<(), Box<dyn Error>> {
+    let original = source.read().await?;
+    let config = parse_config(&original)?;
+    let os_key = os_key_to_edit(&config);
+
+    let mut document: DocumentMut = original
+        .parse()
+        .map_err(|e| io::Error::other(format!("Invalid TOML: {}", e)))?;
+
+    match item {
+        AddItem::Packages {
+            manager,
+            packages,
+            depends_on,
+        } => run_add_packages(&mut document, &os_key, &manager, &packages, &[], depends_on.as_deref())?,
+        AddItem::Task { name, task } => run_add_task(&mut document, &os_key, &name, &task)?,
+        AddItem::App {
+            name,
+            command,
+            depends_on,
+        } => run_add_app(&mut document, &os_key, &name, &command, depends_on.as_deref())?,
+        AddItem::Dotfiles { repository, target } => {
+            run_add_dotfiles(&mut document, &os_key, &repository, target.as_deref())?
+        }
+    }
+
+    let updated = document.to_string();
+    if updated == original {
+        println!("{} Nothing to change, {} already has it", "ℹ".blue(), source);
+        return Ok(());
+    }
+
+    if let Some(error) = validate_config(&updated)
+        .into_iter()
+        .find(|d| d.severity == Severity::Error)
+    {
+        return Err(Box::new(io::Error::other(format!(
+            "Not saving, the edited config would be invalid: {} (line {})",
+            error.message, error.line
+        ))));
+    }
+
+    println!();
+    print_changes(&original, &updated);
+    println!();
+
+    if options.dry_run {
+        println!("{} Dry run, nothing was saved", "ℹ".blue());
+        return Ok(());
+    }
+
+    save_and_offer_gist_update(source, &updated, options).await
+}
+
+/// Adds packages to `[<os>.manager.<manager>]`, creating the section if needed.
+pub fn run_add_packages(
+    document: &mut DocumentMut,
+    os_key: &str,
+    manager: &str,
+    packages: &[String],
+    flags: &[String],
+    depends_on: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let path = format!("{}.manager.{}", os_key, manager);
+    let added = insert_packages(document, os_key, manager, packages, flags)?;
+
+    for package in packages.iter().filter(|p| !added.contains(p)) {
+        println!("{} {} is already in [{}]", "ℹ".blue(), package, path);
+    }
+    if let Some(depends_on) = depends_on {
+        set_depends_on(table_at(document, &[os_key, "manager", manager])?, &path, depends_on)?;
+    }
+
+    if !added.is_empty() {
+        println!("{} Adding {} to [{}]", "+".green(), added.join(", "), path);
+    }
+    Ok(())
+}
+
+/// Appends the packages that aren't listed yet and returns them. `flags` are only
+/// written when the manager section is new.
+pub fn insert_packages(
+    document: &mut DocumentMut,
+    os_key: &str,
+    manager: &str,
+    packages: &[String],
+    flags: &[String],
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let table = table_at(document, &[os_key, "manager", manager])?;
+    let is_new = !table.contains_key("packages");
+
+    let array = table
+        .entry("packages")
+        .or_insert(value(Array::new()))
+        .as_array_mut()
+        .ok_or_else(|| io::Error::other(format!("[{}.manager.{}] packages is not an array", os_key, manager)))?;
+
+    let mut added = Vec::new();
+    for package in packages {
+        if !array.iter().any(|p| package_name(p) == Some(package)) && !added.contains(package) {
+            push_like_siblings(array, package);
+            added.push(package.clone());
+        }
+    }
+
+    // Same layout as `serialize_config`: one package per line once there are several.
+    // Existing one-line arrays are only reflowed when they'd get unreadably long.
+    let is_one_line = !array.iter().any(|p| p.decor().prefix().and_then(|d| d.as_str()).is_some_and(|d| d.contains('\n')));
+    let too_long = !added.is_empty() && is_one_line && array.to_string().len() > 100;
+    if (is_new && array.len() > 1) || too_long {
+        for package in array.iter_mut() {
+            package.decor_mut().set_prefix("\n    ");
+        }
+        array.set_trailing_comma(true);
+        array.set_trailing("\n");
+    }
+
+    if is_new && !flags.is_empty() {
+        table["flags"] = value(flags.iter().collect::<Array>());
+    }
+    Ok(added)
+}
+
+/// Adds `[<os>.tasks.<name>]`. Existing tasks are never overwritten.
+pub fn run_add_task(document: &mut DocumentMut, os_key: &str, name: &str, task: &TaskConfig) -> Result<(), Box<dyn Error>> {
+    let path = format!("{}.tasks.{}", os_key, name);
+    let tasks = table_at(document, &[os_key, "tasks"])?;
+    if tasks.contains_key(name) {
+        return Err(Box::new(io::Error::other(format!(
+            "[{}] already exists, edit it directly",
+            path
+        ))));
+    }
+
+    let mut table = Table::new();
+    table["script"] = value(task.script.as_str());
+    let optional = [
+        ("description", &task.description),
+        ("depends_on", &task.depends_on),
+        ("check", &task.check),
+        ("creates", &task.creates),
+        ("unless", &task.unless),
+        ("shell", &task.shell),
+        ("cwd", &task.cwd),
+        ("when", &task.when),
+    ];
+    for (key, field) in optional {
+        if let Some(field) = field {
+            table[key] = value(field.as_str());
+        }
+    }
+    tasks.insert(name, Item::Table(table));
+
+    println!("{} Adding task [{}]", "+".green(), path);
     Ok(())
 }
 
-pub async fn run_add_dotfiles(
-    _config: &mut Config,
-    _repository: &str,
-    _target: Option<&str>,
-    _os_name: Option<&str>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Add command is temporarily disabled for v7 migration");
+/// Stores a plain `[sudo] <manager> install <packages>` command as manager packages
+/// and anything else as a task named `name`.
+pub fn run_add_app(
+    document: &mut DocumentMut,
+    os_key: &str,
+    name: &str,
+    command: &str,
+    depends_on: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    match parse_install_command(command) {
+        Some((manager, flags, packages)) => {
+            run_add_packages(document, os_key, &manager, &packages, &flags, depends_on)
+        }
+        None => {
+            let task = TaskConfig {
+                script: command.to_string(),
+                description: None,
+                depends_on: depends_on.map(str::to_string),
+                check: None,
+                creates: None,
+                unless: None,
+                shell: None,
+                when: None,
+                env: Default::default(),
+                cwd: None,
+            };
+            run_add_task(document, os_key, name, &task)
+        }
+    }
+}
+
+/// Sets the dotfiles repository (and optionally target) of `[<os>.dotfiles]`.
+pub fn run_add_dotfiles(
+    document: &mut DocumentMut,
+    os_key: &str,
+    repository: &str,
+    target: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    set_dotfiles(document, os_key, repository, target)?;
+    println!("{} Setting [{}.dotfiles] repository to {}", "+".green(), os_key, repository);
     Ok(())
 }
 
-pub async fn run_add_dependency(
-    _config: &mut Config,
-    _package_name: &str,
-    _install_command: &str,
-    _os_name: Option<&str>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Add command is temporarily disabled for v7 migration");
+pub fn set_dotfiles(document: &mut DocumentMut, os_key: &str, repository: &str, target: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let table = table_at(document, &[os_key, "dotfiles"])?;
+    table["repository"] = value(repository);
+    if let Some(target) = target {
+        // Keep whichever spelling the config already uses
+        let key = if table.contains_key("target_directory") { "target_directory" } else { "target" };
+        table[key] = value(target);
+    }
     Ok(())
 }
 
+/// Writes the edited config back: to the file it came from, or to its gist.
+/// Sources that can't be written (URL, git, stdin) get the result on stdout.
 pub async fn save_and_offer_gist_update(
-    _config: &Config,
-    _config_path: &str,
-    _gist_id: Option<&str>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    Ok(())
+    source: &ConfigSource,
+    content: &str,
+    options: &AddOptions,
+) -> Result<(), Box<dyn Error>> {
+    let gist = match (source, &options.gist_id) {
+        (_, Some(gist_id)) => Some(gist_id.clone()),
+        (ConfigSource::Gist(gist), None) => Some(gist.clone()),
+        _ => None,
+    };
+
+    match source {
+        ConfigSource::File(path) => {
+            std::fs::write(path, content)?;
+            println!("{} Saved {}", "✓".green(), path.display());
+        }
+        ConfigSource::Gist(_) => {}
+        other => {
+            print!("{}", content);
+            eprintln!("{} Can't write back to {}, the updated config is printed above", "ℹ".blue(), other);
+        }
+    }
+
+    let Some(gist) = gist else {
+        return Ok(());
+    };
+    if !options.assume_yes && !confirm(&format!("Update gist:{} with these changes?", gist))? {
+        println!("{} Gist not updated", "ℹ".blue());
+        return Ok(());
+    }
+    update_gist(&gist, content).await
 }
 
-pub async fn update_gist(
-    _config: &Config,
-    _gist_id: &str,
-    _filename: &str,
-    _content: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
+/// Replaces the config file of a gist given by ID or by file name.
+pub async fn update_gist(gist: &str, content: &str) -> Result<(), Box<dyn Error>> {
+    let token = auth::authenticate_with_caching().await?;
+    let location = locate_gist(&token, gist).await?;
+    helpers::update_gist_file(&token, &location.id, &location.file_name, content).await?;
+    println!(
+        "{} Updated {} in gist {}",
+        "✓".green(),
+        location.file_name,
+        location.id
+    );
     Ok(())
 }
+
+/// `"git"` or the `name` of `{ name = "git", when = "..." }`
+fn package_name(entry: &toml_edit::Value) -> Option<&str> {
+    match entry.as_inline_table() {
+        Some(table) => table.get("name").and_then(|name| name.as_str()),
+        None => entry.as_str(),
+    }
+}
+
+/// Walks to the table at `path`, creating missing ones. New tables are implicit so
+/// no empty headers like `[os.tasks]` are written.
+fn table_at<'a>(document: &'a mut DocumentMut, path: &[&str]) -> Result<&'a mut Table, Box<dyn Error>> {
+    let mut table = document.as_table_mut();
+    for (depth, key) in path.iter().enumerate() {
+        let item = table.entry(key).or_insert_with(|| {
+            let mut new_table = Table::new();
+            new_table.set_implicit(true);
+            Item::Table(new_table)
+        });
+        table = item.as_table_mut().ok_or_else(|| {
+            io::Error::other(format!(
+                "[{}] is not a table (inline tables can't be edited), edit it directly",
+                path[..=depth].join(".")
+            ))
+        })?;
+    }
+    Ok(table)
+}
+
+/// Appends to an array so multi-line arrays stay one-value-per-line; one-line arrays
+/// get the default spacing.
+fn push_like_siblings(array: &mut Array, package: &str) {
+    let prefix = array
+        .iter()
+        .last()
+        .and_then(|last| last.decor().prefix())
+        .and_then(|prefix| prefix.as_str())
+        .filter(|prefix| prefix.contains('\n'))
+        .map(str::to_string);
+
+    match prefix {
+        Some(prefix) => array.push_formatted(Value::from(package).decorated(prefix, "")),
+        None => array.push(package),
+    }
+}
+
+fn set_depends_on(table: &mut Table, path: &str, depends_on: &str) -> Result<(), Box<dyn Error>> {
+    match table.get("depends_on").and_then(Item::as_str) {
+        Some(existing) if existing != depends_on => Err(Box::new(io::Error::other(format!(
+            "[{}] already depends on '{}'",
+            path, existing
+        )))),
+        _ => {
+            table["depends_on"] = value(depends_on);
+            Ok(())
+        }
+    }
+}
+
+/// Prints the lines that differ between two versions of the config.
+fn print_changes(original: &str, updated: &str) {
+    let old: Vec<&str> = original.lines().collect();
+    let new: Vec<&str> = updated.lines().collect();
+
+    // Longest common subsequence table, configs are small enough for O(n*m)
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            println!("{}", format!("- {}", old[i]).trim_end().red());
+            i += 1;
+        } else {
+            println!("{}", format!("+ {}", new[j]).trim_end().green());
+            j += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_keeps_comments_and_layout() {
+        let content = r#"version = 7
+
+# Workstation
+[fedora]
+description = "Fedora"
+
+[fedora.manager.dnf]
+# core tools
+packages = [
+    "git",
+    "tmux",
+]
+flags = ["-y"]
+"#;
+        let mut document: DocumentMut = content.parse().unwrap();
+
+        run_add_app(&mut document, "fedora", "ripgrep", "sudo dnf install -y ripgrep", None).unwrap();
+        run_add_app(&mut document, "fedora", "rustup", "curl -sSf https://sh.rustup.rs | sh", None).unwrap();
+
+        let updated = document.to_string();
+        assert!(updated.contains("# Workstation\n[fedora]"));
+        assert!(updated.contains("# core tools\npackages = [\n    \"git\",\n    \"tmux\",\n    \"ripgrep\",\n]"));
+        assert!(updated.contains("[fedora.tasks.rustup]\nscript = \"curl -sSf https://sh.rustup.rs | sh\""));
+        assert!(!updated.contains("[fedora.tasks]\n"));
+        assert!(parse_config(&updated).unwrap().os_entries["fedora"].tasks.contains_key("rustup"));
+    }
+}
@@ -0,0 +1,198 @@
+use crate::ci;
+use crate::commands::diff::{is_diff_empty, print_diff};
+use crate::helpers::{apply_config_diff, get_config_diff, prune_config_diff};
+use crate::journal;
+use crate::structs::{Config, ConfigDiff};
+use colored::*;
+use std::io::{self, BufRead, Write};
+
+pub struct ApplyOptions {
+    pub assume_yes: bool,
+    // Remove packages spinup installed that were dropped from the config, after
+    // everything else is installed
+    pub prune: bool,
+    // Skip steps the last failed or interrupted apply of the same config finished
+    pub resume: bool,
+    // Continue past failures and report them all at the end
+    pub keep_going: bool,
+}
+
+pub async fn run_apply(config: &Config, options: &ApplyOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let mut diff = get_config_diff(config, options.prune).await?;
+
+    if options.resume {
+        match journal::resume_point("apply") {
+            Some(previous) => {
+                let skipped = skip_completed(&mut diff, &previous.completed);
+                println!(
+                    "{} Resuming run {}: skipping {} finished step(s)",
+                    "ℹ".blue(),
+                    previous.id,
+                    skipped
+                );
+            }
+            None => println!("{} No failed apply of this config to resume, applying everything", "ℹ".blue()),
+        }
+        println!();
+    }
+
+    print_diff(&diff, config, options.prune);
+
+    if is_diff_empty(&diff, options.prune) {
+        return Ok(());
+    }
+
+    println!();
+    if !options.assume_yes && !confirm("Apply these changes?")? {
+        println!("{} Aborted, no changes were made", "ℹ".blue());
+        return Ok(());
+    }
+
+    apply_config_diff(&diff, config, options.keep_going).await?;
+    if options.prune {
+        prune_config_diff(&diff, config).await?;
+    }
+
+    println!();
+    println!("{} Apply complete", "✓".green());
+    Ok(())
+}
+
+/// Drops the steps in `completed` (as recorded by the journal, e.g. `task:rustup`) from
+/// `diff`. Returns how many were pending.
+fn skip_completed(diff: &mut ConfigDiff, completed: &[String]) -> usize {
+    let done = |step: String| completed.contains(&step);
+    let before = diff.install_order.len();
+    diff.install_order.retain(|node| !done(node.to_string()));
+    let mut skipped = before - diff.install_order.len();
+
+    diff.packages_to_install.retain(|manager, _| !done(format!("manager:{}", manager)));
+    diff.tasks_to_run.retain(|task| !done(format!("task:{}", task)));
+    if done("dotfiles".to_string())
+        && (diff.dotfiles_diff.needs_clone || !diff.dotfiles_diff.packages_to_apply.is_empty())
+    {
+        diff.dotfiles_diff.needs_clone = false;
+        diff.dotfiles_diff.packages_to_apply.clear();
+        skipped += 1;
+    }
+    skipped
+}
+
+/// Asks a yes/no question on stdin; anything but an explicit yes (including EOF) is a no.
+/// In CI mode nothing is asked: the command fails instead, since it needs --yes.
+pub fn confirm(question: &str) -> Result<bool, Box<dyn std::error::Error>> {
+    if ci::enabled() {
+        return Err(Box::new(io::Error::other(format!(
+            "\"{}\" needs an answer, but --ci never prompts: pass --yes",
+            question
+        ))));
+    }
+
+    print!("{} {} [y/N] ", "?".yellow(), question);
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer)? == 0 {
+        println!();
+        println!("{} No input available, pass --yes to apply without confirmation", "ℹ".blue());
+        return Ok(false);
+    }
+
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::Node;
+    use crate::structs::DotfilesDiff;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_skip_completed() {
+        let mut diff = ConfigDiff {
+            os_name: "Fedora".to_string(),
+            os_key: "fedora".to_string(),
+            profile: None,
+            hosts: Vec::new(),
+            packages_to_install: HashMap::from([
+                ("dnf".to_string(), vec!["git".to_string()]),
+                ("flatpak".to_string(), vec!["org.gimp.GIMP".to_string()]),
+            ]),
+            tasks_to_run: vec!["rustup".to_string(), "fonts".to_string()],
+            install_order: vec![
+                Node::Manager("dnf".to_string()),
+                Node::Task("rustup".to_string()),
+                Node::Manager("flatpak".to_string()),
+                Node::Task("fonts".to_string()),
+            ],
+            packages_to_remove: HashMap::new(),
+            dotfiles_diff: DotfilesDiff {
+                needs_clone: true,
+                packages_to_apply: vec!["zsh".to_string()],
+                packages_already_applied: Vec::new(),
+            },
+        };
+        let completed = ["manager:dnf", "task:rustup", "dotfiles"].map(str::to_string);
+
+        assert_eq!(skip_completed(&mut diff, &completed), 3);
+        assert_eq!(
+            diff.install_order,
+            vec![Node::Manager("flatpak".to_string()), Node::Task("fonts".to_string())]
+        );
+        assert_eq!(diff.packages_to_install.keys().collect::<Vec<_>>(), vec!["flatpak"]);
+        assert_eq!(diff.tasks_to_run, vec!["fonts"]);
+        assert!(!diff.dotfiles_diff.needs_clone);
+        assert!(diff.dotfiles_diff.packages_to_apply.is_empty());
+
+        // Nothing left to skip the second time
+        assert_eq!(skip_completed(&mut diff, &completed), 0);
+    }
+
+    #[tokio::test]
+    async fn test_keep_going_reports_every_failure_as_a_partial_failure() {
+        // State and journal live in a temporary directory; the `unless` guards keep the
+        // tasks pending whatever is on $PATH, and their scripts only need `sh`
+        let _state = crate::state::TestStateDir::new("apply-keep-going");
+        let section = crate::platform::current().section_key().to_string();
+        let config = crate::config::parse_config(&format!(
+            r#"
+version = 7
+
+[{0}.tasks.broken]
+script = "exit 3"
+unless = "false"
+
+[{0}.tasks.after-broken]
+script = "true"
+unless = "false"
+depends_on = "broken"
+
+[{0}.tasks.fine]
+script = "true"
+unless = "false"
+"#,
+            section
+        ))
+        .unwrap();
+        let options = ApplyOptions {
+            assume_yes: true,
+            prune: false,
+            resume: false,
+            keep_going: true,
+        };
+
+        journal::start("apply");
+        let error = run_apply(&config, &options).await.unwrap_err();
+        let message = error.to_string();
+        assert!(message.starts_with("1 of 3 steps failed:"), "{}", message);
+        assert!(message.contains("task:broken"));
+        assert!(message.contains("Skipped because a dependency failed: task:after-broken"));
+        assert_eq!(ci::exit_code(error.as_ref(), ci::PARTIAL_FAILURE), ci::PARTIAL_FAILURE);
+
+        // The unrelated task still ran
+        journal::finish(Some(message));
+        assert_eq!(journal::load("last").unwrap().completed, vec!["task:fine"]);
+        assert!(crate::structs::ManagedState::load().unwrap().packages.is_empty());
+    }
+}
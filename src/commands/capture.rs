@@ -0,0 +1,206 @@
+use crate::backends::backend_for;
+use crate::capture::capture_system;
+use crate::commands::add::{insert_packages, set_dotfiles};
+use crate::config::{parse_config, serialize_config};
+use crate::helpers::{check_current_os_name, find_matching_os, os_key_to_edit};
+use crate::platform;
+use crate::source::ConfigSource;
+use crate::structs::{Config, OsConfig, Package};
+use colored::*;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::io;
+use toml_edit::DocumentMut;
+
+/// Packages added to the config per manager
+type AddedPackages = BTreeMap<String, Vec<String>>;
+
+pub struct CaptureOptions {
+    /// Existing config to merge the capture into (comments and layout are kept)
+    pub merge_into: Option<ConfigSource>,
+    pub output: Option<String>,
+}
+
+/// Writes a v7 config describing this machine to stdout or `--output`. The summary
+/// goes to stderr so the TOML can be redirected.
+pub async fn run_capture(options: &CaptureOptions) -> Result<(), Box<dyn Error>> {
+    let os_description = check_current_os_name().await?;
+
+    let existing = match &options.merge_into {
+        Some(source) => {
+            let content = source.read().await?;
+            let config = parse_config(&content)?;
+            Some((source, content, config))
+        }
+        None => None,
+    };
+
+    let os_key = match &existing {
+        Some((_, _, config)) => os_key_to_edit(config),
+        None => platform::current().section_key().to_string(),
+    };
+
+    // Everything that applies to this machine, including versioned sections
+    let declared = existing
+        .as_ref()
+        .and_then(|(_, _, config)| find_matching_os(config))
+        .map(|(_, os_config)| os_config);
+    let dotfiles_target = declared
+        .as_ref()
+        .and_then(|os| os.dotfiles.as_ref())
+        .and_then(|dotfiles| dotfiles.target_directory.clone());
+
+    eprintln!("{} Inspecting {} for [{}]...", "ℹ".blue(), os_description, os_key);
+    let capture = capture_system(&os_description, dotfiles_target.as_deref());
+
+    let (toml, added) = match &existing {
+        Some((_, content, _)) => merge_capture(content, &os_key, &capture.os_config, declared.as_ref())?,
+        None => {
+            let config = Config {
+                version: 7,
+                common: None,
+                vars: HashMap::new(),
+                profiles: HashMap::new(),
+                hosts: HashMap::new(),
+                os_entries: HashMap::from([(os_key.clone(), capture.os_config.clone())]),
+            };
+            (serialize_config(&config)?, BTreeMap::new())
+        }
+    };
+
+    match &options.output {
+        Some(path) => std::fs::write(path, &toml)?,
+        None => print!("{}", toml),
+    }
+
+    let mut managers: Vec<_> = capture.os_config.manager.iter().collect();
+    managers.sort_by_key(|(name, _)| name.as_str());
+    for (name, manager) in managers {
+        eprintln!("{} {}: {} user-installed package(s)", "✓".green(), name, manager.packages.len());
+    }
+    for name in &capture.unsupported {
+        eprintln!("{} {} can't list user-installed packages, add them by hand", "⚠".yellow(), name);
+    }
+    match &capture.os_config.dotfiles {
+        Some(dotfiles) => eprintln!("{} dotfiles: {}", "✓".green(), dotfiles.repository),
+        None => eprintln!("{} No dotfiles checkout found", "ℹ".blue()),
+    }
+
+    if let (Some((source, _, _)), Some(declared)) = (&existing, &declared) {
+        print_comparison(&source.to_string(), &os_key, &added, declared);
+    }
+
+    if let Some(path) = &options.output {
+        eprintln!("{} Wrote {}", "✓".green(), path);
+    }
+    Ok(())
+}
+
+/// Adds captured packages the config doesn't declare yet and returns the new document
+/// with what was added per manager.
+fn merge_capture(
+    content: &str,
+    os_key: &str,
+    captured: &OsConfig,
+    declared: Option<&OsConfig>,
+) -> Result<(String, AddedPackages), Box<dyn Error>> {
+    let mut document: DocumentMut = content
+        .parse()
+        .map_err(|e| io::Error::other(format!("Invalid TOML: {}", e)))?;
+
+    let mut added = BTreeMap::new();
+    let mut managers: Vec<_> = captured.manager.iter().collect();
+    managers.sort_by_key(|(name, _)| name.as_str());
+    for (name, manager) in managers {
+        let new_packages = insert_packages(&mut document, os_key, name, &manager.package_names(), &manager.flags)?;
+        if !new_packages.is_empty() {
+            added.insert(name.clone(), new_packages);
+        }
+    }
+
+    let has_dotfiles = declared.is_some_and(|os| os.dotfiles.is_some());
+    if let Some(dotfiles) = &captured.dotfiles
+        && !has_dotfiles
+    {
+        set_dotfiles(&mut document, os_key, &dotfiles.repository, dotfiles.target_directory.as_deref())?;
+    }
+
+    Ok((document.to_string(), added))
+}
+
+/// What's on the machine versus what the config declares.
+fn print_comparison(source: &str, os_key: &str, added: &AddedPackages, declared: &OsConfig) {
+    eprintln!();
+    eprintln!("{}", format!("This machine vs {} [{}]", source, os_key).bold().underline());
+
+    if added.is_empty() {
+        eprintln!("  {} Everything installed here is declared", "✓".green());
+    }
+    for (manager, packages) in added {
+        eprintln!(
+            "  {} {} installed here but not declared, added to [{}.manager.{}]:",
+            "+".green(),
+            packages.len(),
+            os_key,
+            manager
+        );
+        eprintln!("    {}", packages.join(", "));
+    }
+
+    let mut managers: Vec<_> = declared.manager.iter().collect();
+    managers.sort_by_key(|(name, _)| name.as_str());
+    for (manager, config) in managers {
+        let backend = backend_for(manager);
+        let missing: Vec<&str> = config
+            .packages
+            .iter()
+            .map(Package::name)
+            .filter(|package| !backend.is_installed(package))
+            .collect();
+        if !missing.is_empty() {
+            eprintln!(
+                "  {} {} declared in [{}.manager.{}] but not installed:",
+                "-".red(),
+                missing.len(),
+                os_key,
+                manager
+            );
+            eprintln!("    {}", missing.join(", "));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::ManagerConfig;
+
+    #[test]
+    fn test_merge_capture_only_adds_undeclared() {
+        let content = "version = 7\n\n[fedora]\n# laptop\n\n[fedora.manager.dnf]\npackages = [\"git\"]\nflags = [\"-y\"]\n";
+        let declared = parse_config(content).unwrap().os_entries["fedora"].clone();
+
+        let manager = |packages: &[&str]| ManagerConfig {
+            packages: packages.iter().map(|p| Package::from(*p)).collect(),
+            flags: vec!["-y".to_string()],
+            depends_on: None,
+        };
+        let captured = OsConfig {
+            description: None,
+            manager: HashMap::from([
+                ("dnf".to_string(), manager(&["git", "tmux"])),
+                ("flatpak".to_string(), manager(&["org.mozilla.firefox"])),
+            ]),
+            tasks: HashMap::new(),
+            dotfiles: None,
+        };
+
+        let (merged, added) = merge_capture(content, "fedora", &captured, Some(&declared)).unwrap();
+
+        assert_eq!(added["dnf"], vec!["tmux"]);
+        assert_eq!(added["flatpak"], vec!["org.mozilla.firefox"]);
+        assert!(merged.contains("# laptop"));
+        assert!(merged.contains("packages = [\"git\", \"tmux\"]"));
+        assert!(merged.contains("[fedora.manager.flatpak]\npackages = [\"org.mozilla.firefox\"]\nflags = [\"-y\"]"));
+    }
+}
@@ -1,18 +1,44 @@
-use crate::helpers::{format_diff_json, get_config_diff};
-use crate::structs::Config;
+use crate::helpers::{format_diff_json, get_config_diff, resolved_os_config, manager_install_command, manager_remove_command, is_in_sync};
+use crate::graph::Node;
+use crate::structs::{Config, ConfigDiff};
 use colored::*;
+use std::collections::HashMap;
 
-pub async fn run_diff(config: &Config, json_output: bool) -> Result<(), Box<dyn std::error::Error>> {
-    let diff = get_config_diff(config).await?;
+/// Shows what apply would do. Returns whether there is nothing to do.
+pub async fn run_diff(config: &Config, json_output: bool, prune: bool) -> Result<bool, Box<dyn std::error::Error>> {
+    // Removals are listed even without --prune
+    let diff = get_config_diff(config, true).await?;
 
     if json_output {
         let json = format_diff_json(&diff)?;
         println!("{}", json);
-        return Ok(());
+        return Ok(is_diff_empty(&diff, prune));
     }
 
+    print_diff(&diff, config, prune);
+
+    Ok(is_diff_empty(&diff, prune))
+}
+
+/// Removals only count when pruning; otherwise they are just reported.
+pub fn is_diff_empty(diff: &ConfigDiff, prune: bool) -> bool {
+    (!prune || diff.packages_to_remove.is_empty())
+        && is_in_sync(
+            diff.packages_to_install.values().chain([&diff.tasks_to_run]),
+            &diff.dotfiles_diff,
+        )
+}
+
+pub fn print_diff(diff: &ConfigDiff, config: &Config, prune: bool) {
     println!("{}", "Configuration Diff".bold().underline());
     println!("Operating System: {}", diff.os_name.blue());
+    if let Some(profile) = &diff.profile {
+        println!("Profile: {}", profile.blue());
+    }
+    if !diff.hosts.is_empty() {
+        let hosts: Vec<String> = diff.hosts.iter().map(|host| format!("[hosts.{}]", host)).collect();
+        println!("Host overrides: {}", hosts.join(", ").blue());
+    }
     println!();
 
     println!("{}", "Applications".bold().underline());
@@ -36,6 +62,20 @@ pub async fn run_diff(config: &Config, json_output: bool) -> Result<(), Box<dyn
         }
     }
 
+    if !diff.packages_to_remove.is_empty() {
+        println!();
+        println!("{}", "No Longer in Config".bold().underline());
+        for (manager, packages) in sorted(&diff.packages_to_remove) {
+            println!("  {} Installed by spinup via {} ({}):", "←".yellow(), manager, packages.len());
+            for package in packages {
+                println!("    - {}", package.yellow());
+            }
+        }
+        if !prune {
+            println!("  {} Kept installed, run `spinup prune` to remove them", "ℹ".blue());
+        }
+    }
+
     println!();
     println!("{}", "Dotfiles".bold().underline());
     let df_diff = &diff.dotfiles_diff;
@@ -54,29 +94,64 @@ pub async fn run_diff(config: &Config, json_output: bool) -> Result<(), Box<dyn
             println!("    - {}", pkg.cyan());
         }
     }
+    if !df_diff.packages_already_applied.is_empty() {
+        println!(
+            "  {} Already stowed: {}",
+            "✓".green(),
+            df_diff.packages_already_applied.join(", ")
+        );
+    }
 
     println!();
-    println!("{}", "Preview: What 'spinup apply' would do".bold().underline());
+    println!("{}", "Preview: What 'spinup apply' will do".bold().underline());
 
-    if !diff.packages_to_install.is_empty() {
-        println!("  1. Install applications:");
-        for (manager, packages) in &diff.packages_to_install {
-             for app in packages {
-                 println!("     $ {} install {}", manager, app); // Simplified preview
-             }
+    let os_config = resolved_os_config(config, &diff.os_key);
+    let mut step = 1;
+    for node in &diff.install_order {
+        match node {
+            Node::Manager(manager) => {
+                let manager_config = os_config.as_ref().and_then(|os| os.manager.get(manager));
+                let packages = diff.packages_to_install.get(manager).map(|p| p.as_slice()).unwrap_or(&[]);
+                println!("  {}. Install via {}:", step, manager);
+                match manager_config {
+                    Some(manager_config) => {
+                        println!("     $ {}", manager_install_command(manager, manager_config, packages))
+                    }
+                    None => {
+                        for app in packages {
+                            println!("     ? {} (no manager configured)", app);
+                        }
+                    }
+                }
+            }
+            Node::Task(task) => {
+                println!("  {}. Run task:", step);
+                println!("     $ execute task: {}", task);
+            }
         }
+        step += 1;
     }
-    
-    if !diff.tasks_to_run.is_empty() {
-        println!("  2. Run tasks:");
-        for task in &diff.tasks_to_run {
-            println!("     $ execute task: {}", task);
+
+    for (category, packages) in &diff.packages_to_install {
+        if os_config.as_ref().is_some_and(|os| os.manager.contains_key(category)) {
+            continue;
+        }
+        for app in packages {
+            println!("     ? {} (no manager configured)", app);
+        }
+    }
+
+    if prune {
+        for (manager, packages) in sorted(&diff.packages_to_remove) {
+            let manager_config = os_config.as_ref().and_then(|os| os.manager.get(manager));
+            println!("  {}. Remove via {}:", step, manager);
+            println!("     $ {}", manager_remove_command(manager, manager_config, packages));
+            step += 1;
         }
     }
 
     if df_diff.needs_clone || !df_diff.packages_to_apply.is_empty() {
-        println!();
-        println!("  3. Setup dotfiles:");
+        println!("  {}. Setup dotfiles:", step);
         if df_diff.needs_clone {
             println!("     $ Clone dotfiles repository");
         }
@@ -87,13 +162,13 @@ pub async fn run_diff(config: &Config, json_output: bool) -> Result<(), Box<dyn
         }
     }
 
-    if diff.packages_to_install.is_empty()
-        && diff.tasks_to_run.is_empty()
-        && !df_diff.needs_clone
-        && df_diff.packages_to_apply.is_empty()
-    {
+    if is_diff_empty(diff, prune) {
         println!("  {} System is up to date!", "✓".green());
     }
+}
 
-    Ok(())
+fn sorted(packages: &HashMap<String, Vec<String>>) -> Vec<(&String, &Vec<String>)> {
+    let mut sorted: Vec<_> = packages.iter().collect();
+    sorted.sort();
+    sorted
 }
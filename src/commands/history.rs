@@ -0,0 +1,126 @@
+use crate::journal;
+use crate::structs::RunJournal;
+use colored::*;
+use prettytable::{format, Cell, Row, Table};
+use std::error::Error;
+
+/// Lists past runs, newest first.
+pub fn run_history(json_output: bool) -> Result<(), Box<dyn Error>> {
+    let runs = journal::list();
+
+    if json_output {
+        println!("{}", serde_json::to_string_pretty(&runs)?);
+        return Ok(());
+    }
+
+    if runs.is_empty() {
+        println!("{} No runs recorded yet in {}", "ℹ".blue(), journal::runs_dir().display());
+        return Ok(());
+    }
+
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_LINESEP_WITH_TITLE);
+    table.set_titles(Row::new(
+        ["ID", "Started (UTC)", "Command", "OS", "Steps", "Duration", "Result"]
+            .iter()
+            .map(|title| Cell::new(title).style_spec("b"))
+            .collect(),
+    ));
+    for run in &runs {
+        table.add_row(Row::new(vec![
+            Cell::new(&run.id),
+            Cell::new(&run.started_at.replace('T', " ").replace('Z', "")),
+            Cell::new(&run.command),
+            Cell::new(run.os_key.as_deref().unwrap_or("-")),
+            Cell::new(&run.steps.len().to_string()),
+            Cell::new(&run.duration_ms.map_or("-".to_string(), format_duration)),
+            Cell::new(&outcome(run).to_string()),
+        ]));
+    }
+    table.printstd();
+    println!("Details: spinup history show <id>");
+    Ok(())
+}
+
+/// Prints one run with each step it took.
+pub fn run_history_show(id: &str, json_output: bool) -> Result<(), Box<dyn Error>> {
+    let run = journal::load(id)?;
+
+    if json_output {
+        println!("{}", serde_json::to_string_pretty(&run)?);
+        return Ok(());
+    }
+
+    println!("{} {}", "Run".bold().underline(), run.id.bold().underline());
+    println!("Command: {}", run.command.blue());
+    println!("Started: {}", run.started_at);
+    if let (Some(finished_at), Some(duration_ms)) = (&run.finished_at, run.duration_ms) {
+        println!("Finished: {} (took {})", finished_at, format_duration(duration_ms));
+    }
+    if let Some(source) = &run.config_source {
+        let hash = run.config_hash.as_deref().map(|hash| &hash[..hash.len().min(12)]).unwrap_or("-");
+        println!("Config: {} ({})", source.blue(), hash);
+    }
+    if let Some(os_key) = &run.os_key {
+        println!("OS section: [{}]", os_key.blue());
+    }
+    if let Some(profile) = &run.profile {
+        println!("Profile: {}", profile.blue());
+    }
+    println!("Result: {}", outcome(&run));
+    if let Some(undone_by) = &run.undone_by {
+        println!("Undone by: {}", undone_by.blue());
+    }
+    if let Some(error) = &run.error {
+        println!("  {}", error.red());
+    }
+
+    if run.steps.is_empty() {
+        println!();
+        println!("{} No commands were run", "ℹ".blue());
+        return Ok(());
+    }
+
+    println!();
+    println!("{}", "Steps".bold().underline());
+    for step in &run.steps {
+        let mark = match step.exit_code {
+            Some(0) => "✓".green(),
+            _ => "✖".red(),
+        };
+        let exit = step.exit_code.map_or("no exit code".to_string(), |code| format!("exit {}", code));
+        println!(
+            "{} {} ({}, {})",
+            mark,
+            step.name.bold(),
+            format_duration(step.duration_ms),
+            exit
+        );
+        for line in step.command.lines() {
+            println!("    $ {}", line.dimmed());
+        }
+        if step.exit_code != Some(0) {
+            for line in &step.stderr_tail {
+                println!("    {}", line.red());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn outcome(run: &RunJournal) -> ColoredString {
+    match (&run.finished_at, &run.error) {
+        _ if run.undone_by.is_some() => "undone".blue(),
+        (None, _) => "interrupted".yellow(),
+        (Some(_), Some(_)) => "failed".red(),
+        (Some(_), None) => "ok".green(),
+    }
+}
+
+fn format_duration(ms: u64) -> String {
+    match ms {
+        ms if ms < 1_000 => format!("{}ms", ms),
+        ms if ms < 60_000 => format!("{:.1}s", ms as f64 / 1000.0),
+        ms => format!("{}m{:02}s", ms / 60_000, ms % 60_000 / 1000),
+    }
+}
@@ -0,0 +1,36 @@
+use crate::config::serialize_config;
+use crate::migrate::migrate_config;
+use colored::*;
+
+/// Converts a v2 JSON or v6 TOML config to v7 and writes it to `output` (stdout when `None`).
+/// The report goes to stderr so stdout can be redirected straight into a file.
+pub fn run_migrate(content: &str, source_name: &str, output: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let migration = migrate_config(content)?;
+    let toml = serialize_config(&migration.config)?;
+
+    match output {
+        Some(path) => std::fs::write(path, &toml)?,
+        None => print!("{}", toml),
+    }
+
+    let target = output.unwrap_or("stdout");
+    eprintln!(
+        "{} Migrated {} from {} to v7 ({})",
+        "✓".green(),
+        source_name,
+        migration.from,
+        target
+    );
+
+    if migration.report.is_empty() {
+        eprintln!("  Everything was translated.");
+    } else {
+        eprintln!("{} {} item(s) need a look:", "⚠".yellow(), migration.report.len());
+        for line in &migration.report {
+            eprintln!("  - {}", line);
+        }
+    }
+    eprintln!("  Run `spinup validate {}` to check the result.", output.unwrap_or("<file>"));
+
+    Ok(())
+}
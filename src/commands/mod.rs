@@ -1,8 +1,23 @@
 pub mod add;
+pub mod apply;
+pub mod capture;
 pub mod diff;
+pub mod history;
+pub mod migrate;
+pub mod profile;
+pub mod prune;
 pub mod status;
+pub mod undo;
+pub mod validate;
 
-#[allow(unused_imports)]
-pub use add::{run_add_app, run_add_dependency, run_add_dotfiles, save_and_offer_gist_update, update_gist};
+pub use add::{run_add, AddItem, AddOptions};
+pub use apply::{run_apply, ApplyOptions};
+pub use capture::{run_capture, CaptureOptions};
 pub use diff::run_diff;
+pub use history::{run_history, run_history_show};
+pub use migrate::run_migrate;
+pub use profile::{run_profile, run_profile_clear, ProfileAction};
+pub use prune::run_prune;
 pub use status::run_status;
+pub use undo::{run_to_undo, run_undo};
+pub use validate::run_validate;
@@ -0,0 +1,53 @@
+use crate::profile;
+use crate::structs::Config;
+use colored::*;
+use std::error::Error;
+
+pub enum ProfileAction {
+    List,
+    Use(String),
+}
+
+/// Lists the config's profiles, or saves one for this machine.
+pub fn run_profile(config: &Config, action: &ProfileAction) -> Result<(), Box<dyn Error>> {
+    match action {
+        ProfileAction::List => {
+            let active = profile::selected();
+            let mut names: Vec<_> = config.profiles.keys().collect();
+            names.sort();
+
+            if names.is_empty() {
+                println!("{} The config defines no profiles, add a [profiles.<name>] section", "ℹ".blue());
+            }
+            for name in names {
+                let marker = if Some(name.as_str()) == active { "*".green() } else { " ".normal() };
+                match &config.profiles[name].description {
+                    Some(description) => println!("{} {} - {}", marker, name.bold(), description),
+                    None => println!("{} {}", marker, name.bold()),
+                }
+            }
+            if active.is_none() {
+                println!("{} No profile selected", "ℹ".blue());
+            }
+        }
+        ProfileAction::Use(name) => {
+            if !config.profiles.contains_key(name) {
+                return Err(Box::new(std::io::Error::other(format!(
+                    "The config has no [profiles.{}] section",
+                    name
+                ))));
+            }
+            profile::save(Some(name))?;
+            println!("{} This machine now uses the {} profile", "✓".green(), name.bold());
+        }
+    }
+    Ok(())
+}
+
+/// Forgets the saved profile. Needs no config, so it works even when the profile is
+/// gone from it.
+pub fn run_profile_clear() -> Result<(), Box<dyn Error>> {
+    profile::save(None)?;
+    println!("{} Profile cleared", "✓".green());
+    Ok(())
+}
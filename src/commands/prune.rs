@@ -0,0 +1,40 @@
+use crate::commands::apply::confirm;
+use crate::helpers::{get_config_diff, resolved_os_config, manager_remove_command, prune_config_diff};
+use crate::structs::Config;
+use colored::*;
+
+/// Removes packages spinup installed that are no longer in the config, leaving
+/// everything else (including packages spinup never installed) alone.
+pub async fn run_prune(config: &Config, assume_yes: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let diff = get_config_diff(config, true).await?;
+    let os_config = resolved_os_config(config, &diff.os_key);
+
+    println!("{}", "Prune".bold().underline());
+    println!("Operating System: {}", diff.os_name.blue());
+    println!();
+
+    if diff.packages_to_remove.is_empty() {
+        println!("  {} Nothing to remove, every package spinup installed is still in the config", "✓".green());
+        return Ok(());
+    }
+
+    let mut managers: Vec<_> = diff.packages_to_remove.iter().collect();
+    managers.sort();
+    for (manager, packages) in managers {
+        let manager_config = os_config.as_ref().and_then(|os| os.manager.get(manager));
+        println!("  {} Remove via {} ({}):", "←".yellow(), manager, packages.join(", "));
+        println!("     $ {}", manager_remove_command(manager, manager_config, packages));
+    }
+
+    println!();
+    if !assume_yes && !confirm("Remove these packages?")? {
+        println!("{} Aborted, nothing was removed", "ℹ".blue());
+        return Ok(());
+    }
+
+    prune_config_diff(&diff, config).await?;
+
+    println!();
+    println!("{} Prune complete", "✓".green());
+    Ok(())
+}
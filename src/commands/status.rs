@@ -1,19 +1,29 @@
-use crate::helpers::{format_status_json, get_system_status};
+use crate::helpers::{format_status_json, get_system_status, is_in_sync};
 use crate::structs::Config;
 use colored::*;
 use prettytable::{format, Cell, Row, Table};
 
-pub async fn run_status(config: &Config, json_output: bool) -> Result<(), Box<dyn std::error::Error>> {
+/// Shows installed vs missing packages and dotfiles. Returns whether the machine is in
+/// sync, as `spinup diff` judges it.
+pub async fn run_status(config: &Config, json_output: bool) -> Result<bool, Box<dyn std::error::Error>> {
     let status = get_system_status(config).await?;
+    let in_sync = is_in_sync(status.missing_packages.values(), &status.dotfiles_diff);
 
     if json_output {
         let json = format_status_json(&status)?;
         println!("{}", json);
-        return Ok(());
+        return Ok(in_sync);
     }
 
     println!("{}", "System Status".bold().underline());
     println!("Operating System: {}", status.os_name.blue());
+    if let Some(profile) = &status.profile {
+        println!("Profile: {}", profile.blue());
+    }
+    if !status.hosts.is_empty() {
+        let hosts: Vec<String> = status.hosts.iter().map(|host| format!("[hosts.{}]", host)).collect();
+        println!("Host overrides: {}", hosts.join(", ").blue());
+    }
     println!();
 
     let mut table = Table::new();
@@ -58,6 +68,14 @@ pub async fn run_status(config: &Config, json_output: bool) -> Result<(), Box<dy
     println!("{}", "Dotfiles Status".bold().underline());
     let df_status = &status.dotfiles_status;
     println!("  Cloned: {}", if df_status.cloned { "✓ Yes".green() } else { "✖ No".red() });
+    if df_status.cloned {
+        let pending = &status.dotfiles_diff.packages_to_apply;
+        println!("  Stowed: {}", if df_status.applied {
+            "✓ Yes".green()
+        } else {
+            format!("✖ Not yet: {}", pending.join(", ")).red()
+        });
+    }
     println!("  Packages: {}", if df_status.packages.is_empty() {
         "Auto-discovery".to_string()
     } else {
@@ -73,5 +91,5 @@ pub async fn run_status(config: &Config, json_output: bool) -> Result<(), Box<dy
     println!("  Installed: {}", total_installed.to_string().green());
     println!("  Missing: {}", total_missing.to_string().red());
 
-    Ok(())
+    Ok(in_sync)
 }
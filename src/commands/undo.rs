@@ -0,0 +1,226 @@
+use crate::backends::backend_for;
+use crate::commands::apply::confirm;
+use crate::helpers::{manager_remove_command, remove_manager_packages, restore_backup};
+use crate::journal;
+use crate::structs::{BackedUpFile, RunJournal, StowedPackage};
+use colored::*;
+use std::error::Error;
+use std::path::Path;
+use std::process::Stdio;
+
+/// Runs that change the machine and can be undone.
+const UNDOABLE: &[&str] = &["apply", "run"];
+
+/// The run `spinup undo` reverses: run `id`, or the newest apply or run not undone yet.
+/// Fails when that run can't be undone.
+pub fn run_to_undo(id: Option<&str>) -> Result<RunJournal, Box<dyn Error>> {
+    let run = match id {
+        Some(id) => journal::load(id)?,
+        None => journal::list()
+            .into_iter()
+            .find(|run| UNDOABLE.contains(&run.command.as_str()) && run.undone_by.is_none() && has_changes(run))
+            .ok_or_else(|| Box::new(std::io::Error::other("No run to undo (see `spinup history`)")) as Box<dyn Error>)?,
+    };
+    if !UNDOABLE.contains(&run.command.as_str()) {
+        return Err(Box::new(std::io::Error::other(format!(
+            "Run {} was `spinup {}`, only apply and run can be undone",
+            run.id, run.command
+        ))));
+    }
+    if let Some(undone_by) = &run.undone_by {
+        return Err(Box::new(std::io::Error::other(format!(
+            "Run {} was already undone by run {}",
+            run.id, undone_by
+        ))));
+    }
+    Ok(run)
+}
+
+/// What undoing a run does, in order.
+struct UndoPlan<'a> {
+    // Newest first: links may point into packages' files
+    unstow: Vec<&'a StowedPackage>,
+    // Files stow conflicted with, put back once the links are gone
+    restore: Vec<&'a BackedUpFile>,
+    remove: Vec<(&'a str, Vec<String>)>,
+    // Only listed, since spinup can't know what reverses them
+    manual: Vec<String>,
+}
+
+impl UndoPlan<'_> {
+    fn is_empty(&self) -> bool {
+        self.unstow.is_empty() && self.restore.is_empty() && self.remove.is_empty()
+    }
+}
+
+/// Plans undoing `run`. Only packages `is_installed(manager, package)` still reports
+/// are removed: one removed by hand since needs no undoing.
+fn plan_undo(run: &RunJournal, is_installed: impl Fn(&str, &str) -> bool) -> UndoPlan<'_> {
+    let remove = run
+        .installed
+        .iter()
+        .map(|(manager, packages)| {
+            let installed = packages.iter().filter(|package| is_installed(manager, package)).cloned().collect();
+            (manager.as_str(), installed)
+        })
+        .filter(|(_, packages): &(_, Vec<String>)| !packages.is_empty())
+        .collect();
+    let manual = run
+        .completed
+        .iter()
+        .filter_map(|step| step.strip_prefix("task:"))
+        .map(|task| format!("task {} (an arbitrary script)", task))
+        .chain(run.cloned.iter().map(|path| format!("the dotfiles checkout at {}", path)))
+        .collect();
+    UndoPlan {
+        unstow: run.stowed.iter().rev().collect(),
+        restore: run.backed_up.iter().rev().collect(),
+        remove,
+        manual,
+    }
+}
+
+/// Reverses `run`: unlinks the stow packages it linked, moves back the files it backed
+/// up for them, and removes the packages it installed that are still installed. Tasks
+/// and the dotfiles checkout are only listed.
+pub async fn run_undo(run: &RunJournal, assume_yes: bool) -> Result<(), Box<dyn Error>> {
+    let plan = plan_undo(run, |manager, package| backend_for(manager).is_installed(package));
+
+    println!("{} {}", "Undo run".bold().underline(), run.id.bold().underline());
+    println!("Started: {}, `spinup {}`", run.started_at, run.command);
+    println!();
+
+    for stowed in &plan.unstow {
+        println!("  {} Unlink {} from {}", "←".yellow(), stowed.package, stowed.directory);
+        println!("     $ stow -D -d {} {}", stowed.directory, stowed.package);
+    }
+    for backed_up in &plan.restore {
+        println!("  {} Restore {} from {}", "←".yellow(), backed_up.original, backed_up.backup);
+    }
+    for (manager, packages) in &plan.remove {
+        println!("  {} Remove via {} ({}):", "←".yellow(), manager, packages.join(", "));
+        println!("     $ {}", manager_remove_command(manager, None, packages));
+    }
+    if plan.is_empty() {
+        println!("  {} Nothing spinup can reverse automatically", "ℹ".blue());
+    }
+
+    if !plan.manual.is_empty() {
+        println!();
+        println!("{} Not reversed, handle these yourself:", "⚠".yellow());
+        for item in &plan.manual {
+            println!("    - {}", item);
+        }
+    }
+
+    if plan.is_empty() {
+        journal::mark_undone(&run.id)?;
+        return Ok(());
+    }
+
+    println!();
+    if !assume_yes && !confirm("Undo these changes?")? {
+        println!("{} Aborted, no changes were made", "ℹ".blue());
+        return Ok(());
+    }
+
+    for stowed in &plan.unstow {
+        unstow(stowed).await?;
+    }
+    for backed_up in &plan.restore {
+        if restore_backup(backed_up)? {
+            println!("  {} Restored {}", "✓".green(), backed_up.original);
+        } else {
+            println!("  {} {} is gone, skipping", "ℹ".blue(), backed_up.backup);
+        }
+    }
+    for (manager, packages) in &plan.remove {
+        remove_manager_packages(manager, None, packages).await?;
+    }
+    journal::mark_undone(&run.id)?;
+
+    println!();
+    println!("{} Undo complete", "✓".green());
+    Ok(())
+}
+
+fn has_changes(run: &RunJournal) -> bool {
+    !run.installed.is_empty() || !run.stowed.is_empty() || !run.backed_up.is_empty() || !run.completed.is_empty()
+}
+
+async fn unstow(stowed: &StowedPackage) -> Result<(), Box<dyn Error>> {
+    if !Path::new(&stowed.directory).join(&stowed.package).exists() {
+        println!("  {} {} is gone from {}, skipping", "ℹ".blue(), stowed.package, stowed.directory);
+        return Ok(());
+    }
+
+    let mut command = tokio::process::Command::new("stow");
+    command
+        .args(["-D", "-d", &stowed.directory, &stowed.package])
+        .stdout(Stdio::inherit());
+    let shown = format!("stow -D -d {} {}", stowed.directory, stowed.package);
+    let status = journal::run_step(&format!("dotfiles:{}", stowed.package), &shown, command)
+        .await
+        .map_err(|e| std::io::Error::other(format!("Failed to run stow: {}", e)))?;
+
+    if status.success() {
+        println!("  {} Unlinked {}", "✓".green(), stowed.package);
+        Ok(())
+    } else {
+        Err(Box::new(std::io::Error::other(format!(
+            "Failed to unlink {}: stow exited with code {:?}",
+            stowed.package,
+            status.code()
+        ))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan_undo() {
+        let run: RunJournal = serde_json::from_str(
+            r#"{
+                "id": "20261017-143012",
+                "command": "apply",
+                "started_at": "2026-10-17T14:30:12Z",
+                "finished_at": null,
+                "duration_ms": null,
+                "config_source": null,
+                "config_hash": null,
+                "os_key": "fedora",
+                "profile": null,
+                "steps": [],
+                "completed": ["manager:dnf", "task:rustup", "dotfiles"],
+                "error": null,
+                "installed": {"dnf": ["git", "htop"], "flatpak": ["org.gimp.GIMP"]},
+                "stowed": [
+                    {"directory": "/home/me/dotfiles", "package": "zsh"},
+                    {"directory": "/home/me/dotfiles", "package": "nvim"}
+                ],
+                "backed_up": [{"original": "/home/me/.zshrc", "backup": "/home/me/.spinup/backups/20261017-143012/.zshrc"}],
+                "cloned": "/home/me/dotfiles"
+            }"#,
+        )
+        .unwrap();
+
+        // htop and the GIMP were removed by hand since
+        let plan = plan_undo(&run, |manager, package| manager == "dnf" && package == "git");
+        let unstow: Vec<&str> = plan.unstow.iter().map(|stowed| stowed.package.as_str()).collect();
+        assert_eq!(unstow, vec!["nvim", "zsh"]);
+        assert_eq!(plan.restore.len(), 1);
+        assert_eq!(plan.restore[0].original, "/home/me/.zshrc");
+        assert_eq!(plan.remove, vec![("dnf", vec!["git".to_string()])]);
+        assert_eq!(
+            plan.manual,
+            vec!["task rustup (an arbitrary script)", "the dotfiles checkout at /home/me/dotfiles"]
+        );
+        assert!(!plan.is_empty());
+
+        let plan = plan_undo(&run, |_, _| false);
+        assert!(plan.remove.is_empty());
+        assert_eq!(plan.unstow.len(), 2);
+    }
+}
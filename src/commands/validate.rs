@@ -0,0 +1,50 @@
+use crate::structs::Severity;
+use crate::validate::validate_config;
+use colored::*;
+
+/// Prints every diagnostic for `content` and returns whether the config is free of errors.
+pub fn run_validate(content: &str, source_name: &str, json_output: bool) -> Result<bool, Box<dyn std::error::Error>> {
+    let diagnostics = validate_config(content);
+    let errors = diagnostics.iter().filter(|d| d.severity == Severity::Error).count();
+    let warnings = diagnostics.len() - errors;
+
+    if json_output {
+        println!("{}", serde_json::to_string_pretty(&diagnostics)?);
+        return Ok(errors == 0);
+    }
+
+    for diagnostic in &diagnostics {
+        let label = match diagnostic.severity {
+            Severity::Error => "error".red().bold(),
+            Severity::Warning => "warning".yellow().bold(),
+        };
+        println!("{}: {}", label, diagnostic.message.bold());
+        println!(
+            "  {} {}:{}:{}",
+            "-->".blue(),
+            source_name,
+            diagnostic.line,
+            diagnostic.column
+        );
+        if let Some(help) = &diagnostic.help {
+            println!("   {} help: {}", "=".blue(), help);
+        }
+        println!();
+    }
+
+    if errors == 0 && warnings == 0 {
+        println!("{} {} is valid", "✓".green(), source_name);
+    } else if errors == 0 {
+        println!("{} {} is valid with {} warning(s)", "✓".green(), source_name, warnings);
+    } else {
+        println!(
+            "{} {} has {} error(s) and {} warning(s)",
+            "✖".red(),
+            source_name,
+            errors,
+            warnings
+        );
+    }
+
+    Ok(errors == 0)
+}
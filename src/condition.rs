@@ -0,0 +1,414 @@
+use crate::platform::{self, glob_match};
+use colored::Colorize;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+/// Facts a `when` condition can test, for error messages and the validator.
+pub const FACTS: &[&str] = &["arch", "hostname", "os", "os_version", "display", "container", "wsl", "env.<NAME>"];
+
+static CURRENT: OnceLock<Facts> = OnceLock::new();
+
+/// What spinup knows about the machine when evaluating `when` conditions.
+#[derive(Debug, Clone, Default)]
+pub struct Facts {
+    /// e.g. x86_64, aarch64
+    pub arch: String,
+    pub hostname: Option<String>,
+    /// Detected OS keys, most specific first; `os == ubuntu` holds on Pop!_OS too
+    pub os: Vec<String>,
+    /// VERSION_ID, e.g. 41 or 24.04
+    pub os_version: Option<String>,
+    /// A graphical session is available (X11, Wayland or macOS)
+    pub display: bool,
+    /// Running inside Docker, Podman or another container
+    pub container: bool,
+    /// Running under Windows Subsystem for Linux
+    pub wsl: bool,
+    pub env: HashMap<String, String>,
+}
+
+impl Facts {
+    pub fn detect() -> Self {
+        let platform = platform::current();
+        let env: HashMap<String, String> = std::env::vars().collect();
+        let has_env = |name: &str| env.get(name).is_some_and(|value| !value.is_empty());
+
+        let display = cfg!(target_os = "macos") || has_env("DISPLAY") || has_env("WAYLAND_DISPLAY");
+        let container = has_env("container")
+            || Path::new("/.dockerenv").exists()
+            || Path::new("/run/.containerenv").exists();
+        let wsl = has_env("WSL_DISTRO_NAME")
+            || std::fs::read_to_string("/proc/sys/kernel/osrelease")
+                .is_ok_and(|release| release.to_lowercase().contains("microsoft"));
+
+        Facts {
+            arch: std::env::consts::ARCH.to_string(),
+            hostname: platform.hostname.clone(),
+            os: platform
+                .candidates
+                .iter()
+                .filter(|key| platform::split_version(key).is_none())
+                .cloned()
+                .collect(),
+            os_version: platform.version_id.clone(),
+            display,
+            container,
+            wsl,
+            env,
+        }
+    }
+}
+
+/// The facts of this machine, detected once per run.
+pub fn current() -> &'static Facts {
+    CURRENT.get_or_init(Facts::detect)
+}
+
+/// A parsed `when` condition, e.g. `arch == x86_64 && !container`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    Fact(Fact),
+    Compare(Fact, Op, String),
+    Not(Box<Condition>),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fact {
+    Arch,
+    Hostname,
+    Os,
+    OsVersion,
+    Display,
+    Container,
+    Wsl,
+    Env(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Op(Op),
+    Not,
+    And,
+    Or,
+    Open,
+    Close,
+}
+
+impl Condition {
+    pub fn parse(source: &str) -> Result<Condition, String> {
+        let tokens = tokenize(source)?;
+        if tokens.is_empty() {
+            return Err("empty condition".to_string());
+        }
+        let mut parser = Parser { tokens, position: 0 };
+        let condition = parser.or()?;
+        match parser.tokens.get(parser.position) {
+            None => Ok(condition),
+            Some(token) => Err(format!("unexpected {}", describe(token))),
+        }
+    }
+
+    pub fn eval(&self, facts: &Facts) -> bool {
+        match self {
+            Condition::Fact(fact) => match fact {
+                Fact::Display => facts.display,
+                Fact::Container => facts.container,
+                Fact::Wsl => facts.wsl,
+                Fact::Env(name) => facts.env.get(name).is_some_and(|value| !value.is_empty()),
+                // Only the boolean facts parse on their own
+                _ => false,
+            },
+            Condition::Compare(fact, op, expected) => compare(fact, *op, expected, facts),
+            Condition::Not(inner) => !inner.eval(facts),
+            Condition::And(left, right) => left.eval(facts) && right.eval(facts),
+            Condition::Or(left, right) => left.eval(facts) || right.eval(facts),
+        }
+    }
+}
+
+/// Parses and evaluates `source` against `facts`.
+pub fn evaluate(source: &str, facts: &Facts) -> Result<bool, String> {
+    Condition::parse(source).map(|condition| condition.eval(facts))
+}
+
+/// Whether `source` holds on this machine. A broken condition counts as false and is
+/// reported once; `spinup validate` points at it.
+pub fn holds(source: &str) -> bool {
+    static REPORTED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    evaluate(source, current()).unwrap_or_else(|e| {
+        let mut reported = REPORTED.lock().unwrap_or_else(|e| e.into_inner());
+        if !reported.iter().any(|r| r == source) {
+            eprintln!("{} Invalid when = \"{}\": {}, skipping the entry", "⚠".yellow(), source, e);
+            reported.push(source.to_string());
+        }
+        false
+    })
+}
+
+fn compare(fact: &Fact, op: Op, expected: &str, facts: &Facts) -> bool {
+    let values: Vec<String> = match fact {
+        Fact::Arch => vec![facts.arch.clone()],
+        Fact::Hostname => facts.hostname.iter().map(|host| host.to_lowercase()).collect(),
+        Fact::Os => facts.os.clone(),
+        Fact::OsVersion => facts.os_version.iter().cloned().collect(),
+        Fact::Env(name) => facts.env.get(name).iter().map(|value| value.to_string()).collect(),
+        Fact::Display => vec![facts.display.to_string()],
+        Fact::Container => vec![facts.container.to_string()],
+        Fact::Wsl => vec![facts.wsl.to_string()],
+    };
+    let expected = if *fact == Fact::Hostname { expected.to_lowercase() } else { expected.to_string() };
+
+    match op {
+        // `os` holds when any detected key matches; `!=` when none does
+        Op::Eq => values.iter().any(|value| glob_match(&expected, value)),
+        Op::Ne => !values.iter().any(|value| glob_match(&expected, value)),
+        _ => values.iter().any(|value| {
+            let ordering = compare_versions(value, &expected);
+            match op {
+                Op::Lt => ordering == Ordering::Less,
+                Op::Le => ordering != Ordering::Greater,
+                Op::Gt => ordering == Ordering::Greater,
+                _ => ordering != Ordering::Less,
+            }
+        }),
+    }
+}
+
+/// Compares dotted versions numerically where possible, so 9.10 > 9.9 and 24.04 > 22.10.
+fn compare_versions(left: &str, right: &str) -> Ordering {
+    let mut left_parts = left.split(['.', '-']);
+    let mut right_parts = right.split(['.', '-']);
+    loop {
+        match (left_parts.next(), right_parts.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(l), Some(r)) => {
+                let ordering = match (l.parse::<u64>(), r.parse::<u64>()) {
+                    (Ok(l), Ok(r)) => l.cmp(&r),
+                    _ => l.cmp(r),
+                };
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' | ')' => {
+                chars.next();
+                tokens.push(if c == '(' { Token::Open } else { Token::Close });
+            }
+            '\'' | '"' => {
+                chars.next();
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some(q) if q == c => break,
+                        Some(ch) => value.push(ch),
+                        None => return Err(format!("unterminated string `{}{}`", c, value)),
+                    }
+                }
+                tokens.push(Token::Quoted(value));
+            }
+            '&' | '|' => {
+                chars.next();
+                if chars.next() != Some(c) {
+                    return Err(format!("expected `{}{}`", c, c));
+                }
+                tokens.push(if c == '&' { Token::And } else { Token::Or });
+            }
+            '=' | '!' | '<' | '>' => {
+                chars.next();
+                let eq = chars.next_if_eq(&'=').is_some();
+                tokens.push(match (c, eq) {
+                    ('=', true) => Token::Op(Op::Eq),
+                    ('=', false) => return Err("expected `==`".to_string()),
+                    ('!', true) => Token::Op(Op::Ne),
+                    ('!', false) => Token::Not,
+                    ('<', true) => Token::Op(Op::Le),
+                    ('<', false) => Token::Op(Op::Lt),
+                    ('>', true) => Token::Op(Op::Ge),
+                    _ => Token::Op(Op::Gt),
+                });
+            }
+            c if is_word_char(c) => {
+                let mut word = String::new();
+                while let Some(ch) = chars.next_if(|ch| is_word_char(*ch)) {
+                    word.push(ch);
+                }
+                tokens.push(Token::Word(word));
+            }
+            other => return Err(format!("unexpected character `{}`", other)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '.' | '-' | '*' | '?' | '/' | ':')
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Word(word) => format!("`{}`", word),
+        Token::Quoted(value) => format!("'{}'", value),
+        Token::Op(op) => format!("operator {:?}", op),
+        Token::Not => "`!`".to_string(),
+        Token::And => "`&&`".to_string(),
+        Token::Or => "`||`".to_string(),
+        Token::Open => "`(`".to_string(),
+        Token::Close => "`)`".to_string(),
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn or(&mut self) -> Result<Condition, String> {
+        let mut condition = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            condition = Condition::Or(Box::new(condition), Box::new(self.and()?));
+        }
+        Ok(condition)
+    }
+
+    fn and(&mut self) -> Result<Condition, String> {
+        let mut condition = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            condition = Condition::And(Box::new(condition), Box::new(self.unary()?));
+        }
+        Ok(condition)
+    }
+
+    fn unary(&mut self) -> Result<Condition, String> {
+        match self.next() {
+            Some(Token::Not) => Ok(Condition::Not(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let condition = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(condition),
+                    _ => Err("missing `)`".to_string()),
+                }
+            }
+            Some(Token::Word(word)) => {
+                let fact = parse_fact(&word)?;
+                let Some(Token::Op(op)) = self.peek().cloned() else {
+                    return match fact {
+                        Fact::Display | Fact::Container | Fact::Wsl | Fact::Env(_) => Ok(Condition::Fact(fact)),
+                        _ => Err(format!("`{}` needs a comparison, e.g. `{} == ...`", word, word)),
+                    };
+                };
+                self.next();
+                match self.next() {
+                    Some(Token::Word(value) | Token::Quoted(value)) => Ok(Condition::Compare(fact, op, value)),
+                    Some(token) => Err(format!("expected a value after the operator, found {}", describe(&token))),
+                    None => Err("expected a value after the operator".to_string()),
+                }
+            }
+            Some(token) => Err(format!("unexpected {}", describe(&token))),
+            None => Err("unexpected end of condition".to_string()),
+        }
+    }
+}
+
+fn parse_fact(word: &str) -> Result<Fact, String> {
+    match word {
+        "arch" => Ok(Fact::Arch),
+        "hostname" => Ok(Fact::Hostname),
+        "os" => Ok(Fact::Os),
+        "os_version" => Ok(Fact::OsVersion),
+        "display" => Ok(Fact::Display),
+        "container" => Ok(Fact::Container),
+        "wsl" => Ok(Fact::Wsl),
+        _ => match word.strip_prefix("env.") {
+            Some(name) if !name.is_empty() => Ok(Fact::Env(name.to_string())),
+            _ => Err(format!("unknown fact `{}` (known: {})", word, FACTS.join(", "))),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn facts() -> Facts {
+        Facts {
+            arch: "x86_64".to_string(),
+            hostname: Some("build-03".to_string()),
+            os: vec!["pop".to_string(), "ubuntu".to_string(), "debian".to_string(), "linux".to_string()],
+            os_version: Some("22.04".to_string()),
+            display: true,
+            container: false,
+            wsl: false,
+            env: HashMap::from([("CI".to_string(), "true".to_string())]),
+        }
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let facts = facts();
+        let holds = |source: &str| evaluate(source, &facts).unwrap();
+
+        assert!(holds("arch == x86_64"));
+        assert!(holds("os == ubuntu && os_version >= 22.04"));
+        assert!(holds("os_version < '22.10'"));
+        assert!(holds("hostname == 'BUILD-*' && !container"));
+        assert!(holds("display && (wsl || env.CI)"));
+        assert!(!holds("arch != x86_64 || env.HOME_UNSET"));
+        assert!(!holds("os == fedora"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Condition::parse("arch =").is_err());
+        assert!(Condition::parse("gpu").unwrap_err().contains("unknown fact"));
+        assert!(Condition::parse("arch").unwrap_err().contains("needs a comparison"));
+        assert!(Condition::parse("(display").is_err());
+        assert!(Condition::parse("display display").is_err());
+        assert!(Condition::parse("").is_err());
+    }
+}
@@ -1,32 +1,30 @@
+use crate::validate::ROOT_KEYS;
 use crate::structs::Config;
-use reqwest;
+use schemars::schema::Schema;
 use std::error::Error;
 use std::io;
 
-pub async fn fetch_config_from_gist(url: &str) -> Result<Config, Box<dyn Error>> {
-    let response = reqwest::get(url).await?.error_for_status()?;
-    let content = response.text().await?;
+pub fn read_config_file(path: &str) -> Result<Config, Box<dyn Error>> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("Failed to read {}: {}", path, e)))?;
     parse_config(&content)
 }
 
 const OLD_JSON_ERROR: &str = r#"Detected old JSON config format with $schema field.
 
-The new SpinUp version uses TOML configuration. Please migrate your config to TOML format.
+The new SpinUp version uses TOML configuration. Convert it with:
 
-Example TOML config:
-version = 6
+    spinup migrate <file> --output config.toml
 
-[fedora]
-description = "Fedora Linux"
+See schema.md for the full schema reference."#;
 
-[fedora.applications]
-git = "sudo dnf install -y git"
-neovim = "sudo dnf install -y neovim"
+const V6_ERROR: &str = r#"Detected a v6 config with [<os>.applications] or [<os>.dependencies] tables, which v7 ignores.
 
-[fedora.dotfiles]
-repository = "https://github.com/yourusername/dotfiles.git"
+Convert it with:
 
-See docs/schema.toml for the full schema reference."#;
+    spinup migrate <file> --output config.toml
+
+See schema.md for the full schema reference."#;
 
 pub fn parse_config(content: &str) -> Result<Config, Box<dyn Error>> {
     let trimmed = content.trim_start();
@@ -36,16 +34,14 @@ pub fn parse_config(content: &str) -> Result<Config, Box<dyn Error>> {
         let json_value = match json_result {
             Ok(v) => v,
             Err(e) => {
-                return Err(Box::new(io::Error::new(
-                    io::ErrorKind::Other,
+                return Err(Box::new(io::Error::other(
                     format!("Invalid JSON: {}", e),
                 )));
             }
         };
 
         if json_value.get("$schema").is_some() {
-            return Err(Box::new(io::Error::new(
-                io::ErrorKind::Other,
+            return Err(Box::new(io::Error::other(
                 OLD_JSON_ERROR.to_string(),
             )));
         }
@@ -53,33 +49,59 @@ pub fn parse_config(content: &str) -> Result<Config, Box<dyn Error>> {
         let config = match serde_json::from_value(json_value) {
             Ok(c) => c,
             Err(e) => {
-                return Err(Box::new(io::Error::new(
-                    io::ErrorKind::Other,
+                return Err(Box::new(io::Error::other(
                     format!("Invalid config structure: {}", e),
                 )));
             }
         };
         Ok(config)
     } else if trimmed.starts_with("[") || trimmed.contains("[") {
+        if has_v6_sections(content) {
+            return Err(Box::new(io::Error::other(V6_ERROR.to_string())));
+        }
         match toml::from_str(content) {
             Ok(c) => Ok(c),
-            Err(e) => Err(Box::new(io::Error::new(
-                io::ErrorKind::Other,
+            Err(e) => Err(Box::new(io::Error::other(
                 format!("Invalid TOML: {}", e),
             ))),
         }
     } else {
         match toml::from_str(content) {
             Ok(c) => Ok(c),
-            Err(_) => Err(Box::new(io::Error::new(
-                io::ErrorKind::Other,
+            Err(_) => Err(Box::new(io::Error::other(
                 "Unknown config format. Expected JSON (starts with '{') or TOML.",
             ))),
         }
     }
 }
 
-#[allow(dead_code)]
+/// v6 tables deserialize into v7 structs without error, so look for them explicitly.
+/// Only OS sections count: `[vars]` or a profile may well use those names.
+fn has_v6_sections(content: &str) -> bool {
+    let Ok(root) = content.parse::<toml::Table>() else {
+        return false;
+    };
+    root.iter()
+        .filter(|(key, _)| !ROOT_KEYS.contains(&key.as_str()))
+        .filter_map(|(_, value)| value.as_table())
+        .any(|os| os.contains_key("applications") || os.contains_key("dependencies"))
+}
+
+/// JSON Schema for the v7 config, generated from the config structs so it can't drift.
+pub fn config_json_schema() -> Result<String, Box<dyn Error>> {
+    let mut schema = schemars::schema_for!(Config);
+    schema.schema.metadata().title = Some("SpinUp configuration".to_string());
+
+    // schemars drops flattened maps, so describe the [os] sections explicitly
+    schema.schema.object().additional_properties = Some(Box::new(Schema::new_ref(
+        "#/definitions/OsConfig".to_string(),
+    )));
+    serde_json::to_string_pretty(&schema).map_err(|e| Box::new(e) as Box<dyn Error>)
+}
+
+/// Serializes through `toml::Value` so sections and keys come out sorted instead of in
+/// `HashMap` order.
 pub fn serialize_config(config: &Config) -> Result<String, Box<dyn Error>> {
-    toml::to_string_pretty(config).map_err(|e| Box::new(e) as Box<dyn Error>)
+    let value = toml::Value::try_from(config)?;
+    toml::to_string_pretty(&value).map_err(|e| Box::new(e) as Box<dyn Error>)
 }
@@ -78,8 +78,7 @@ impl EncryptionAPI {
                     self.cipher = Some(c);
                 }
                 Err(e) => {
-                    return Err(Box::new(std::io::Error::new(
-                        std::io::ErrorKind::Other,
+                    return Err(Box::new(std::io::Error::other(
                         format!("Failed to create cipher: {}", e),
                     )));
                 }
@@ -100,8 +99,7 @@ impl EncryptionAPI {
         let ciphertext = cipher
             .encrypt(&Nonce::from(nonce), token.as_bytes())
             .map_err(|e| {
-                Box::new(std::io::Error::new(
-                    std::io::ErrorKind::Other,
+                Box::new(std::io::Error::other(
                     format!("Encryption failed: {}", e),
                 ))
             })?;
@@ -129,15 +127,13 @@ impl EncryptionAPI {
                 encrypted.ciphertext.as_slice(),
             )
             .map_err(|e| {
-                Box::new(std::io::Error::new(
-                    std::io::ErrorKind::Other,
+                Box::new(std::io::Error::other(
                     format!("Decryption failed: {}", e),
                 ))
             })?;
 
         let token = String::from_utf8(plaintext).map_err(|e| {
-            Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
+            Box::new(std::io::Error::other(
                 format!("Invalid token encoding: {}", e),
             ))
         })?;
@@ -223,8 +219,7 @@ impl EncryptionAPI {
         let ciphertext = cipher
             .encrypt(&Nonce::from(nonce), secret.as_bytes())
             .map_err(|e| {
-                Box::new(std::io::Error::new(
-                    std::io::ErrorKind::Other,
+                Box::new(std::io::Error::other(
                     format!("Encryption failed: {}", e),
                 ))
             })?;
@@ -238,8 +233,7 @@ impl EncryptionAPI {
 
     pub fn decrypt_secret(&mut self, data: &[u8]) -> Result<String, Box<dyn std::error::Error>> {
         if data.len() < 16 {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
+            return Err(Box::new(std::io::Error::other(
                 "Invalid encrypted data",
             )));
         }
@@ -253,15 +247,13 @@ impl EncryptionAPI {
         let plaintext = cipher
             .decrypt(&Nonce::from(nonce), ciphertext)
             .map_err(|e| {
-                Box::new(std::io::Error::new(
-                    std::io::ErrorKind::Other,
+                Box::new(std::io::Error::other(
                     format!("Decryption failed: {}", e),
                 ))
             })?;
 
         Ok(String::from_utf8(plaintext).map_err(|e| {
-            Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
+            Box::new(std::io::Error::other(
                 format!("Invalid secret encoding: {}", e),
             ))
         })?)
@@ -0,0 +1,222 @@
+use crate::structs::OsConfig;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+
+/// A unit of installation work: all packages of one manager, or one task.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(tag = "kind", content = "name", rename_all = "lowercase")]
+pub enum Node {
+    Manager(String),
+    Task(String),
+}
+
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Node::Manager(name) => write!(f, "manager:{}", name),
+            Node::Task(name) => write!(f, "task:{}", name),
+        }
+    }
+}
+
+/// Dependency graph over the managers and tasks of one OS section.
+///
+/// A `depends_on` value names a task or a manager. When both exist with the same
+/// name (e.g. a `brew` bootstrap task and a `brew` manager), the task wins unless
+/// it is the node itself. `task:<name>` and `manager:<name>` make the target explicit.
+#[derive(Debug)]
+pub struct DependencyGraph {
+    // Node -> the nodes it depends on
+    edges: BTreeMap<Node, Vec<Node>>,
+}
+
+impl DependencyGraph {
+    pub fn build(os_config: &OsConfig) -> Result<Self, Box<dyn Error>> {
+        let mut edges = BTreeMap::new();
+
+        for (name, manager) in &os_config.manager {
+            let node = Node::Manager(name.clone());
+            let deps = resolve_dependency(os_config, &node, manager.depends_on.as_deref())?;
+            edges.insert(node, deps);
+        }
+
+        for (name, task) in &os_config.tasks {
+            let node = Node::Task(name.clone());
+            let deps = resolve_dependency(os_config, &node, task.depends_on.as_deref())?;
+            edges.insert(node, deps);
+        }
+
+        Ok(DependencyGraph { edges })
+    }
+
+    /// Returns every node with its dependencies first. Independent nodes keep
+    /// alphabetical order (managers before tasks) so runs are reproducible.
+    pub fn order(&self) -> Result<Vec<Node>, Box<dyn Error>> {
+        let mut state: HashMap<&Node, Visit> = HashMap::new();
+        let mut order = Vec::with_capacity(self.edges.len());
+        let mut path = Vec::new();
+
+        for node in self.edges.keys() {
+            self.visit(node, &mut state, &mut path, &mut order)?;
+        }
+
+        Ok(order)
+    }
+
+    pub fn dependencies(&self, node: &Node) -> &[Node] {
+        self.edges.get(node).map(|deps| deps.as_slice()).unwrap_or(&[])
+    }
+
+    fn visit<'a>(
+        &'a self,
+        node: &'a Node,
+        state: &mut HashMap<&'a Node, Visit>,
+        path: &mut Vec<&'a Node>,
+        order: &mut Vec<Node>,
+    ) -> Result<(), Box<dyn Error>> {
+        match state.get(node) {
+            Some(Visit::Done) => return Ok(()),
+            Some(Visit::InProgress) => {
+                let start = path.iter().position(|n| *n == node).unwrap_or(0);
+                let mut cycle: Vec<String> = path[start..].iter().map(|n| n.to_string()).collect();
+                cycle.push(node.to_string());
+                return Err(Box::new(std::io::Error::other(format!(
+                    "Dependency cycle detected: {}",
+                    cycle.join(" -> ")
+                ))));
+            }
+            None => {}
+        }
+
+        state.insert(node, Visit::InProgress);
+        path.push(node);
+
+        for dep in self.dependencies(node) {
+            self.visit(dep, state, path, order)?;
+        }
+
+        path.pop();
+        state.insert(node, Visit::Done);
+        order.push(node.clone());
+        Ok(())
+    }
+}
+
+enum Visit {
+    InProgress,
+    Done,
+}
+
+pub fn resolve_dependency(
+    os_config: &OsConfig,
+    from: &Node,
+    depends_on: Option<&str>,
+) -> Result<Vec<Node>, Box<dyn Error>> {
+    let Some(target) = depends_on else {
+        return Ok(vec![]);
+    };
+
+    let resolved = if let Some(name) = target.strip_prefix("task:") {
+        os_config.tasks.contains_key(name).then(|| Node::Task(name.to_string()))
+    } else if let Some(name) = target.strip_prefix("manager:") {
+        os_config.manager.contains_key(name).then(|| Node::Manager(name.to_string()))
+    } else {
+        let task = Node::Task(target.to_string());
+        let manager = Node::Manager(target.to_string());
+        if os_config.tasks.contains_key(target) && *from != task {
+            Some(task)
+        } else if os_config.manager.contains_key(target) && *from != manager {
+            Some(manager)
+        } else if os_config.tasks.contains_key(target) {
+            Some(task)
+        } else {
+            None
+        }
+    };
+
+    match resolved {
+        Some(node) => Ok(vec![node]),
+        None => Err(Box::new(std::io::Error::other(format!(
+            "{} depends on '{}', which is not a task or manager in this OS section",
+            from, target
+        )))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::{ManagerConfig, TaskConfig};
+
+    fn task(depends_on: Option<&str>) -> TaskConfig {
+        TaskConfig {
+            script: "true".to_string(),
+            description: None,
+            depends_on: depends_on.map(|d| d.to_string()),
+            check: None,
+            creates: None,
+            unless: None,
+            shell: None,
+            env: HashMap::new(),
+            cwd: None,
+            when: None,
+        }
+    }
+
+    fn manager(depends_on: Option<&str>) -> ManagerConfig {
+        ManagerConfig {
+            packages: vec!["pkg".into()],
+            flags: vec![],
+            depends_on: depends_on.map(|d| d.to_string()),
+        }
+    }
+
+    fn os_config(managers: Vec<(&str, ManagerConfig)>, tasks: Vec<(&str, TaskConfig)>) -> OsConfig {
+        OsConfig {
+            description: None,
+            manager: managers.into_iter().map(|(k, v)| (k.to_string(), v)).collect(),
+            tasks: tasks.into_iter().map(|(k, v)| (k.to_string(), v)).collect(),
+            dotfiles: None,
+        }
+    }
+
+    #[test]
+    fn test_brew_bootstrap_runs_before_brew_packages() {
+        let os = os_config(
+            vec![("brew", manager(Some("brew"))), ("dnf", manager(None))],
+            vec![("brew", task(Some("dnf"))), ("k9s", task(Some("manager:brew")))],
+        );
+
+        let order = DependencyGraph::build(&os).unwrap().order().unwrap();
+        let position = |node: Node| order.iter().position(|n| *n == node).unwrap();
+
+        assert!(position(Node::Manager("dnf".into())) < position(Node::Task("brew".into())));
+        assert!(position(Node::Task("brew".into())) < position(Node::Manager("brew".into())));
+        assert!(position(Node::Manager("brew".into())) < position(Node::Task("k9s".into())));
+        assert_eq!(order.len(), 4);
+    }
+
+    #[test]
+    fn test_cycle_reports_path() {
+        let os = os_config(
+            vec![],
+            vec![("a", task(Some("b"))), ("b", task(Some("c"))), ("c", task(Some("a")))],
+        );
+
+        let err = DependencyGraph::build(&os).unwrap().order().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Dependency cycle detected: task:a -> task:b -> task:c -> task:a"
+        );
+    }
+
+    #[test]
+    fn test_dangling_reference() {
+        let os = os_config(vec![("brew", manager(Some("homebrew")))], vec![]);
+
+        let err = DependencyGraph::build(&os).unwrap_err();
+        assert!(err.to_string().contains("manager:brew depends on 'homebrew'"));
+    }
+}
@@ -1,12 +1,23 @@
-use crate::structs::{Config, ConfigDiff, Dotfiles, DotfilesDiff, DotfilesStatus, GistList, OsConfig, SystemStatus};
+use crate::backends::backend_for;
+use crate::condition;
+use crate::journal;
+use crate::graph::{DependencyGraph, Node};
+use crate::state;
+use crate::platform;
+use crate::profile;
+use crate::schedule::{self, run_nodes};
+use crate::vars::{self, Vars};
+use crate::structs::{package_names, BackedUpFile, Config, ConfigDiff, Dotfiles, DotfilesDiff, DotfilesStatus, Gist, GistList, ManagedState, ManagerConfig, OsConfig, Package, SystemStatus, TaskConfig};
 use colored::*;
 use figlet_rs::FIGfont;
 use prettytable::{format, Cell, Row, Table};
-use reqwest::{header, Client};
-use std::collections::HashMap;
+use reqwest::{header, Client, StatusCode};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use futures::stream::{self, StreamExt};
 use std::process::Command;
 use std::process::Stdio;
-use std::{error::Error, path::Path};
+use std::sync::Arc;
+use std::{error::Error, path::{Path, PathBuf}};
 use sysinfo::{Cpu, System, IS_SUPPORTED_SYSTEM};
 
 pub fn is_app_installed(app_package_name: &str) -> bool {
@@ -17,24 +28,167 @@ pub fn is_app_installed(app_package_name: &str) -> bool {
     }
 }
 
+/// Manager packages are checked against their package database, tasks against
+/// their guards, everything else via `which`.
+pub fn is_configured_app_installed(app: &str, os_config: &OsConfig) -> bool {
+    if let Some(manager_name) = manager_of(app, os_config) {
+        return backend_for(manager_name).is_installed(app);
+    }
+    match os_config.tasks.get(app) {
+        Some(task) => is_task_done(app, task),
+        None => is_app_installed(app),
+    }
+}
+
+/// A task is done when its `creates` path exists, its `check` succeeds or its
+/// `unless` succeeds. Without guards, a binary named after the task must exist.
+pub fn is_task_done(task_name: &str, task: &TaskConfig) -> bool {
+    if task.check.is_none() && task.creates.is_none() && task.unless.is_none() {
+        return is_app_installed(task_name);
+    }
+
+    if let Some(path) = &task.creates
+        && Path::new(&expand_home(path)).exists()
+    {
+        return true;
+    }
+
+    [&task.check, &task.unless]
+        .into_iter()
+        .flatten()
+        .any(|command| guard_succeeds(command, task))
+}
+
+/// Guards are shell one-liners, so they always run under `sh` with the task's env and cwd.
+fn guard_succeeds(command: &str, task: &TaskConfig) -> bool {
+    task_command(&shell_argv(None, command), task)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false)
+}
+
+/// Shells and interpreters that take the script with `-c`. Any other `shell` is the
+/// full command line the script is appended to, e.g. `pwsh -NoProfile -Command`.
+pub const C_FLAG_SHELLS: &[&str] = &["sh", "bash", "zsh", "fish", "dash", "ksh", "python", "python3"];
+
+/// Builds the interpreter invocation for a script, so pipes, quotes, `&&`,
+/// redirects and multi-line scripts behave as they would in a terminal.
+pub fn shell_argv(shell: Option<&str>, script: &str) -> Vec<String> {
+    let mut argv: Vec<String> = match shell.map(str::trim) {
+        None | Some("") | Some("sh") => vec!["sh".into()],
+        Some("bash") => vec!["bash".into(), "-euo".into(), "pipefail".into()],
+        Some("python") | Some("python3") => vec!["python3".into()],
+        Some(other) => other.split_whitespace().map(String::from).collect(),
+    };
+    if takes_c_flag(&argv[0]) {
+        argv.push("-c".into());
+    }
+    argv.push(script.to_string());
+    argv
+}
+
+/// Whether `program` (a name or a path like `/bin/zsh`) is one of `C_FLAG_SHELLS`.
+pub fn takes_c_flag(program: &str) -> bool {
+    Path::new(program)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| C_FLAG_SHELLS.contains(&name))
+}
+
+fn task_command(argv: &[String], task: &TaskConfig) -> Command {
+    let mut command = Command::new(&argv[0]);
+    command.args(&argv[1..]).envs(&task.env);
+    if let Some(cwd) = &task.cwd {
+        command.current_dir(expand_home(cwd));
+    }
+    command
+}
+
+pub fn expand_home(path: &str) -> String {
+    match (path.strip_prefix("~/"), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) => format!("{}/{}", home, rest),
+        _ => path.to_string(),
+    }
+}
+
+/// Runs a task and, when it has a `check` guard, verifies the task actually took effect.
+async fn run_task(task_name: &str, task: &TaskConfig) -> Result<(), Box<dyn Error>> {
+    println!("Running task: {}", task_name);
+    execute_task_script(task_name, task).await?;
+
+    // The check blocks, so it runs off the runtime like the probes do
+    if let Some(check) = task.check.clone() {
+        let guarded = task.clone();
+        if !tokio::task::spawn_blocking(move || guard_succeeds(&check, &guarded)).await? {
+            return Err(Box::new(std::io::Error::other(format!(
+                "Task {} ran but its check still fails: {}",
+                task_name,
+                task.check.as_deref().unwrap_or_default()
+            ))));
+        }
+    }
+
+    Ok(())
+}
+
+fn manager_of<'a>(app: &str, os_config: &'a OsConfig) -> Option<&'a String> {
+    os_config
+        .manager
+        .iter()
+        .find(|(_, manager)| manager.has_package(app))
+        .map(|(name, _)| name)
+}
+
+/// Probes run on the blocking pool, up to `schedule::jobs()` at a time, so slow
+/// package databases are queried in parallel.
 pub async fn check_applications_status(
     applications: &[String],
+    os_config: &OsConfig,
 ) -> Result<(Vec<String>, Vec<String>), Box<dyn Error>> {
+    let os_config = Arc::new(os_config.clone());
+    let probes = probe_all(applications.to_vec(), move |app| is_configured_app_installed(app, &os_config)).await?;
+
     let mut installed = vec![];
     let mut missing = vec![];
 
-    for app in applications {
-        if is_app_installed(app) {
-            installed.push(app.clone());
-        } else {
-            missing.push(app.clone());
+    for probe in probes {
+        match probe {
+            (app, true) => installed.push(app),
+            (app, false) => missing.push(app),
         }
     }
 
     Ok((installed, missing))
 }
 
-pub fn display_installation_status(installed: &Vec<String>, missing: &Vec<String>) {
+/// Runs the blocking `probe` on each item off the async runtime, `schedule::jobs()` at
+/// a time, and pairs each item with its answer, in order.
+async fn probe_all<T, F>(items: Vec<T>, probe: F) -> Result<Vec<(T, bool)>, Box<dyn Error>>
+where
+    T: Send + 'static,
+    F: Fn(&T) -> bool + Send + Sync + 'static,
+{
+    let probe = Arc::new(probe);
+    let results: Vec<_> = stream::iter(items)
+        .map(|item| {
+            let probe = Arc::clone(&probe);
+            tokio::task::spawn_blocking(move || {
+                let answer = probe(&item);
+                (item, answer)
+            })
+        })
+        .buffered(schedule::jobs())
+        .collect()
+        .await;
+    results
+        .into_iter()
+        .map(|result| result.map_err(|e| Box::new(e) as Box<dyn Error>))
+        .collect()
+}
+
+pub fn display_installation_status(installed: &[String], missing: &[String]) {
     let mut table = Table::new();
     table.set_format(*format::consts::FORMAT_NO_LINESEP);
 
@@ -70,17 +224,22 @@ pub fn display_installation_status(installed: &Vec<String>, missing: &Vec<String
     table.printstd();
 }
 
+/// `[common]` packages whose `when` condition holds on this machine.
+pub fn common_packages(config: &Config) -> Vec<String> {
+    let Some(mut common) = config.common.clone() else { return Vec::new() };
+    common.retain_applicable(condition::holds);
+    package_names(&common.packages)
+}
+
 pub fn get_all_configured_apps(config: &Config, os_config: &OsConfig) -> Vec<String> {
     let mut apps = Vec::new();
 
     // 1. Common packages
-    if let Some(common) = &config.common {
-        apps.extend(common.packages.clone());
-    }
+    apps.extend(common_packages(config));
 
     // 2. Manager packages
     for manager in os_config.manager.values() {
-        apps.extend(manager.packages.clone());
+        apps.extend(manager.package_names());
     }
 
     // 3. Tasks
@@ -97,44 +256,117 @@ pub fn get_all_configured_dependencies(_os_config: &OsConfig) -> Vec<String> {
 }
 
 pub async fn check_current_os_name() -> Result<String, Box<dyn Error>> {
-    let mut system = System::new_all();
-    system.refresh_all();
-    let os_name = System::long_os_version().unwrap();
-    Ok(os_name)
-}
-
-pub fn detect_os_key(os_description: &str) -> &str {
-    if os_description.contains("Fedora") || os_description.contains("Red Hat") || os_description.contains("Linux") {
-        "fedora"
-    } else if os_description.contains("macOS") || os_description.contains("MacOS") {
-        "macos"
-    } else if os_description.contains("Ubuntu") || os_description.contains("Debian") {
-        "ubuntu"
-    } else if os_description.contains("Arch") {
-        "arch"
-    } else {
-        ""
+    Ok(platform::current().description.clone())
+}
+
+/// The most specific OS section for this machine: the first detected key in fallback
+/// order (e.g. fedora-41, fedora, linux), else a section whose description matches.
+fn matching_os_key(config: &Config) -> Option<String> {
+    let platform = platform::current();
+
+    if let Some(os_key) = platform.candidates.iter().find(|key| config.os_entries.contains_key(*key)) {
+        return Some(os_key.clone());
+    }
+
+    if platform.overridden {
+        return None;
+    }
+
+    let os_description = platform.description.to_lowercase();
+    let mut entries: Vec<_> = config.os_entries.iter().collect();
+    entries.sort_by_key(|(key, _)| key.as_str());
+    entries.into_iter().find_map(|(key, os_config)| {
+        let desc = os_config.description.as_ref()?.to_lowercase();
+        (os_description.contains(&desc) || desc.contains(&os_description)).then(|| key.clone())
+    })
+}
+
+/// The sections `os_key` is made of, most specific first: [rocky-9.3], [rocky-9], [rocky]
+fn os_layers(config: &Config, os_key: &str) -> Vec<String> {
+    let chain = match platform::split_version(os_key) {
+        Some((base, version)) => platform::versioned_keys(base, Some(version)),
+        None => vec![os_key.to_string()],
+    };
+    chain.into_iter().filter(|key| config.os_entries.contains_key(key)).collect()
+}
+
+/// `os_key` with the less specific sections it builds on layered underneath, so
+/// [fedora-41] only needs what differs from [fedora].
+pub fn layered_os_config(config: &Config, os_key: &str) -> Option<OsConfig> {
+    let layers = os_layers(config, os_key);
+    let (base, specific) = layers.split_last()?;
+
+    let mut os_config = config.os_entries[base].clone();
+    for key in specific.iter().rev() {
+        os_config.layer(&config.os_entries[key]);
     }
+    Some(os_config)
 }
 
-pub async fn find_matching_os(config: &Config, os_description: &str) -> Option<(String, OsConfig)> {
-    let os_key = detect_os_key(os_description);
+/// The profile from `--profile` or `spinup profile use`, if the config defines it.
+pub fn active_profile(config: &Config) -> Option<String> {
+    profile::selected()
+        .filter(|name| config.profiles.contains_key(*name))
+        .map(str::to_string)
+}
 
-    if let Some(os_config) = config.os_entries.get(os_key) {
-        return Some((os_key.to_string(), os_config.clone()));
+/// The [hosts.*] sections matching this machine's hostname, in the order they apply:
+/// glob patterns first, then the exact hostname so it has the last word.
+pub fn active_hosts(config: &Config) -> Vec<String> {
+    let platform = platform::current();
+    let mut hosts: Vec<&String> = config
+        .hosts
+        .keys()
+        .filter(|pattern| platform.host_matches(pattern))
+        .collect();
+    hosts.sort_by_key(|pattern| (!pattern.contains(['*', '?']), pattern.as_str()));
+    hosts.into_iter().cloned().collect()
+}
+
+/// `layered_os_config` with the active profile and host overlays applied, entries
+/// whose `when` condition doesn't hold dropped and `{{vars}}` filled in: what this
+/// machine should have.
+pub fn resolved_os_config(config: &Config, os_key: &str) -> Option<OsConfig> {
+    let mut os_config = layered_os_config(config, os_key)?;
+    if let Some(name) = active_profile(config) {
+        os_config.apply_profile(&config.profiles[&name]);
+    }
+    for host in active_hosts(config) {
+        os_config.apply_profile(&config.hosts[&host]);
     }
+    os_config.retain_applicable(condition::holds);
+    let vars = Vars::current(&config.vars);
+    os_config.interpolate(|text| vars.interpolate(text));
+    Some(os_config)
+}
 
-    for (key, os_config) in &config.os_entries {
-        if let Some(desc) = &os_config.description {
-            if os_description.to_lowercase().contains(&desc.to_lowercase())
-                || desc.to_lowercase().contains(&os_description.to_lowercase())
-            {
-                return Some((key.clone(), os_config.clone()));
-            }
-        }
+/// The OS section for this machine, with versioned sections layered over their base
+/// and the active profile and host overlays applied. The key is the most specific section, e.g. fedora-41.
+pub fn find_matching_os(config: &Config) -> Option<(String, OsConfig)> {
+    let os_key = matching_os_key(config)?;
+    let os_config = resolved_os_config(config, &os_key)?;
+    Some((os_key, os_config))
+}
+
+/// Section `add` and `capture` write to: the base section this machine uses (not a
+/// versioned one unless `--os` names it), else a new one for the detected OS.
+pub fn os_key_to_edit(config: &Config) -> String {
+    let platform = platform::current();
+    if platform.overridden {
+        return platform.section_key().to_string();
     }
+    matching_os_key(config)
+        .and_then(|os_key| os_layers(config, &os_key).pop())
+        .unwrap_or_else(|| platform.section_key().to_string())
+}
 
-    None
+pub fn no_matching_os_error() -> Box<dyn Error> {
+    let platform = platform::current();
+    Box::new(std::io::Error::other(format!(
+        "No configuration found for {} (tried [{}]), add a section or pass --os <key>",
+        platform.description,
+        platform.candidates.join("], [")
+    )))
 }
 
 fn categorize_apps(apps: &[String], os_config: &OsConfig, config: &Config) -> HashMap<String, Vec<String>> {
@@ -142,7 +374,7 @@ fn categorize_apps(apps: &[String], os_config: &OsConfig, config: &Config) -> Ha
     for app in apps {
         let mut placed = false;
         for (mgr_name, mgr_cfg) in &os_config.manager {
-            if mgr_cfg.packages.contains(app) {
+            if mgr_cfg.has_package(app) {
                 map.entry(mgr_name.clone()).or_insert_with(Vec::new).push(app.clone());
                 placed = true;
                 break;
@@ -156,11 +388,11 @@ fn categorize_apps(apps: &[String], os_config: &OsConfig, config: &Config) -> Ha
         }
         if placed { continue; }
 
-        if let Some(common) = &config.common {
-            if common.packages.contains(app) {
-                 map.entry("common".to_string()).or_insert_with(Vec::new).push(app.clone());
-                 placed = true;
-            }
+        if let Some(common) = &config.common
+            && common.packages.iter().any(|package| package.name() == app)
+        {
+             map.entry("common".to_string()).or_insert_with(Vec::new).push(app.clone());
+             placed = true;
         }
         if placed { continue; }
 
@@ -169,11 +401,10 @@ fn categorize_apps(apps: &[String], os_config: &OsConfig, config: &Config) -> Ha
     map
 }
 
-pub async fn check_applications(applications: &[String]) -> Result<(), Box<dyn Error>> {
-    let apps = check_applications_status(applications).await;
+pub async fn check_applications(applications: &[String], os_config: &OsConfig) -> Result<(), Box<dyn Error>> {
+    let apps = check_applications_status(applications, os_config).await;
     if apps.is_err() {
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::Other,
+        return Err(Box::new(std::io::Error::other(
             "Failed to check applications",
         )));
     }
@@ -201,7 +432,7 @@ pub async fn display_system_info() {
     let cpu = system.cpus().iter().next().unwrap();
     let brand = Cpu::brand(cpu);
     let model = Cpu::name(cpu);
-    let os_release = System::long_os_version().unwrap();
+    let os_release = platform::current().description.clone();
 
     let mut table = Table::new();
     table.set_format(*format::consts::FORMAT_DEFAULT);
@@ -216,6 +447,11 @@ pub async fn display_system_info() {
         Cell::new(&format!("{} {}", brand.bright_green(), model.bright_green())),
     ]));
 
+    table.add_row(Row::new(vec![
+        Cell::new(&format!("{}", "Config Sections".blue())),
+        Cell::new(&format!("{}", platform::current().candidates.join(" → ").bright_green())),
+    ]));
+
     table.printstd();
 }
 
@@ -228,14 +464,13 @@ pub fn print_banner() {
     let figure = standard_font.convert("SpinUp");
     println!("{}", figure.unwrap().to_string().green());
     println!(
-        "{} {} {} {}{}{}{}",
+        "{} {} {} {}{}{}>",
         "Version".bold(),
         current_version.bright_green(),
         "by".bold(),
         author_name.bold().bright_green(),
         " <".bold(),
-        author_email.bold().bright_blue(),
-        ">"
+        author_email.bold().bright_blue()
     );
 }
 
@@ -256,113 +491,489 @@ pub async fn get_gists(token: &str) -> Result<GistList, Box<dyn Error>> {
         let gists = response.json::<GistList>().await?;
         Ok(gists)
     } else {
-        match status.as_u16() {
-            401 => Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::PermissionDenied,
-                "401 Unauthorized: Your authentication token is invalid or expired.",
-            ))),
-            403 => Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::PermissionDenied,
-                "403 Forbidden: Access denied. This might be due to rate limiting or insufficient permissions.",
-            ))),
-            404 => Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                "404 Not Found: Unable to find gists.",
-            ))),
-            429 => Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "429 Too Many Requests: You are being rate-limited by GitHub. Please wait a moment before trying again.",
-            ))),
-            500..=599 => Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("GitHub Server Error: {} - Please try again later.", status),
-            ))),
-            _ => Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("Failed to fetch gists: {}", status),
-            ))),
-        }
+        Err(github_status_error(status))
+    }
+}
+
+pub async fn get_gist(token: &str, gist_id: &str) -> Result<Gist, Box<dyn Error>> {
+    let client = Client::new();
+    let request_url = format!("https://api.github.com/gists/{}", gist_id);
+
+    let response = client
+        .get(&request_url)
+        .header(header::AUTHORIZATION, format!("token {}", token))
+        .header(header::USER_AGENT, "spinup")
+        .send()
+        .await?;
+
+    let status = response.status();
+
+    if status.is_success() {
+        let gist = response.json::<Gist>().await?;
+        Ok(gist)
+    } else {
+        Err(github_status_error(status))
+    }
+}
+
+/// Replaces the content of one file in a gist, leaving its other files untouched.
+pub async fn update_gist_file(token: &str, gist_id: &str, file_name: &str, content: &str) -> Result<(), Box<dyn Error>> {
+    let client = Client::new();
+    let request_url = format!("https://api.github.com/gists/{}", gist_id);
+    let body = serde_json::json!({ "files": { file_name: { "content": content } } });
+
+    let response = client
+        .patch(&request_url)
+        .header(header::AUTHORIZATION, format!("token {}", token))
+        .header(header::USER_AGENT, "spinup")
+        .json(&body)
+        .send()
+        .await?;
+
+    let status = response.status();
+
+    if status.is_success() {
+        Ok(())
+    } else {
+        Err(github_status_error(status))
+    }
+}
+
+fn github_status_error(status: StatusCode) -> Box<dyn Error> {
+    match status.as_u16() {
+        401 => Box::new(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            "401 Unauthorized: Your authentication token is invalid or expired.",
+        )),
+        403 => Box::new(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            "403 Forbidden: Access denied. This might be due to rate limiting or insufficient permissions.",
+        )),
+        404 => Box::new(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "404 Not Found: Unable to find gists.",
+        )),
+        429 => Box::new(std::io::Error::other(
+            "429 Too Many Requests: You are being rate-limited by GitHub. Please wait a moment before trying again.",
+        )),
+        500..=599 => Box::new(std::io::Error::other(
+            format!("GitHub Server Error: {} - Please try again later.", status),
+        )),
+        _ => Box::new(std::io::Error::other(
+            format!("Failed to fetch gists: {}", status),
+        )),
     }
 }
 
 pub async fn install_applications(applications: &[String], config: &Config) -> Result<(), Box<dyn Error>> {
-    let os_description = check_current_os_name().await?;
-    let Some((_, os_config)) = find_matching_os(config, &os_description).await else {
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!("No matching OS config found for: {}", os_description),
-        )));
+    let Some((os_key, os_config)) = find_matching_os(config) else {
+        return Err(no_matching_os_error());
     };
+    journal::set_os(&os_key);
 
-    let apps_status = check_applications_status(applications).await?;
+    let graph = DependencyGraph::build(&os_config)?;
+
+    let apps_status = check_applications_status(applications, &os_config).await?;
     let (_, missing) = apps_status;
 
+    let packages_of = |manager_name: &str| -> Vec<String> {
+        os_config.manager[manager_name]
+            .package_names()
+            .into_iter()
+            .filter(|p| missing.contains(p))
+            .collect()
+    };
+    let pending: Vec<Node> = graph
+        .order()?
+        .into_iter()
+        .filter(|node| match node {
+            Node::Manager(manager_name) => !packages_of(manager_name).is_empty(),
+            Node::Task(task_name) => missing.contains(task_name),
+        })
+        .collect();
+
+    run_nodes(&pending, &graph, &os_config, schedule::jobs(), false, |node| {
+        let (os_config, packages_of) = (&os_config, &packages_of);
+        async move {
+            match node {
+                Node::Manager(manager_name) => {
+                    install_manager_packages(manager_name, &os_config.manager[manager_name], &packages_of(manager_name)).await
+                }
+                Node::Task(task_name) => run_task(task_name, &os_config.tasks[task_name]).await,
+            }
+        }
+    })
+    .await?;
+
     for app_name in missing.iter() {
-        // 1. Try to find in tasks
-        if let Some(task) = os_config.tasks.get(app_name) {
-            println!("Running task: {}", app_name);
-            execute_install_command(&task.script, app_name).await?;
-            continue;
+        let handled = os_config.tasks.contains_key(app_name)
+            || os_config.manager.values().any(|m| m.has_package(app_name));
+        if !handled {
+            println!("Could not determine how to install {}", app_name);
         }
+    }
 
-        // 2. Try to find in managers
-        let mut found_in_manager = false;
-        for (manager_name, manager_config) in &os_config.manager {
-            if manager_config.packages.contains(app_name) {
-                let flags = manager_config.flags.join(" ");
-                // Basic heuristic for install command
-                let install_cmd = if manager_name == "brew" {
-                    format!("brew install {} {}", flags, app_name)
-                } else {
-                    format!("sudo {} install {} {}", manager_name, flags, app_name)
-                };
+    Ok(())
+}
 
-                println!("Installing {} via {}...", app_name, manager_name);
-                execute_install_command(&install_cmd, app_name).await?;
-                found_in_manager = true;
-                break;
-            }
+pub fn manager_install_command(manager_name: &str, manager_config: &ManagerConfig, packages: &[String]) -> String {
+    backend_for(manager_name)
+        .install_command(packages, &manager_config.flags)
+        .join(" ")
+}
+
+/// Installs all `packages` in one package-manager transaction. If the transaction
+/// fails, each package is retried on its own so the report names the culprits.
+pub async fn install_manager_packages(
+    manager_name: &str,
+    manager_config: &ManagerConfig,
+    packages: &[String],
+) -> Result<(), Box<dyn Error>> {
+    let backend = backend_for(manager_name);
+
+    println!("Installing {} via {}...", packages.join(", "), backend.name());
+    let outcome = install_batch_or_each(packages, backend.name(), |batch| {
+        let install_cmd = backend.install_command(&batch, &manager_config.flags);
+        let label = if batch.len() == 1 { batch[0].clone() } else { manager_name.to_string() };
+        async move { execute_command(&install_cmd, &label).await }
+    })
+    .await;
+
+    for package in &outcome.installed {
+        println!("  {} {}", "✓".green(), package);
+    }
+    for (package, error) in &outcome.failed {
+        println!("  {} {}: {}", "✖".red(), package, error);
+    }
+    state::record_installed(manager_name, &outcome.installed);
+    journal::record_installed(manager_name, &outcome.installed);
+
+    if outcome.failed.is_empty() {
+        Ok(())
+    } else if let [(_, error)] = outcome.failed.as_slice()
+        && packages.len() == 1
+    {
+        Err(Box::new(std::io::Error::other(error.clone())))
+    } else {
+        let failed: Vec<&str> = outcome.failed.iter().map(|(package, _)| package.as_str()).collect();
+        Err(Box::new(std::io::Error::other(
+            format!("Failed to install via {}: {}", backend.name(), failed.join(", ")),
+        )))
+    }
+}
+
+/// What an install of several packages ended with: the ones installed, and the ones
+/// that failed with the reason.
+#[derive(Debug, Default)]
+pub struct InstallOutcome {
+    pub installed: Vec<String>,
+    pub failed: Vec<(String, String)>,
+}
+
+/// Installs `packages` with one `install` call. When that fails and there are several,
+/// retries each one on its own, so one bad name doesn't keep the others out.
+pub async fn install_batch_or_each<F, Fut>(packages: &[String], manager: &str, mut install: F) -> InstallOutcome
+where
+    F: FnMut(Vec<String>) -> Fut,
+    Fut: std::future::Future<Output = Result<(), Box<dyn Error>>>,
+{
+    let mut outcome = InstallOutcome::default();
+    let batch_error = match install(packages.to_vec()).await {
+        Ok(()) => {
+            outcome.installed = packages.to_vec();
+            return outcome;
         }
+        Err(e) => e,
+    };
 
-        if !found_in_manager {
-            println!("Could not determine how to install {}", app_name);
+    if packages.len() == 1 {
+        outcome.failed.push((packages[0].clone(), batch_error.to_string()));
+        return outcome;
+    }
+
+    println!(
+        "{} Batch install via {} failed ({}), retrying packages one at a time...",
+        "⚠".yellow(),
+        manager,
+        batch_error
+    );
+    for package in packages {
+        match install(vec![package.clone()]).await {
+            Ok(()) => outcome.installed.push(package.clone()),
+            Err(e) => outcome.failed.push((package.clone(), e.to_string())),
         }
     }
+    outcome
+}
 
+/// Flags come from the manager's config section when it still exists (e.g. `-y`).
+pub fn manager_remove_command(manager_name: &str, manager_config: Option<&ManagerConfig>, packages: &[String]) -> String {
+    let flags = manager_config.map(|m| m.flags.as_slice()).unwrap_or(&[]);
+    backend_for(manager_name).remove_command(packages, flags).join(" ")
+}
+
+/// Removes `packages` in one transaction and drops them from the managed state.
+pub async fn remove_manager_packages(
+    manager_name: &str,
+    manager_config: Option<&ManagerConfig>,
+    packages: &[String],
+) -> Result<(), Box<dyn Error>> {
+    let backend = backend_for(manager_name);
+    let flags = manager_config.map(|m| m.flags.as_slice()).unwrap_or(&[]);
+
+    println!("Removing {} via {}...", packages.join(", "), backend.name());
+    execute_command(&backend.remove_command(packages, flags), manager_name).await?;
+
+    for package in packages {
+        println!("  {} {}", "✓".green(), package);
+    }
+    state::forget_removed(manager_name, packages);
     Ok(())
 }
 
-async fn execute_install_command(command: &str, app_name: &str) -> Result<(), Box<dyn Error>> {
-    let parts: Vec<&str> = command.split_whitespace().collect();
-    if parts.is_empty() {
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!("Empty install command for {}", app_name),
+/// Removes the packages in `diff.packages_to_remove`, one transaction per manager.
+pub async fn prune_config_diff(diff: &ConfigDiff, config: &Config) -> Result<(), Box<dyn Error>> {
+    let os_config = resolved_os_config(config, &diff.os_key);
+    journal::set_os(&diff.os_key);
+
+    let mut managers: Vec<_> = diff.packages_to_remove.iter().collect();
+    managers.sort();
+    for (manager_name, packages) in managers {
+        let manager_config = os_config.as_ref().and_then(|os| os.manager.get(manager_name));
+        remove_manager_packages(manager_name, manager_config, packages).await?;
+    }
+    Ok(())
+}
+
+/// Every package the config could want from each manager on this machine: `os_key`
+/// layered but unfiltered, plus what any profile or matching host overlay adds, whatever
+/// their `when` conditions and whichever profile is active today.
+fn wanted_packages(config: &Config, os_key: &str) -> HashMap<String, HashSet<String>> {
+    let mut wanted: HashMap<String, HashSet<String>> = HashMap::new();
+    if let Some(os_config) = layered_os_config(config, os_key) {
+        for (name, manager) in os_config.manager {
+            wanted.entry(name).or_default().extend(manager.package_names());
+        }
+    }
+
+    let hosts = active_hosts(config);
+    let overlays = config.profiles.values().chain(hosts.iter().map(|host| &config.hosts[host]));
+    for overlay in overlays {
+        for (name, changes) in &overlay.manager {
+            wanted.entry(name.clone()).or_default().extend(package_names(&changes.packages));
+        }
+    }
+    wanted
+}
+
+/// Packages recorded in the managed state that the config no longer wants (see
+/// `wanted_packages`; `[common]` packages count for every manager) and that
+/// `is_installed(manager, package)` still finds. A package a false `when` or a one-off
+/// `--profile` hides is kept, and so is everything of a manager this section doesn't
+/// declare but another OS section does (e.g. under `--os`). Anything spinup didn't install is never listed. Probed
+/// like `check_applications_status`, in parallel.
+pub async fn packages_to_prune<F>(
+    config: &Config,
+    os_key: &str,
+    managed: &ManagedState,
+    is_installed: F,
+) -> Result<HashMap<String, Vec<String>>, Box<dyn Error>>
+where
+    F: Fn(&str, &str) -> bool + Send + Sync + 'static,
+{
+    let wanted = wanted_packages(config, os_key);
+    let common: HashSet<String> = config.common.iter().flat_map(|common| package_names(&common.packages)).collect();
+    let section = layered_os_config(config, os_key);
+    let belongs_here = |manager_name: &str| {
+        section.as_ref().is_some_and(|os_config| os_config.manager.contains_key(manager_name))
+            || !config.os_entries.values().any(|os_config| os_config.manager.contains_key(manager_name))
+    };
+
+    let dropped: Vec<(String, String)> = managed
+        .packages
+        .iter()
+        .filter(|(manager_name, _)| belongs_here(manager_name))
+        .flat_map(|(manager_name, packages)| {
+            let configured = wanted.get(manager_name);
+            let common = &common;
+            packages
+                .iter()
+                .filter(move |package| !common.contains(*package) && !configured.is_some_and(|c| c.contains(*package)))
+                .map(move |package| (manager_name.clone(), package.clone()))
+        })
+        .collect();
+
+    let mut to_remove: HashMap<String, Vec<String>> = HashMap::new();
+    for ((manager_name, package), installed) in
+        probe_all(dropped, move |(manager, package)| is_installed(manager, package)).await?
+    {
+        if installed {
+            to_remove.entry(manager_name).or_default().push(package);
+        }
+    }
+    Ok(to_remove)
+}
+
+/// Executes exactly what `get_config_diff` computed, following its `install_order`
+/// with independent branches in parallel (see `schedule::run_nodes`), then dotfiles.
+/// Finished steps are recorded in the run journal for `apply --resume`. With
+/// `keep_going`, failures don't stop unrelated steps or the dotfiles; they are all
+/// reported at the end.
+pub async fn apply_config_diff(diff: &ConfigDiff, config: &Config, keep_going: bool) -> Result<(), Box<dyn Error>> {
+    let Some(os_config) = resolved_os_config(config, &diff.os_key) else {
+        return Err(Box::new(std::io::Error::other(
+            format!("No configuration found for OS: {}", diff.os_key),
         )));
+    };
+    journal::set_os(&diff.os_key);
+
+    let graph = DependencyGraph::build(&os_config)?;
+    let installed = run_nodes(&diff.install_order, &graph, &os_config, schedule::jobs(), keep_going, |node| {
+        let os_config = &os_config;
+        async move {
+            let result = match node {
+                Node::Manager(manager_name) => {
+                    let (Some(manager_config), Some(packages)) = (
+                        os_config.manager.get(manager_name),
+                        diff.packages_to_install.get(manager_name),
+                    ) else {
+                        return Ok(());
+                    };
+
+                    install_manager_packages(manager_name, manager_config, packages).await
+                }
+                Node::Task(task_name) => {
+                    let Some(task) = os_config.tasks.get(task_name) else {
+                        println!("Could not find task {}", task_name);
+                        return Ok(());
+                    };
+                    run_task(task_name, task).await
+                }
+            };
+            if result.is_ok() {
+                journal::complete(&node.to_string());
+            }
+            result
+        }
+    })
+    .await;
+    let installed = match installed {
+        Err(e) if !keep_going => return Err(e),
+        installed => installed,
+    };
+
+    for (category, packages) in &diff.packages_to_install {
+        if !os_config.manager.contains_key(category) {
+            for package in packages {
+                println!("Could not determine how to install {}", package);
+            }
+        }
+    }
+
+    let df_diff = &diff.dotfiles_diff;
+    let dotfiles = match &os_config.dotfiles {
+        Some(dotfiles) if df_diff.needs_clone || !df_diff.packages_to_apply.is_empty() => {
+            let result = apply_dotfiles_diff(dotfiles, df_diff).await;
+            if result.is_ok() {
+                journal::complete("dotfiles");
+            }
+            result
+        }
+        _ => Ok(()),
+    };
+
+    match (installed, dotfiles) {
+        (Ok(()), Ok(())) => Ok(()),
+        (Err(e), Ok(())) | (Ok(()), Err(e)) => Err(e),
+        (Err(installed), Err(dotfiles)) => Err(Box::new(std::io::Error::other(
+            format!("{}\nDotfiles failed: {}", installed, dotfiles),
+        ))),
+    }
+}
+
+async fn apply_dotfiles_diff(dotfiles: &Dotfiles, df_diff: &DotfilesDiff) -> Result<(), Box<dyn Error>> {
+    println!("\n{} Setting up dotfiles...", "📁".bold());
+
+    if !is_stow_installed() {
+        install_stow().await?;
     }
 
-    let command_name = parts[0];
-    let args = &parts[1..];
+    if df_diff.needs_clone {
+        clone_dotfiles(dotfiles).await?;
+    }
 
-    let output = Command::new(command_name)
-        .args(args)
-        .stderr(Stdio::inherit())
-        .stdout(Stdio::inherit())
-        .output();
+    // With auto-discovery, packages are only known once the repository is cloned
+    let mut dotfiles = dotfiles.clone();
+    if !df_diff.packages_to_apply.is_empty() {
+        dotfiles.packages = Some(df_diff.packages_to_apply.iter().map(|p| Package::from(p.as_str())).collect());
+    }
+    apply_dotfiles(&dotfiles).await
+}
 
-    match output {
-        Ok(status) if status.status.success() => Ok(()),
-        Ok(status) => Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!("Install command exited with code: {:?}", status.status.code()),
+async fn execute_command(argv: &[String], app_name: &str) -> Result<(), Box<dyn Error>> {
+    let Some((program, args)) = argv.split_first() else {
+        return Err(Box::new(std::io::Error::other(
+            format!("Empty install command for {}", app_name),
+        )));
+    };
+
+    let mut command = tokio::process::Command::new(program);
+    command.args(args).stdout(Stdio::inherit());
+    let status = journal::run_step(app_name, &argv.join(" "), command).await;
+
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(Box::new(std::io::Error::other(
+            format!("Install command exited with code: {:?}", status.code()),
         ))),
-        Err(e) => Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::Other,
+        Err(e) => Err(Box::new(std::io::Error::other(
             format!("Failed to execute install command for {}: {}", app_name, e),
         ))),
     }
 }
 
+async fn execute_task_script(task_name: &str, task: &TaskConfig) -> Result<(), Box<dyn Error>> {
+    if task.script.trim().is_empty() {
+        return Err(Box::new(std::io::Error::other(
+            format!("Empty script for task {}", task_name),
+        )));
+    }
+    let fields = [Some(&task.script), task.check.as_ref(), task.creates.as_ref(), task.unless.as_ref(), task.cwd.as_ref()];
+    check_unresolved(&format!("Task {}", task_name), fields.into_iter().flatten())?;
+
+    let argv = shell_argv(task.shell.as_deref(), &task.script);
+    let mut command = task_command(&argv, task);
+    command.stdout(Stdio::inherit());
+    let status = journal::run_step(&format!("task:{}", task_name), &task.script, command.into()).await;
+
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(Box::new(std::io::Error::other(
+            format!("Task {} exited with code: {:?}", task_name, status.code()),
+        ))),
+        Err(e) => Err(Box::new(std::io::Error::other(
+            format!("Failed to start {} for task {}: {}", argv[0], task_name, e),
+        ))),
+    }
+}
+
+/// Fails when interpolation left `{{name}}` placeholders behind, instead of handing
+/// them to the shell or git.
+pub fn check_unresolved<'a>(what: &str, fields: impl Iterator<Item = &'a String>) -> Result<(), Box<dyn Error>> {
+    let unknown: BTreeSet<String> = fields
+        .flat_map(|field| vars::placeholders(field))
+        .map(|(_, name)| format!("{{{{{}}}}}", name))
+        .collect();
+    if unknown.is_empty() {
+        return Ok(());
+    }
+    Err(Box::new(std::io::Error::other(format!(
+        "{} uses undefined variables {}, see `spinup validate`",
+        what,
+        unknown.into_iter().collect::<Vec<_>>().join(", ")
+    ))))
+}
+
 pub fn is_stow_installed() -> bool {
     Command::new("which")
         .arg("stow")
@@ -374,48 +985,42 @@ pub fn is_stow_installed() -> bool {
 pub async fn install_stow() -> Result<(), Box<dyn Error>> {
     println!("{} Installing stow...", "⚙".yellow());
 
-    let os_name = check_current_os_name().await?;
+    let platform = platform::current();
 
-    let install_command = if os_name.contains("Fedora") || os_name.contains("Red Hat") {
+    let install_command = if platform.is_like("fedora") || platform.is_like("rhel") {
         "sudo dnf install -y stow"
-    } else if os_name.contains("Ubuntu") || os_name.contains("Debian") {
+    } else if platform.is_like("debian") || platform.is_like("ubuntu") {
         "sudo apt update && sudo apt install -y stow"
-    } else if os_name.contains("Arch") {
+    } else if platform.is_like("arch") {
         "sudo pacman -S stow"
-    } else if os_name.contains("macOS") {
+    } else if platform.is_like("macos") {
         "brew install stow"
     } else {
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::Other,
+        return Err(Box::new(std::io::Error::other(
             "Unsupported OS for stow installation"
         )));
     };
 
-    let parts: Vec<&str> = install_command.split_whitespace().collect();
-    let command = parts[0];
-    let args = &parts[1..];
+    let mut command = tokio::process::Command::new("sh");
+    command.args(["-c", install_command]).stdout(Stdio::inherit());
+    let status = journal::run_step("stow", install_command, command).await?;
 
-    let output = Command::new(command)
-        .args(args)
-        .stderr(Stdio::inherit())
-        .stdout(Stdio::inherit())
-        .output()?;
-
-    if output.status.success() {
+    if status.success() {
         println!("{} Stow installed successfully", "✓".green());
         Ok(())
     } else {
-        Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::Other,
+        Err(Box::new(std::io::Error::other(
             "Failed to install stow"
         )))
     }
 }
 
 pub async fn clone_dotfiles(dotfiles: &Dotfiles) -> Result<(), Box<dyn Error>> {
-    let target_dir = dotfiles.target_directory
-        .as_ref()
-        .map(|s| s.as_str())
+    if dotfiles.repository.is_empty() {
+        return Err(Box::new(std::io::Error::other("The dotfiles section has no repository")));
+    }
+    check_unresolved("Dotfiles", [Some(&dotfiles.repository), dotfiles.target_directory.as_ref()].into_iter().flatten())?;
+    let target_dir = dotfiles.target_directory.as_deref()
         .unwrap_or("dotfiles");
 
     let home_dir = std::env::var("HOME")?;
@@ -428,56 +1033,206 @@ pub async fn clone_dotfiles(dotfiles: &Dotfiles) -> Result<(), Box<dyn Error>> {
 
     println!("{} Cloning dotfiles from {}...", "⬇".yellow(), dotfiles.repository);
 
-    let output = Command::new("git")
-        .args(&["clone", &dotfiles.repository, &full_path])
-        .stderr(Stdio::inherit())
-        .stdout(Stdio::inherit())
-        .status()?;
+    let mut command = tokio::process::Command::new("git");
+    command.args(["clone", &dotfiles.repository, &full_path]).stdout(Stdio::inherit());
+    let shown = format!("git clone {} {}", dotfiles.repository, full_path);
+    let status = journal::run_step("dotfiles:clone", &shown, command).await?;
 
-    if output.success() {
+    if status.success() {
         println!("{} Dotfiles cloned successfully", "✓".green());
+        journal::record_cloned(&full_path);
         Ok(())
     } else {
-        Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::Other,
+        Err(Box::new(std::io::Error::other(
             "Failed to clone dotfiles repository"
         )))
     }
 }
 
+/// Stow packages in a dotfiles checkout: its top-level directories, minus hidden
+/// ones and `exclude`.
+fn discover_dotfiles_packages(dotfiles_path: &Path, exclude: &[String]) -> std::io::Result<Vec<String>> {
+    let mut packages = Vec::new();
+    for entry in std::fs::read_dir(dotfiles_path)? {
+        let path = entry?.path();
+        if path.is_dir()
+            && let Some(name) = path.file_name().and_then(|n| n.to_str())
+            && !name.starts_with('.')
+            && name != "README.md"
+            && !exclude.iter().any(|excluded| excluded == name)
+        {
+            packages.push(name.to_string());
+        }
+    }
+    Ok(packages)
+}
+
+/// What applying `dotfiles` under `home` would do: clone the checkout if it's missing,
+/// and stow the packages that aren't linked yet.
+pub fn dotfiles_diff(dotfiles: &Dotfiles, home: &Path) -> DotfilesDiff {
+    let dotfiles_path = home.join(dotfiles.target_directory.as_deref().unwrap_or("dotfiles"));
+    let needs_clone = !dotfiles_path.exists();
+
+    let packages = match &dotfiles.packages {
+        Some(pkgs) => package_names(pkgs),
+        // Not cloned yet: packages are only known after the clone
+        None => discover_dotfiles_packages(&dotfiles_path, &dotfiles.exclude).unwrap_or_default(),
+    };
+
+    // `stow` runs inside the checkout, so it links into the checkout's parent
+    let target = dotfiles_path.parent().unwrap_or(home);
+    let (packages_already_applied, packages_to_apply) = packages
+        .into_iter()
+        .partition(|package| !needs_clone && is_stowed(&dotfiles_path.join(package), target));
+
+    DotfilesDiff {
+        needs_clone,
+        packages_to_apply,
+        packages_already_applied,
+    }
+}
+
+/// Whether the machine matches the config: no manager package or task is `pending`
+/// and the dotfiles are cloned and fully stowed. `spinup status` and `spinup diff`
+/// both decide drift with this.
+pub fn is_in_sync<'a>(pending: impl IntoIterator<Item = &'a Vec<String>>, dotfiles: &DotfilesDiff) -> bool {
+    pending.into_iter().all(Vec::is_empty) && !dotfiles.needs_clone && dotfiles.packages_to_apply.is_empty()
+}
+
+/// Whether every file of the stow package at `package_dir` is linked from `target`,
+/// directly or through a folded directory link. Files stow ignores by default (VCS
+/// metadata, top-level README/LICENSE/COPYING) don't count.
+pub fn is_stowed(package_dir: &Path, target: &Path) -> bool {
+    fn linked(dir: &Path, relative: &Path, target: &Path) -> bool {
+        let Ok(entries) = std::fs::read_dir(dir) else { return false };
+        entries.flatten().all(|entry| {
+            let name = entry.file_name();
+            if stow_ignores(relative, &name.to_string_lossy()) {
+                return true;
+            }
+
+            let path = entry.path();
+            let relative = relative.join(&name);
+            if path.is_dir() && !path.is_symlink() {
+                return linked(&path, &relative, target);
+            }
+            match (target.join(&relative).canonicalize(), path.canonicalize()) {
+                (Ok(linked_to), Ok(source)) => linked_to == source,
+                _ => false,
+            }
+        })
+    }
+    package_dir.is_dir() && linked(package_dir, Path::new(""), target)
+}
+
+/// Paths under the stow `target` (relative to it) that would keep the package at
+/// `package_dir` from being stowed: they exist but aren't links into the package.
+pub fn stow_conflicts(package_dir: &Path, target: &Path) -> Vec<PathBuf> {
+    fn collect(dir: &Path, relative: &Path, target: &Path, conflicts: &mut Vec<PathBuf>) {
+        let Ok(entries) = std::fs::read_dir(dir) else { return };
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            if stow_ignores(relative, &name.to_string_lossy()) {
+                continue;
+            }
+
+            let path = entry.path();
+            let relative = relative.join(&name);
+            let existing = target.join(&relative);
+            if existing.symlink_metadata().is_err() {
+                continue;
+            }
+            let linked = matches!(
+                (existing.canonicalize(), path.canonicalize()),
+                (Ok(linked_to), Ok(source)) if linked_to == source
+            );
+            if linked {
+                continue;
+            }
+            // stow links into (or unfolds) an existing directory, so only what's inside can clash
+            if path.is_dir() && !path.is_symlink() && existing.is_dir() {
+                collect(&path, &relative, target, conflicts);
+            } else {
+                conflicts.push(relative);
+            }
+        }
+    }
+    let mut conflicts = Vec::new();
+    collect(package_dir, Path::new(""), target, &mut conflicts);
+    conflicts.sort();
+    conflicts
+}
+
+/// Moves the files in the way of stowing `package_dir` into `target` to this run's
+/// backups, recording each in the journal so `spinup undo` puts them back.
+pub fn back_up_conflicts(package_dir: &Path, target: &Path) -> Result<Vec<BackedUpFile>, Box<dyn Error>> {
+    let mut backed_up = Vec::new();
+    for relative in stow_conflicts(package_dir, target) {
+        let original = target.join(&relative);
+        let backup = journal::backup_path(&relative);
+        if let Some(parent) = backup.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::rename(&original, &backup).map_err(|e| {
+            std::io::Error::other(format!("Could not back up {} to {}: {}", original.display(), backup.display(), e))
+        })?;
+        journal::record_backup(&original, &backup);
+        println!("    {} Moved {} to {}", "↪".blue(), original.display(), backup.display());
+        backed_up.push(BackedUpFile {
+            original: original.display().to_string(),
+            backup: backup.display().to_string(),
+        });
+    }
+    Ok(backed_up)
+}
+
+/// Moves a backed up file back. Returns false when there's nothing left to restore;
+/// a file that has since reappeared at the original path is never overwritten.
+pub fn restore_backup(backed_up: &BackedUpFile) -> Result<bool, Box<dyn Error>> {
+    let (original, backup) = (Path::new(&backed_up.original), Path::new(&backed_up.backup));
+    if backup.symlink_metadata().is_err() {
+        return Ok(false);
+    }
+    if original.symlink_metadata().is_ok() {
+        return Err(Box::new(std::io::Error::other(format!(
+            "{} exists again, restore it from {} by hand",
+            original.display(),
+            backup.display()
+        ))));
+    }
+    if let Some(parent) = original.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::rename(backup, original)?;
+    Ok(true)
+}
+
+/// Files stow skips by default: VCS metadata, editor backups and top-level
+/// README/LICENSE/COPYING. `relative` is the directory `name` is in.
+fn stow_ignores(relative: &Path, name: &str) -> bool {
+    let top_level = relative.as_os_str().is_empty();
+    STOW_IGNORED.contains(&name)
+        || name.ends_with('~')
+        || (top_level && ["README", "LICENSE", "COPYING"].iter().any(|prefix| name.starts_with(prefix)))
+}
+
+const STOW_IGNORED: &[&str] = &[".git", ".gitignore", ".gitmodules", ".svn", ".hg", "CVS", "_darcs", ".stow-local-ignore"];
+
 pub async fn apply_dotfiles(dotfiles: &Dotfiles) -> Result<(), Box<dyn Error>> {
-    let target_dir = dotfiles.target_directory
-        .as_ref()
-        .map(|s| s.as_str())
+    let target_dir = dotfiles.target_directory.as_deref()
         .unwrap_or("dotfiles");
 
     let home_dir = std::env::var("HOME")?;
     let dotfiles_path = format!("{}/{}", home_dir, target_dir);
 
-    std::env::set_current_dir(&dotfiles_path)?;
-
     let packages = match &dotfiles.packages {
-        Some(packages) => packages.clone(),
-        None => {
-            let entries = std::fs::read_dir(".")?;
-            let mut discovered_packages = Vec::new();
-
-            for entry in entries {
-                let entry = entry?;
-                let path = entry.path();
-                if path.is_dir() {
-                    if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                        if !name.starts_with('.') && name != "README.md" {
-                            discovered_packages.push(name.to_string());
-                        }
-                    }
-                }
-            }
-            discovered_packages
-        }
+        Some(packages) => package_names(packages),
+        None => discover_dotfiles_packages(Path::new(&dotfiles_path), &dotfiles.exclude)?,
     };
 
     let dry_run = dotfiles.dry_run.unwrap_or(false);
+    // stow's default target: the checkout's parent
+    let stow_target = Path::new(&dotfiles_path).parent().unwrap_or(Path::new(&home_dir));
 
     if dry_run {
         println!("{} DRY RUN: Would apply dotfiles packages: {}", "🔍".yellow(), packages.join(", "));
@@ -485,15 +1240,26 @@ pub async fn apply_dotfiles(dotfiles: &Dotfiles) -> Result<(), Box<dyn Error>> {
         println!("{} Applying dotfiles packages: {}", "🔗".yellow(), packages.join(", "));
     }
 
+    // Package -> why it wasn't stowed
+    let mut failed: Vec<(String, String)> = Vec::new();
     for package in &packages {
         if dry_run {
             println!("  {} DRY RUN: Would stow {}...", "→".cyan(), package);
 
-            let output = Command::new("stow")
-                .args(&["--no", "-v", package])
+            let conflicts = stow_conflicts(&Path::new(&dotfiles_path).join(package), stow_target);
+            if !conflicts.is_empty() {
+                let shown: Vec<String> = conflicts.iter().map(|path| path.display().to_string()).collect();
+                println!("  {} {} would first back up existing files: {}", "⚠".yellow(), package, shown.join(", "));
+                continue;
+            }
+
+            let output = tokio::process::Command::new("stow")
+                .args(["--no", "-v", package])
+                .current_dir(&dotfiles_path)
                 .stderr(Stdio::piped())
                 .stdout(Stdio::piped())
-                .output()?;
+                .output()
+                .await?;
 
             if output.status.success() {
                 println!("  {} {} would be applied successfully", "✓".green(), package);
@@ -510,14 +1276,20 @@ pub async fn apply_dotfiles(dotfiles: &Dotfiles) -> Result<(), Box<dyn Error>> {
         } else {
             println!("  {} Stowing {}...", "→".cyan(), package);
 
-            let output = Command::new("stow")
-                .args(&["-v", package])
+            let backed_up = back_up_conflicts(&Path::new(&dotfiles_path).join(package), stow_target)?;
+            let started = std::time::SystemTime::now();
+            let output = tokio::process::Command::new("stow")
+                .args(["-v", package])
+                .current_dir(&dotfiles_path)
                 .stderr(Stdio::piped())
                 .stdout(Stdio::piped())
-                .output()?;
+                .output()
+                .await?;
+            journal::record_output(&format!("dotfiles:{}", package), &format!("stow -v {}", package), started, &output);
 
             if output.status.success() {
                 println!("  {} {} applied successfully", "✓".green(), package);
+                journal::record_stowed(&dotfiles_path, package);
             } else {
                 let stderr = String::from_utf8_lossy(&output.stderr);
 
@@ -525,13 +1297,31 @@ pub async fn apply_dotfiles(dotfiles: &Dotfiles) -> Result<(), Box<dyn Error>> {
                     println!("  {} {} has conflicts with existing files", "⚠".yellow(), package);
                     println!("    {} To resolve: backup existing files or use 'stow --adopt {}'", "💡".blue(), package);
                     println!("    Skipping {} for now...", package);
+                    failed.push((package.clone(), "conflicts with existing files".to_string()));
                 } else {
                     println!("  {} Failed to apply {}: {}", "✖".red(), package, stderr.trim());
+                    failed.push((package.clone(), stderr.trim().to_string()));
+                }
+                // Nothing was linked, so the moved files go back where they were
+                for backup in &backed_up {
+                    restore_backup(backup)?;
                 }
             }
         }
     }
 
+    if !failed.is_empty() {
+        let reasons: Vec<String> = failed
+            .iter()
+            .map(|(package, reason)| format!("{} ({})", package, reason))
+            .collect();
+        return Err(Box::new(std::io::Error::other(format!(
+            "{} of {} dotfiles packages were not stowed: {}",
+            failed.len(),
+            packages.len(),
+            reasons.join(", ")
+        ))));
+    }
     println!("{} All dotfiles applied successfully!", "🎉".green());
     Ok(())
 }
@@ -553,55 +1343,48 @@ pub async fn setup_dotfiles(dotfiles: &Dotfiles) -> Result<(), Box<dyn Error>> {
 }
 
 pub async fn get_system_status(config: &Config) -> Result<SystemStatus, Box<dyn Error>> {
-    let os_description = check_current_os_name().await?;
-    let os_key = detect_os_key(&os_description);
-
-    let Some(os_config) = config.os_entries.get(os_key) else {
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!("No configuration found for OS: {}", os_description),
-        )));
+    let Some((_, os_config)) = find_matching_os(config) else {
+        return Err(no_matching_os_error());
     };
 
-    let configured_apps = get_all_configured_apps(config, os_config);
-    let (installed, missing) = check_applications_status(&configured_apps).await?;
-
-    let installed_packages = categorize_apps(&installed, os_config, config);
-    let missing_packages = categorize_apps(&missing, os_config, config);
-
-    let dotfiles_status = if let Some(dotfiles) = &os_config.dotfiles {
-        let target_dir = dotfiles.target_directory
-            .as_ref()
-            .map(|s| s.as_str())
-            .unwrap_or("dotfiles");
-
-        let home_dir = std::env::var("HOME")?;
-        let dotfiles_path = format!("{}/{}", home_dir, target_dir);
-        let cloned = Path::new(&dotfiles_path).exists();
-
-        let packages = match &dotfiles.packages {
-            Some(pkgs) => pkgs.clone(),
-            None => vec![],
-        };
-
-        DotfilesStatus {
-            cloned,
-            applied: false,
-            packages,
-        }
-    } else {
-        DotfilesStatus {
-            cloned: false,
-            applied: false,
-            packages: vec![],
+    let configured_apps = get_all_configured_apps(config, &os_config);
+    let (installed, missing) = check_applications_status(&configured_apps, &os_config).await?;
+
+    let installed_packages = categorize_apps(&installed, &os_config, config);
+    let missing_packages = categorize_apps(&missing, &os_config, config);
+
+    let (dotfiles_status, dotfiles_diff) = match &os_config.dotfiles {
+        Some(dotfiles) => {
+            let diff = dotfiles_diff(dotfiles, Path::new(&std::env::var("HOME")?));
+            let status = DotfilesStatus {
+                cloned: !diff.needs_clone,
+                applied: !diff.needs_clone && diff.packages_to_apply.is_empty(),
+                packages: dotfiles.packages.as_deref().map(package_names).unwrap_or_default(),
+            };
+            (status, diff)
         }
+        None => (
+            DotfilesStatus {
+                cloned: false,
+                applied: false,
+                packages: vec![],
+            },
+            DotfilesDiff {
+                needs_clone: false,
+                packages_to_apply: vec![],
+                packages_already_applied: vec![],
+            },
+        ),
     };
 
     Ok(SystemStatus {
-        os_name: os_description,
+        os_name: platform::current().description.clone(),
+        profile: active_profile(config),
+        hosts: active_hosts(config),
         installed_packages,
         missing_packages,
         dotfiles_status,
+        dotfiles_diff,
     })
 }
 
@@ -609,58 +1392,18 @@ pub fn format_status_json(status: &SystemStatus) -> Result<String, Box<dyn Error
     serde_json::to_string_pretty(status).map_err(|e| Box::new(e) as Box<dyn Error>)
 }
 
-pub async fn get_config_diff(config: &Config) -> Result<ConfigDiff, Box<dyn Error>> {
-    let os_description = check_current_os_name().await?;
-    let os_key = detect_os_key(&os_description);
-
-    let Some(os_config) = config.os_entries.get(os_key) else {
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!("No configuration found for OS: {}", os_description),
-        )));
+/// What apply would do on this machine. Probing for `packages_to_remove` checks every
+/// package spinup ever installed, so it's only done `with_removals`; otherwise it's empty.
+pub async fn get_config_diff(config: &Config, with_removals: bool) -> Result<ConfigDiff, Box<dyn Error>> {
+    let Some((os_key, os_config)) = find_matching_os(config) else {
+        return Err(no_matching_os_error());
     };
 
-    let configured_apps = get_all_configured_apps(config, os_config);
-    let (_installed, missing) = check_applications_status(&configured_apps).await?;
+    let configured_apps = get_all_configured_apps(config, &os_config);
+    let (_installed, missing) = check_applications_status(&configured_apps, &os_config).await?;
 
     let dotfiles_diff = if let Some(dotfiles) = &os_config.dotfiles {
-
-        let target_dir = dotfiles.target_directory
-            .as_ref()
-            .map(|s| s.as_str())
-            .unwrap_or("dotfiles");
-
-        let home_dir = std::env::var("HOME")?;
-        let dotfiles_path = format!("{}/{}", home_dir, target_dir);
-        let needs_clone = !Path::new(&dotfiles_path).exists();
-
-        let packages = match &dotfiles.packages {
-            Some(pkgs) => pkgs.clone(),
-            None => {
-                let mut pkgs = Vec::new();
-                if let Ok(entries) = std::fs::read_dir(&dotfiles_path) {
-                    for entry in entries {
-                        if let Ok(entry) = entry {
-                            let path = entry.path();
-                            if path.is_dir() {
-                                if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                                    if !name.starts_with('.') && name != "README.md" {
-                                        pkgs.push(name.to_string());
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-                pkgs
-            }
-        };
-
-        DotfilesDiff {
-            needs_clone,
-            packages_to_apply: packages.clone(),
-            packages_already_applied: vec![],
-        }
+        dotfiles_diff(dotfiles, Path::new(&std::env::var("HOME")?))
     } else {
         DotfilesDiff {
             needs_clone: false,
@@ -669,14 +1412,47 @@ pub async fn get_config_diff(config: &Config) -> Result<ConfigDiff, Box<dyn Erro
         }
     };
 
-    let mut missing_categorized = categorize_apps(&missing, os_config, config);
-    let tasks_to_run = missing_categorized.remove("tasks").unwrap_or_default();
+    let mut missing_categorized = categorize_apps(&missing, &os_config, config);
+    missing_categorized.remove("tasks");
     let packages_to_install = missing_categorized;
 
+    // Only the managers and tasks with pending work, dependencies first
+    let install_order: Vec<Node> = DependencyGraph::build(&os_config)?
+        .order()?
+        .into_iter()
+        .filter(|node| match node {
+            Node::Manager(name) => packages_to_install.contains_key(name),
+            Node::Task(name) => missing.contains(name),
+        })
+        .collect();
+
+    let tasks_to_run = install_order
+        .iter()
+        .filter_map(|node| match node {
+            Node::Task(name) => Some(name.clone()),
+            Node::Manager(_) => None,
+        })
+        .collect();
+
+    let packages_to_remove = if with_removals {
+        let managed = ManagedState::load().unwrap_or_else(|e| {
+            eprintln!("{} {}, ignoring it", "⚠".yellow(), e);
+            ManagedState::default()
+        });
+        packages_to_prune(config, &os_key, &managed, |manager, package| backend_for(manager).is_installed(package)).await?
+    } else {
+        HashMap::new()
+    };
+
     Ok(ConfigDiff {
-        os_name: os_description,
+        os_key,
+        os_name: platform::current().description.clone(),
+        profile: active_profile(config),
+        hosts: active_hosts(config),
         packages_to_install,
         tasks_to_run,
+        install_order,
+        packages_to_remove,
         dotfiles_diff,
     })
 }
@@ -0,0 +1,484 @@
+use crate::profile;
+use crate::state::state_dir;
+use crate::structs::{BackedUpFile, JournalStep, RunJournal, StowedPackage};
+use blake2::{Blake2s256, Digest};
+use colored::Colorize;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Output, Stdio};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+const RUNS_DIR: &str = "runs";
+const BACKUPS_DIR: &str = "backups";
+/// Older journals are deleted when a run starts.
+const KEEP_RUNS: usize = 100;
+const STDERR_TAIL_LINES: usize = 20;
+
+static CURRENT: Mutex<Option<RunJournal>> = Mutex::new(None);
+static STARTED: OnceLock<Instant> = OnceLock::new();
+/// `~/.spinup/runs`, next to the state file and the token cache.
+pub fn runs_dir() -> PathBuf {
+    state_dir().join(RUNS_DIR)
+}
+
+/// Where the current run moves `relative` (a path under the stow target) out of the
+/// way: `~/.spinup/backups/<run id>/<relative>`.
+pub fn backup_path(relative: &Path) -> PathBuf {
+    let id = CURRENT
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_ref()
+        .map_or_else(|| format_time(SystemTime::now(), "%Y%m%d-%H%M%S"), |journal| journal.id.clone());
+    state_dir().join(BACKUPS_DIR).join(id).join(relative)
+}
+
+/// Forgets the current run, so each test starts outside one.
+#[cfg(test)]
+pub(crate) fn reset() {
+    CURRENT.lock().unwrap_or_else(|e| e.into_inner()).take();
+}
+
+/// Starts journaling `command`. Until this is called, the other functions do nothing,
+/// so read-only commands leave no trace.
+pub fn start(command: &str) {
+    let now = SystemTime::now();
+    STARTED.get_or_init(Instant::now);
+    let mut id = format_time(now, "%Y%m%d-%H%M%S");
+    let mut suffix = 1;
+    while runs_dir().join(format!("{}.json", id)).exists() {
+        suffix += 1;
+        // Padded, so ids of the same second still sort in order
+        id = format!("{}-{:03}", format_time(now, "%Y%m%d-%H%M%S"), suffix);
+    }
+
+    remove_old_runs();
+    let journal = RunJournal {
+        id,
+        command: command.to_string(),
+        started_at: format_time(now, "%Y-%m-%dT%H:%M:%SZ"),
+        finished_at: None,
+        duration_ms: None,
+        config_source: None,
+        config_hash: None,
+        os_key: None,
+        profile: None,
+        steps: Vec::new(),
+        completed: Vec::new(),
+        error: None,
+        installed: Default::default(),
+        stowed: Vec::new(),
+        backed_up: Vec::new(),
+        cloned: None,
+        undone_by: None,
+    };
+    save(&journal);
+    *CURRENT.lock().unwrap_or_else(|e| e.into_inner()) = Some(journal);
+}
+
+/// Records where the config came from and a hash of its text.
+pub fn set_config(source: &str, content: &str) {
+    let hash = Blake2s256::digest(content.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    update(|journal| {
+        journal.config_source = Some(source.to_string());
+        journal.config_hash = Some(hash);
+    });
+}
+
+/// Records the OS section the run resolved, with the active profile.
+pub fn set_os(os_key: &str) {
+    update(|journal| {
+        journal.os_key = Some(os_key.to_string());
+        journal.profile = profile::selected().map(str::to_string);
+    });
+}
+
+pub fn record(step: JournalStep) {
+    update(|journal| journal.steps.push(step));
+}
+
+/// Marks a manager, task or dotfiles as done, e.g. `task:rustup`.
+pub fn complete(name: &str) {
+    update(|journal| journal.completed.push(name.to_string()));
+}
+
+pub fn record_installed(manager: &str, packages: &[String]) {
+    update(|journal| {
+        journal
+            .installed
+            .entry(manager.to_string())
+            .or_default()
+            .extend(packages.iter().cloned())
+    });
+}
+
+pub fn record_stowed(directory: &str, package: &str) {
+    update(|journal| {
+        journal.stowed.push(StowedPackage {
+            directory: directory.to_string(),
+            package: package.to_string(),
+        })
+    });
+}
+
+pub fn record_backup(original: &Path, backup: &Path) {
+    update(|journal| {
+        journal.backed_up.push(BackedUpFile {
+            original: original.display().to_string(),
+            backup: backup.display().to_string(),
+        })
+    });
+}
+
+pub fn record_cloned(path: &str) {
+    update(|journal| journal.cloned = Some(path.to_string()));
+}
+
+/// Notes on run `id` that the current run undid it.
+pub fn mark_undone(id: &str) -> Result<(), Box<dyn Error>> {
+    let mut run = load(id)?;
+    run.undone_by = CURRENT
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_ref()
+        .map(|journal| journal.id.clone());
+    save(&run);
+    Ok(())
+}
+
+/// The last `command` run for the same config text that did anything, when it didn't
+/// succeed: the point `--resume` continues from. Runs that ran no command (declined at
+/// the prompt, nothing to do, or stopped before the first step) are passed over. Its
+/// completed steps are carried into this run, so resuming a resumed run still skips
+/// everything done so far.
+pub fn resume_point(command: &str) -> Option<RunJournal> {
+    let (id, hash) = {
+        let current = CURRENT.lock().unwrap_or_else(|e| e.into_inner());
+        let journal = current.as_ref()?;
+        (journal.id.clone(), journal.config_hash.clone()?)
+    };
+    let previous = list()
+        .into_iter()
+        .filter(|run| run.id != id && run.command == command)
+        .filter(|run| !run.steps.is_empty())
+        .find(|run| run.config_hash.as_deref() == Some(hash.as_str()))?;
+    if previous.finished_at.is_some() && previous.error.is_none() {
+        return None;
+    }
+    update(|journal| journal.completed.extend(previous.completed.iter().cloned()));
+    Some(previous)
+}
+
+/// Marks the run finished, failed when `error` is given.
+pub fn finish(error: Option<String>) {
+    update(|journal| {
+        journal.finished_at = Some(format_time(SystemTime::now(), "%Y-%m-%dT%H:%M:%SZ"));
+        journal.duration_ms = STARTED.get().map(|started| started.elapsed().as_millis() as u64);
+        journal.error = error;
+    });
+    CURRENT.lock().unwrap_or_else(|e| e.into_inner()).take();
+}
+
+/// Runs `command` with stdout on the terminal and stderr both shown and kept, then
+/// records it as step `name`, shown in the journal as `shown`.
+pub async fn run_step(name: &str, shown: &str, mut command: tokio::process::Command) -> std::io::Result<ExitStatus> {
+    let started_at = format_time(SystemTime::now(), "%Y-%m-%dT%H:%M:%SZ");
+    let timer = Instant::now();
+
+    let result = async {
+        let mut child = command.stderr(Stdio::piped()).spawn()?;
+        let mut tail = VecDeque::with_capacity(STDERR_TAIL_LINES);
+        if let Some(stderr) = child.stderr.take() {
+            let mut lines = BufReader::new(stderr).lines();
+            while let Some(line) = lines.next_line().await? {
+                eprintln!("{}", line);
+                if tail.len() == STDERR_TAIL_LINES {
+                    tail.pop_front();
+                }
+                tail.push_back(line);
+            }
+        }
+        Ok::<_, std::io::Error>((child.wait().await?, tail))
+    }
+    .await;
+
+    let (exit_code, stderr_tail) = match &result {
+        Ok((status, tail)) => (status.code(), tail.iter().cloned().collect()),
+        Err(e) => (None, vec![e.to_string()]),
+    };
+    record(JournalStep {
+        name: name.to_string(),
+        command: shown.to_string(),
+        started_at,
+        duration_ms: timer.elapsed().as_millis() as u64,
+        exit_code,
+        stderr_tail,
+    });
+    result.map(|(status, _)| status)
+}
+
+/// Records a command that already ran with its output captured.
+pub fn record_output(name: &str, shown: &str, started: SystemTime, output: &Output) {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let lines: Vec<&str> = stderr.lines().collect();
+    record(JournalStep {
+        name: name.to_string(),
+        command: shown.to_string(),
+        started_at: format_time(started, "%Y-%m-%dT%H:%M:%SZ"),
+        duration_ms: started.elapsed().map_or(0, |d| d.as_millis() as u64),
+        exit_code: output.status.code(),
+        stderr_tail: lines[lines.len().saturating_sub(STDERR_TAIL_LINES)..].iter().map(|l| l.to_string()).collect(),
+    });
+}
+
+/// All journals, newest first. Unreadable files are skipped.
+pub fn list() -> Vec<RunJournal> {
+    let Ok(entries) = fs::read_dir(runs_dir()) else { return Vec::new() };
+    let mut runs: Vec<RunJournal> = entries
+        .flatten()
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|entry| fs::read_to_string(entry.path()).ok())
+        .filter_map(|content| serde_json::from_str(&content).ok())
+        .collect();
+    runs.sort_by(|a, b| b.id.cmp(&a.id));
+    runs
+}
+
+/// The journal with `id`, or the newest one for `last`.
+pub fn load(id: &str) -> Result<RunJournal, Box<dyn Error>> {
+    if id == "last" {
+        return list()
+            .into_iter()
+            .next()
+            .ok_or_else(|| Box::new(std::io::Error::other("No runs recorded yet")) as Box<dyn Error>);
+    }
+    // Ids are made of digits and dashes: nothing that could lead out of the runs dir
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err(Box::new(std::io::Error::other(format!(
+            "Invalid run id {:?} (see `spinup history`)",
+            id
+        ))));
+    }
+    let path = runs_dir().join(format!("{}.json", id));
+    let content = fs::read_to_string(&path).map_err(|e| {
+        Box::new(std::io::Error::new(
+            e.kind(),
+            format!("No run {} in {} (see `spinup history`)", id, runs_dir().display()),
+        )) as Box<dyn Error>
+    })?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+/// Applies `change` to the current journal and saves it right away, so an
+/// interrupted run still leaves its steps behind.
+fn update(change: impl FnOnce(&mut RunJournal)) {
+    let mut current = CURRENT.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(journal) = current.as_mut() {
+        change(journal);
+        save(journal);
+    }
+}
+
+/// Failing to write the journal only warns: it must never fail the run itself.
+fn save(journal: &RunJournal) {
+    let path = runs_dir().join(format!("{}.json", journal.id));
+    let result = fs::create_dir_all(runs_dir())
+        .and_then(|_| serde_json::to_string_pretty(journal).map_err(std::io::Error::other))
+        .and_then(|json| {
+            let tmp = path.with_extension("json.tmp");
+            fs::write(&tmp, json)?;
+            fs::rename(&tmp, &path)
+        });
+    if let Err(e) = result {
+        eprintln!("{} Could not write run journal {}: {}", "⚠".yellow(), path.display(), e);
+    }
+}
+
+/// Deletes the journals past the newest `KEEP_RUNS`, with the files they backed up:
+/// without the journal, `spinup undo` couldn't restore them anyway.
+fn remove_old_runs() {
+    for run in list().iter().skip(KEEP_RUNS - 1) {
+        let _ = fs::remove_file(runs_dir().join(format!("{}.json", run.id)));
+        let _ = fs::remove_dir_all(state_dir().join(BACKUPS_DIR).join(&run.id));
+    }
+}
+
+/// Formats a UTC time with `%Y`, `%m`, `%d`, `%H`, `%M` and `%S`.
+fn format_time(time: SystemTime, pattern: &str) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let seconds_of_day = secs % 86_400;
+    pattern
+        .replace("%Y", &format!("{:04}", year))
+        .replace("%m", &format!("{:02}", month))
+        .replace("%d", &format!("{:02}", day))
+        .replace("%H", &format!("{:02}", seconds_of_day / 3600))
+        .replace("%M", &format!("{:02}", seconds_of_day % 3600 / 60))
+        .replace("%S", &format!("{:02}", seconds_of_day % 60))
+}
+
+/// Days since 1970-01-01 to a (year, month, day) date, from Howard Hinnant's algorithm.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::TestStateDir;
+    use std::time::Duration;
+
+    #[test]
+    fn test_format_time() {
+        let time = UNIX_EPOCH + Duration::from_secs(1_792_247_412);
+        assert_eq!(format_time(time, "%Y-%m-%dT%H:%M:%SZ"), "2026-10-17T14:30:12Z");
+        assert_eq!(format_time(UNIX_EPOCH + Duration::from_secs(951_782_400), "%Y%m%d"), "20000229");
+    }
+
+    #[test]
+    fn test_journal_round_trip() {
+        let _state = TestStateDir::new("journal-round-trip");
+        start("apply");
+        set_config("spinup.toml", "version = 7");
+        record_installed("apt", &["git".to_string(), "curl".to_string()]);
+        record_stowed("/home/me/dotfiles", "zsh");
+        record_backup(Path::new("/home/me/.zshrc"), &backup_path(Path::new(".zshrc")));
+        complete("manager:apt");
+        finish(Some("task:fonts failed".to_string()));
+
+        let run = load("last").unwrap();
+        assert_eq!(run.command, "apply");
+        assert_eq!(run.config_source.as_deref(), Some("spinup.toml"));
+        assert!(run.config_hash.is_some());
+        assert_eq!(run.installed["apt"], vec!["git", "curl"]);
+        assert_eq!(run.stowed.len(), 1);
+        assert_eq!(run.stowed[0].directory, "/home/me/dotfiles");
+        assert_eq!(run.stowed[0].package, "zsh");
+        assert_eq!(run.backed_up[0].original, "/home/me/.zshrc");
+        assert!(run.backed_up[0].backup.ends_with(&format!("backups/{}/.zshrc", run.id)));
+        assert_eq!(run.completed, vec!["manager:apt"]);
+        assert!(run.finished_at.is_some());
+        assert_eq!(run.error.as_deref(), Some("task:fonts failed"));
+        assert_eq!(load(&run.id).unwrap().id, run.id);
+        assert!(load("../state").unwrap_err().to_string().starts_with("Invalid run id"));
+        assert!(load("/etc/passwd").is_err());
+
+        // Nothing is recorded outside a run
+        complete("manager:brew");
+        assert_eq!(load(&run.id).unwrap().completed, vec!["manager:apt"]);
+    }
+
+    #[test]
+    fn test_resume_point_needs_a_failed_run_of_the_same_config() {
+        let _state = TestStateDir::new("journal-resume");
+        let step = |name: &str| {
+            record(JournalStep {
+                name: name.to_string(),
+                command: format!("install {}", name),
+                started_at: "2026-10-17T14:30:12Z".to_string(),
+                duration_ms: 1,
+                exit_code: Some(0),
+                stderr_tail: Vec::new(),
+            })
+        };
+        start("apply");
+        set_config("spinup.toml", "version = 7");
+        step("apt");
+        complete("manager:apt");
+        finish(Some("task:fonts failed".to_string()));
+
+        // Declined at the prompt: it ran nothing, so it doesn't hide the failed run
+        start("apply");
+        set_config("spinup.toml", "version = 7");
+        finish(None);
+
+        // The config changed since: nothing to resume
+        start("apply");
+        set_config("spinup.toml", "version = 7\n# edited");
+        assert!(resume_point("apply").is_none());
+        finish(None);
+
+        // A resume declined at the prompt carried the steps over but ran nothing either
+        start("apply");
+        set_config("spinup.toml", "version = 7");
+        assert!(resume_point("apply").is_some());
+        finish(None);
+
+        start("apply");
+        set_config("spinup.toml", "version = 7");
+        let previous = resume_point("apply").unwrap();
+        assert_eq!(previous.error.as_deref(), Some("task:fonts failed"));
+        step("task:fonts");
+        complete("task:fonts");
+        finish(None);
+        assert_eq!(load("last").unwrap().completed, vec!["manager:apt", "task:fonts"]);
+
+        // The resumed run succeeded, so the next apply starts from scratch
+        start("apply");
+        set_config("spinup.toml", "version = 7");
+        assert!(resume_point("apply").is_none());
+        assert!(resume_point("run").is_none());
+        finish(None);
+    }
+
+    #[test]
+    fn test_list_is_newest_first_and_keeps_the_last_runs() {
+        let _state = TestStateDir::new("journal-list");
+        assert!(load("last").is_err());
+        start("apply");
+        finish(None);
+        start("run");
+        finish(None);
+
+        let runs = list();
+        let commands: Vec<&str> = runs.iter().map(|run| run.command.as_str()).collect();
+        assert_eq!(commands, vec!["run", "apply"]);
+        assert_eq!(load("last").unwrap().id, runs[0].id);
+
+        // More runs than fit a one-digit suffix in the same second still list in order
+        let mut started = Vec::new();
+        for _ in 0..12 {
+            start("history");
+            started.push(CURRENT.lock().unwrap().as_ref().unwrap().id.clone());
+            finish(None);
+        }
+        started.reverse();
+        let listed: Vec<String> = list().into_iter().take(12).map(|run| run.id).collect();
+        assert_eq!(listed, started);
+        for id in &started {
+            fs::remove_file(runs_dir().join(format!("{}.json", id))).unwrap();
+        }
+
+        let mut old = runs[1].clone();
+        for i in 0..120 {
+            old.id = format!("20000101-000000-{:03}", i);
+            save(&old);
+            fs::create_dir_all(state_dir().join(BACKUPS_DIR).join(&old.id)).unwrap();
+        }
+        start("apply");
+        finish(None);
+
+        // The new run, the two above and the newest 97 of the old ones
+        assert_eq!(list().len(), KEEP_RUNS);
+        assert!(load("20000101-000000-022").is_err());
+        assert!(!state_dir().join(BACKUPS_DIR).join("20000101-000000-022").exists());
+        assert!(load("20000101-000000-023").is_ok());
+        assert!(state_dir().join(BACKUPS_DIR).join("20000101-000000-023").exists());
+        assert_eq!(load("last").unwrap().command, "apply");
+    }
+}
@@ -1,15 +1,29 @@
 mod auth;
+mod backends;
+mod capture;
+mod ci;
 mod commands;
+mod condition;
 mod config;
 mod crypto;
+mod graph;
 mod helpers;
+mod journal;
+mod migrate;
+mod platform;
+mod profile;
+mod schedule;
+mod source;
+mod state;
 mod structs;
 mod test_stow;
 mod tests_v7;
-use commands::{run_diff, run_status};
-use helpers::{check_current_os_name, find_matching_os};
+mod validate;
+mod vars;
+use commands::{run_add, run_apply, run_capture, run_diff, run_history, run_history_show, run_migrate, run_profile, run_profile_clear, run_prune, run_status, run_to_undo, run_undo, run_validate, AddItem, AddOptions, ApplyOptions, CaptureOptions, ProfileAction};
+use helpers::{find_matching_os, no_matching_os_error};
+use source::{ConfigSource, DEFAULT_SOURCE};
 use structs::Config;
-use tokio;
 use colored::Colorize;
 
 fn print_help() {
@@ -22,31 +36,114 @@ USAGE:
 SUBCOMMANDS:
     status          Show current system status compared to config
     diff            Show differences between system and config (with preview)
-    add             Add new applications or dotfiles to config
+    apply           Show the diff, confirm, then apply exactly that diff
+    prune           Remove packages spinup installed that are no longer in the config
+    history         List past apply/prune/run journals; `history show <id|last>` shows one
+    undo [ID]       Reverse a run (default: the last apply or run): remove the packages it
+                      installed and the stow links it created, restore the files it backed up
+                      for them, list tasks it can't reverse
+    profile         List profiles; `profile use <name>` saves one for this machine, `profile clear` drops it
+    validate [SRC]  Check the config for errors (exits 2 if any are found)
+    capture         Write a v7 config describing this machine (--merge: into the config)
+    migrate [SRC]   Convert a v2 JSON or v6 TOML config to v7
+    schema          Print the JSON Schema for config.toml (for Taplo / VS Code)
+    add             Add packages, tasks or dotfiles to the config, keeping its comments:
+                      add package <manager> <package>...
+                      add task <name> <script> [--check CMD] [--creates PATH] [--unless CMD]
+                               [--description TEXT] [--shell SHELL] [--cwd DIR] [--when COND]
+                      add app <name> <install command>   (package if recognizable, else task)
+                      add dotfiles --repo URL [--target DIR]
     test-stow       Run stow integration tests
     test-config     Test configuration file parsing
     run             Run the full setup (default)
 
 OPTIONS:
-    --json          Output in JSON format (for status/diff)
-    --dry-run       Preview changes without applying (for add)
-    --gist-id ID    Gist ID to update (for add subcommand)
+    --config SOURCE Where to read the config from (for run/status/diff/apply):
+                      gist:<id|filename>  (default: gist:config.toml)
+                      <path> | file:<path>
+                      https://host/config.toml
+                      git+<repository>#<ref>:<path>
+                      - (stdin)
+    --json          Output in JSON format (for status/diff/validate/history)
+    --yes, -y       Don't ask for confirmation (for apply/prune/undo/add)
+    --prune         Also remove packages dropped from the config (for diff/apply)
+    --resume        Skip what the last failed or interrupted apply of the same config
+                      finished (for apply)
+    --keep-going, -k
+                    Continue past failed steps and list them all at the end (for apply)
+    --output FILE   Write the result to FILE instead of stdout (for migrate/capture)
+    --merge         Merge into the --config source and compare with it (for capture)
+    --profile NAME  Apply [profiles.NAME] on top of the OS section (default: the saved profile)
+    --os KEY        Use this OS section instead of detecting it from /etc/os-release,
+                      e.g. --os ubuntu (default: ID, then ID_LIKE, then [linux])
+    --jobs N        How many independent installs and tasks run at once (default: 4);
+                      managers sharing a lock (apt, dnf, sudo tasks) still run one at a time
+    --depends NAME  Task or manager that must run first (for add)
+    --dry-run       Show the edit without saving it (for add)
+    --gist-id ID    Also push the edited config to this gist (for add)
+    --ci, --non-interactive
+                    Never prompt (pass --yes), clear the screen or start a GitHub login;
+                      status/diff exit 5 when the machine differs from the config
     --help          Show this help message
 
+EXIT CODES:
+    0  Success
+    1  Other failure (bad arguments, a command that couldn't run, --ci without --yes)
+    2  Config error: unreadable, invalid, failed validation or no section for this machine
+    3  GitHub authentication failed or would need a login (--ci)
+    4  Partial failure: some apply/prune/run steps failed
+    5  Drift: status/diff found changes to make (--ci only)
+
 EXAMPLES:
     spinup status              # Show installed vs missing apps
     spinup status --json       # JSON output for scripting
     spinup diff                # Show what would be installed
-    spinup add app git "sudo dnf install -y git"
-    spinup add app k9s "brew install k9s" --depends brew
-    spinup add dotfiles --repo https://github.com/user/dotfiles.git
+    spinup apply               # Review the diff, confirm, then apply it
+    spinup apply --yes         # Apply without prompting
+    spinup apply --prune       # Apply, then remove packages dropped from the config
+    spinup --jobs 1 apply      # Install strictly one thing at a time
+    spinup apply --resume --keep-going   # Pick up after a failed apply, don't stop at the next failure
+    spinup --ci --config ./config.toml diff   # In a pipeline: exit 5 if the machine drifted
+    spinup history show last   # What the last apply ran, with exit codes and stderr
+    spinup undo                # Take back the last apply: packages and stow links
+    spinup profile use server  # Use [profiles.server] on this machine from now on
+    spinup --profile laptop diff
+    spinup validate ./config.toml   # Lint a config before pushing it
+    spinup migrate ./config.toml --output config.v7.toml
+    spinup capture --output config.toml       # Start a config from this machine
+    spinup --config ./config.toml capture --merge --output merged.toml
+    spinup --config ./config.toml run   # Set up from a local file, no GitHub
+    spinup --config git+https://github.com/org/configs.git#main:spinup.toml diff
+    spinup add package dnf ripgrep fd-find    # Edits the gist config and pushes it back
+    spinup add app k9s "brew install k9s" --depends task:brew
+    spinup add task rustup "curl -sSf https://sh.rustup.rs | sh -s -- -y" --creates ~/.cargo/bin/rustup
+    spinup --config ./config.toml add dotfiles --repo https://github.com/user/dotfiles.git --os macos
 "#
     );
 }
 
 #[tokio::main]
 async fn main() {
-    let args: Vec<String> = std::env::args().collect();
+    let mut args: Vec<String> = std::env::args().collect();
+    let config_source = take_option(&mut args, "--config");
+    if let Some(os_key) = take_option(&mut args, "--os") {
+        platform::set_override(&os_key);
+    }
+    if let Some(name) = take_option(&mut args, "--profile") {
+        profile::set_override(&name);
+    }
+    if let Some(jobs) = take_option(&mut args, "--jobs") {
+        match jobs.parse::<usize>() {
+            Ok(jobs) if jobs > 0 => schedule::set_jobs(jobs),
+            _ => {
+                eprintln!("--jobs expects a positive number, got `{}`", jobs);
+                std::process::exit(ci::FAILURE);
+            }
+        }
+    }
+    if take_flag(&mut args, &["--ci", "--non-interactive"]) {
+        ci::enable();
+    }
 
     if args.len() > 1 && (args[1] == "--help" || args[1] == "-h") {
         print_help();
@@ -68,159 +165,443 @@ async fn main() {
         let config_file = &args[2];
         let dry_run = args.len() > 3 && args[3] == "--dry-run";
 
-        match std::fs::read_to_string(config_file) {
-            Ok(content) => match config::parse_config(&content) {
-                Ok(config) => {
-                    let os_description = check_current_os_name().await.unwrap();
-                    let Some((_, os)) = find_matching_os(&config, &os_description).await else {
-                        eprintln!("No matching OS found for: {}", os_description);
-                        return;
-                    };
-
-                    let apps = helpers::get_all_configured_apps(&config, &os);
-                    if let Err(e) = helpers::check_applications(&apps).await {
-                        eprintln!("Error checking applications: {}", e);
-                    }
+        match config::read_config_file(config_file) {
+            Ok(config) => {
+                let Some((_, os)) = find_matching_os(&config) else {
+                    eprintln!("{}", no_matching_os_error());
+                    std::process::exit(ci::CONFIG_ERROR);
+                };
 
-                    if let Some(mut dotfiles) = os.dotfiles.clone() {
-                        if dry_run {
-                            dotfiles.dry_run = Some(true);
-                            println!("🔍 DRY RUN MODE: No changes will be made\n");
-                        }
+                let apps = helpers::get_all_configured_apps(&config, &os);
+                if let Err(e) = helpers::check_applications(&apps, &os).await {
+                    eprintln!("Error checking applications: {}", e);
+                    std::process::exit(ci::FAILURE);
+                }
+
+                if let Some(mut dotfiles) = os.dotfiles.clone() {
+                    if dry_run {
+                        dotfiles.dry_run = Some(true);
+                        println!("🔍 DRY RUN MODE: No changes will be made\n");
+                    }
 
-                        if let Err(e) = helpers::setup_dotfiles(&dotfiles).await {
-                            eprintln!("Error setting up dotfiles: {}", e);
-                        }
+                    if let Err(e) = helpers::setup_dotfiles(&dotfiles).await {
+                        eprintln!("Error setting up dotfiles: {}", e);
+                        std::process::exit(ci::PARTIAL_FAILURE);
                     }
                 }
-                Err(e) => eprintln!("Error parsing config: {}", e),
-            },
-            Err(e) => eprintln!("Error reading config file: {}", e),
+            }
+            Err(e) => {
+                eprintln!("Error loading config file: {}", e);
+                std::process::exit(ci::CONFIG_ERROR);
+            }
         }
         return;
     }
 
     if args.len() > 1 && args[1] == "status" {
         let json_output = args.contains(&"--json".to_string());
-        let config = load_config().await;
-        match config {
-            Ok(config) => {
-                if let Err(e) = run_status(&config, json_output).await {
+        let code = match load_config(config_source.as_deref()).await {
+            Ok(config) => match run_status(&config, json_output).await {
+                Ok(in_sync) => drift_code(in_sync),
+                Err(e) => {
                     eprintln!("Error showing status: {}", e);
+                    ci::exit_code(e.as_ref(), ci::FAILURE)
                 }
-            }
-            Err(e) => eprintln!("Error loading config: {}", e),
-        }
-        return;
+            },
+            Err(e) => config_error(e.as_ref()),
+        };
+        std::process::exit(code);
     }
 
     if args.len() > 1 && args[1] == "diff" {
         let json_output = args.contains(&"--json".to_string());
-        let config = load_config().await;
-        match config {
-            Ok(config) => {
-                if let Err(e) = run_diff(&config, json_output).await {
+        let prune = args.contains(&"--prune".to_string());
+        let code = match load_config(config_source.as_deref()).await {
+            Ok(config) => match run_diff(&config, json_output, prune).await {
+                Ok(in_sync) => drift_code(in_sync),
+                Err(e) => {
                     eprintln!("Error showing diff: {}", e);
+                    ci::exit_code(e.as_ref(), ci::FAILURE)
                 }
+            },
+            Err(e) => config_error(e.as_ref()),
+        };
+        std::process::exit(code);
+    }
+
+    if args.len() > 1 && args[1] == "apply" {
+        let options = ApplyOptions {
+            assume_yes: take_flag(&mut args, &["--yes", "-y"]),
+            prune: take_flag(&mut args, &["--prune"]),
+            resume: take_flag(&mut args, &["--resume"]),
+            keep_going: take_flag(&mut args, &["--keep-going", "-k"]),
+        };
+        require_yes("apply", options.assume_yes);
+        journal::start("apply");
+        let (error, code) = match load_config(config_source.as_deref()).await {
+            Ok(config) => match run_apply(&config, &options).await {
+                Ok(()) => (None, ci::SUCCESS),
+                Err(e) => {
+                    eprintln!("Error applying changes: {}", e);
+                    let code = ci::exit_code(e.as_ref(), ci::PARTIAL_FAILURE);
+                    (Some(e), code)
+                }
+            },
+            Err(e) => {
+                let code = config_error(e.as_ref());
+                (Some(e), code)
+            }
+        };
+        journal::finish(error.map(|e| e.to_string()));
+        std::process::exit(code);
+    }
+
+    if args.len() > 1 && args[1] == "prune" {
+        let assume_yes = args.iter().any(|a| a == "--yes" || a == "-y");
+        require_yes("prune", assume_yes);
+        journal::start("prune");
+        let (error, code) = match load_config(config_source.as_deref()).await {
+            Ok(config) => match run_prune(&config, assume_yes).await {
+                Ok(()) => (None, ci::SUCCESS),
+                Err(e) => {
+                    eprintln!("Error pruning packages: {}", e);
+                    let code = ci::exit_code(e.as_ref(), ci::PARTIAL_FAILURE);
+                    (Some(e), code)
+                }
+            },
+            Err(e) => {
+                let code = config_error(e.as_ref());
+                (Some(e), code)
+            }
+        };
+        journal::finish(error.map(|e| e.to_string()));
+        std::process::exit(code);
+    }
+
+    if args.len() > 1 && args[1] == "profile" {
+        let action = match (args.get(2).map(String::as_str), args.get(3)) {
+            (None | Some("list"), _) => Some(ProfileAction::List),
+            (Some("use"), Some(name)) => Some(ProfileAction::Use(name.clone())),
+            (Some("clear"), _) => None,
+            _ => {
+                eprintln!("Usage: spinup profile [list | use <name> | clear]");
+                std::process::exit(ci::FAILURE);
             }
-            Err(e) => eprintln!("Error loading config: {}", e),
+        };
+        // Not load_config: a saved profile that's gone from the config must still be clearable
+        let result = match action {
+            Some(action) => match ConfigSource::parse(config_source.as_deref().unwrap_or(DEFAULT_SOURCE)) {
+                Ok(source) => match source.load().await {
+                    Ok(config) => run_profile(&config, &action),
+                    Err(e) => Err(e),
+                },
+                Err(e) => Err(e),
+            },
+            None => run_profile_clear(),
+        };
+        if let Err(e) = result {
+            eprintln!("Error updating profile: {}", e);
+            std::process::exit(ci::exit_code(e.as_ref(), ci::FAILURE));
         }
         return;
     }
 
-    if args.len() > 1 && args[1] == "add" {
-        println!("Add subcommand - See --help for usage");
+    if args.len() > 1 && args[1] == "history" {
+        let json_output = take_flag(&mut args, &["--json"]);
+        let result = match (args.get(2).map(String::as_str), args.get(3)) {
+            (None | Some("list"), _) => run_history(json_output),
+            (Some("show"), id) => run_history_show(id.map_or("last", String::as_str), json_output),
+            _ => {
+                eprintln!("Usage: spinup history [list | show [<id> | last]] [--json]");
+                std::process::exit(ci::FAILURE);
+            }
+        };
+        if let Err(e) = result {
+            eprintln!("Error reading history: {}", e);
+            std::process::exit(ci::FAILURE);
+        }
         return;
     }
 
-    helpers::clear_console();
-    helpers::print_banner();
-    helpers::display_system_info().await;
+    if args.len() > 1 && args[1] == "undo" {
+        let assume_yes = take_flag(&mut args, &["--yes", "-y"]);
+        let id = args.get(2).cloned();
+        require_yes("undo", assume_yes);
+        journal::start("undo");
+        // Failing to pick the run changes nothing; failing after that may leave it half undone
+        let (error, code) = match run_to_undo(id.as_deref()) {
+            Ok(run) => match run_undo(&run, assume_yes).await {
+                Ok(()) => (None, ci::SUCCESS),
+                Err(e) => {
+                    let code = ci::exit_code(e.as_ref(), ci::PARTIAL_FAILURE);
+                    (Some(e), code)
+                }
+            },
+            Err(e) => {
+                let code = ci::exit_code(e.as_ref(), ci::FAILURE);
+                (Some(e), code)
+            }
+        };
+        if let Some(e) = &error {
+            eprintln!("Error undoing run: {}", e);
+        }
+        journal::finish(error.map(|e| e.to_string()));
+        std::process::exit(code);
+    }
 
-    let token = match auth::authenticate_with_caching().await {
-        Ok(t) => t,
-        Err(e) => {
-            eprintln!("Authentication failed: {}", e);
-            return;
+    if args.len() > 1 && args[1] == "validate" {
+        let json_output = args.contains(&"--json".to_string());
+        let spec = args
+            .get(2)
+            .filter(|a| !a.starts_with("--"))
+            .cloned()
+            .or(config_source)
+            .unwrap_or_else(|| DEFAULT_SOURCE.to_string());
+
+        let code = match ConfigSource::parse(&spec) {
+            Ok(source) => match source.read().await {
+                Ok(content) => match run_validate(&content, &source.to_string(), json_output) {
+                    Ok(true) => ci::SUCCESS,
+                    Ok(false) => ci::CONFIG_ERROR,
+                    Err(e) => {
+                        eprintln!("Error validating config: {}", e);
+                        ci::FAILURE
+                    }
+                },
+                Err(e) => config_error(e.as_ref()),
+            },
+            Err(e) => config_error(e.as_ref()),
+        };
+        std::process::exit(code);
+    }
+
+    if args.len() > 1 && args[1] == "migrate" {
+        let output = take_option(&mut args, "--output").or_else(|| take_option(&mut args, "-o"));
+        let spec = args
+            .get(2)
+            .filter(|a| !a.starts_with("--"))
+            .cloned()
+            .or(config_source)
+            .unwrap_or_else(|| DEFAULT_SOURCE.to_string());
+
+        let result = match ConfigSource::parse(&spec) {
+            Ok(source) => match source.read().await {
+                Ok(content) => run_migrate(&content, &source.to_string(), output.as_deref()),
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            eprintln!("Error migrating config: {}", e);
+            std::process::exit(ci::exit_code(e.as_ref(), ci::CONFIG_ERROR));
         }
-    };
+        return;
+    }
 
-    let gists = match helpers::get_gists(&token).await {
-        Ok(g) => g,
-        Err(e) => {
-            let err_msg = e.to_string();
-            if err_msg.contains("401 Unauthorized") {
-                eprintln!("{} Authentication token is invalid or expired.", "✖".red());
-                eprintln!(
-                    "{} Clearing cached credentials. Please run spinup again to re-authenticate.",
-                    "ℹ".blue()
-                );
-                let mut crypto = crypto::EncryptionAPI::new();
-                if let Err(c_err) = crypto.clear_cached_token() {
-                    eprintln!("Failed to clear token cache: {}", c_err);
-                }
-            } else {
-                eprintln!("Error fetching gists: {}", e);
+    if args.len() > 1 && args[1] == "capture" {
+        let output = take_option(&mut args, "--output").or_else(|| take_option(&mut args, "-o"));
+        let merge_into = if take_flag(&mut args, &["--merge"]) {
+            match ConfigSource::parse(config_source.as_deref().unwrap_or(DEFAULT_SOURCE)) {
+                Ok(source) => Some(source),
+                Err(e) => std::process::exit(config_error(e.as_ref())),
             }
-            return;
+        } else {
+            None
+        };
+
+        let options = CaptureOptions {
+            merge_into,
+            output,
+        };
+        if let Err(e) = run_capture(&options).await {
+            eprintln!("Error capturing system: {}", e);
+            std::process::exit(ci::exit_code(e.as_ref(), ci::FAILURE));
         }
-    };
+        return;
+    }
 
-    let gist = match gists.find_by_file_name("config.toml") {
-        Some(g) => g,
-        None => {
-            eprintln!("Error: No config.toml found in your gists");
-            eprintln!("Please create a config.toml file in a private gist");
-            eprintln!("See docs/schema.toml for the configuration format");
-            return;
+    if args.len() > 1 && args[1] == "schema" {
+        match config::config_json_schema() {
+            Ok(schema) => println!("{}", schema),
+            Err(e) => {
+                eprintln!("Error generating schema: {}", e);
+                std::process::exit(ci::FAILURE);
+            }
         }
-    };
+        return;
+    }
+
+    if args.len() > 1 && args[1] == "add" {
+        let options = AddOptions {
+            gist_id: take_option(&mut args, "--gist-id"),
+            dry_run: take_flag(&mut args, &["--dry-run"]),
+            assume_yes: take_flag(&mut args, &["--yes", "-y"]),
+        };
+        let item = match parse_add_item(&mut args) {
+            Some(item) => item,
+            None => {
+                eprintln!("Usage: see `spinup --help` for the add subcommands");
+                std::process::exit(ci::FAILURE);
+            }
+        };
+
+        let spec = config_source.unwrap_or_else(|| DEFAULT_SOURCE.to_string());
+        let result = match ConfigSource::parse(&spec) {
+            Ok(source) => run_add(&source, item, &options).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            eprintln!("Error updating config: {}", e);
+            std::process::exit(ci::exit_code(e.as_ref(), ci::FAILURE));
+        }
+        return;
+    }
 
-    let url = &gist.files.iter().next().unwrap().1.raw_url;
+    if !ci::enabled() {
+        helpers::clear_console();
+        helpers::print_banner();
+    }
+    helpers::display_system_info().await;
 
-    match config::fetch_config_from_gist(&url).await {
+    journal::start("run");
+    let mut errors = Vec::new();
+    let mut code = ci::SUCCESS;
+    match load_config(config_source.as_deref()).await {
         Ok(config) => {
-            let os_description = check_current_os_name().await.unwrap();
-            let Some((_, os)) = find_matching_os(&config, &os_description).await else {
-                eprintln!("No matching OS found for: {}", os_description);
-                return;
+            let Some((_, os)) = find_matching_os(&config) else {
+                eprintln!("{}", no_matching_os_error());
+                journal::finish(Some(no_matching_os_error().to_string()));
+                std::process::exit(ci::CONFIG_ERROR);
             };
 
             let apps = helpers::get_all_configured_apps(&config, &os);
-            if let Err(e) = helpers::check_applications(&apps).await {
+            if let Err(e) = helpers::check_applications(&apps, &os).await {
                 eprintln!("Error checking applications: {}", e);
+                code = ci::exit_code(e.as_ref(), ci::PARTIAL_FAILURE);
+                errors.push(e.to_string());
             }
 
             if let Err(e) = helpers::install_applications(&apps, &config).await {
                 eprintln!("Error installing applications: {}", e);
+                code = ci::exit_code(e.as_ref(), ci::PARTIAL_FAILURE);
+                errors.push(e.to_string());
             }
 
-            if let Some(dotfiles) = &os.dotfiles {
-                if let Err(e) = helpers::setup_dotfiles(dotfiles).await {
-                    eprintln!("Error setting up dotfiles: {}", e);
-                }
+            if let Some(dotfiles) = &os.dotfiles
+                && let Err(e) = helpers::setup_dotfiles(dotfiles).await
+            {
+                eprintln!("Error setting up dotfiles: {}", e);
+                code = ci::exit_code(e.as_ref(), ci::PARTIAL_FAILURE);
+                errors.push(e.to_string());
             }
         }
         Err(e) => {
-            eprintln!("Error fetching config: {}", e);
+            code = config_error(e.as_ref());
+            errors.push(e.to_string());
         }
     }
+    journal::finish((!errors.is_empty()).then(|| errors.join("; ")));
+    std::process::exit(code);
 }
 
-async fn load_config() -> Result<Config, Box<dyn std::error::Error>> {
-    let token = auth::authenticate_with_caching().await?;
+async fn load_config(config_source: Option<&str>) -> Result<Config, Box<dyn std::error::Error>> {
+    let source = ConfigSource::parse(config_source.unwrap_or(DEFAULT_SOURCE))?;
+    if config_source.is_some() {
+        eprintln!("{} Loading config from {}", "ℹ".blue(), source);
+    }
+    let content = source.read().await?;
+    journal::set_config(&source.to_string(), &content);
+    let config = config::parse_config(&content)?;
+    profile::check(&config)?;
+    Ok(config)
+}
+
+/// Prints why the config couldn't be loaded and returns the exit code for it.
+fn config_error(error: &(dyn std::error::Error + 'static)) -> i32 {
+    eprintln!("Error loading config: {}", error);
+    ci::exit_code(error, ci::CONFIG_ERROR)
+}
 
-    let gists = helpers::get_gists(&token).await?;
-    let gist = gists.find_by_file_name("config.toml");
+/// Exit code for status and diff: drift only fails the command in CI mode.
+fn drift_code(in_sync: bool) -> i32 {
+    if in_sync || !ci::enabled() {
+        ci::SUCCESS
+    } else {
+        ci::DRIFT
+    }
+}
 
-    match gist {
-        Some(gist) => {
-            let url = &gist.files.iter().next().unwrap().1.raw_url;
-            config::fetch_config_from_gist(url).await
+/// In CI mode, a command that would ask for confirmation fails up front instead.
+fn require_yes(command: &str, assume_yes: bool) {
+    if ci::enabled() && !assume_yes {
+        eprintln!("Error: {} asks for confirmation, which --ci never does: pass --yes", command);
+        std::process::exit(ci::FAILURE);
+    }
+}
+
+/// Removes every occurrence of the given flags from `args` and returns whether any was present.
+fn take_flag(args: &mut Vec<String>, names: &[&str]) -> bool {
+    let before = args.len();
+    args.retain(|a| !names.contains(&a.as_str()));
+    args.len() != before
+}
+
+/// Parses `add <kind> ...` after the shared options have been taken out of `args`.
+fn parse_add_item(args: &mut Vec<String>) -> Option<AddItem> {
+    let depends_on = take_option(args, "--depends");
+    let kind = args.get(2)?.clone();
+    let item = match kind.as_str() {
+        "package" | "packages" if args.len() > 4 => AddItem::Packages {
+            manager: args[3].clone(),
+            packages: args[4..].to_vec(),
+            depends_on,
+        },
+        "task" => {
+            let task = structs::TaskConfig {
+                description: take_option(args, "--description"),
+                check: take_option(args, "--check"),
+                creates: take_option(args, "--creates"),
+                unless: take_option(args, "--unless"),
+                shell: take_option(args, "--shell"),
+                cwd: take_option(args, "--cwd"),
+                when: take_option(args, "--when"),
+                script: args.get(4)?.clone(),
+                depends_on,
+                env: Default::default(),
+            };
+            AddItem::Task {
+                name: args.get(3)?.clone(),
+                task: Box::new(task),
+            }
         }
-        None => Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            "No config.toml found in gists",
-        )) as Box<dyn std::error::Error>),
+        "app" => AddItem::App {
+            name: args.get(3)?.clone(),
+            command: args.get(4)?.clone(),
+            depends_on,
+        },
+        "dotfiles" => AddItem::Dotfiles {
+            repository: take_option(args, "--repo")?,
+            target: take_option(args, "--target"),
+        },
+        _ => return None,
+    };
+    Some(item)
+}
+
+/// Removes `--name <value>` (or `--name=<value>`) from `args` and returns the value.
+/// Exits when the option is given without a value, rather than falling back to a default.
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let prefix = format!("{}=", name);
+    let index = args.iter().position(|a| a == name || a.starts_with(&prefix))?;
+    let arg = args.remove(index);
+    let value = match arg.strip_prefix(&prefix) {
+        Some(value) => value.to_string(),
+        None if index < args.len() && !args[index].starts_with("--") => args.remove(index),
+        None => String::new(),
+    };
+    if value.is_empty() {
+        eprintln!("{} needs a value", name);
+        std::process::exit(ci::FAILURE);
     }
+    Some(value)
 }
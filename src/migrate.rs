@@ -0,0 +1,553 @@
+use crate::backends::KNOWN_MANAGERS;
+use crate::graph::{DependencyGraph, Node};
+use crate::structs::{CommonConfig, Config, Dotfiles, ManagerConfig, OsConfig, Package, TaskConfig};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::io;
+
+/// Flags that only answer prompts, so commands that differ just by these can share a transaction.
+const ASSUME_YES_FLAGS: &[&str] = &["-y", "--yes", "--assumeyes", "--noconfirm", "--non-interactive"];
+
+/// Characters that mean an install command is really a shell script.
+const SHELL_CHARS: &[char] = &['|', '&', ';', '<', '>', '$', '`', '\'', '"', '\\', '(', ')', '{', '}', '*', '\n'];
+
+/// A v7 config converted from an older format, plus everything that couldn't be translated as-is.
+pub struct Migration {
+    pub from: &'static str,
+    pub config: Config,
+    pub report: Vec<String>,
+}
+
+/// An `applications` or `dependencies` entry of a v6 (or v2 JSON) OS section.
+struct LegacyEntry {
+    name: String,
+    install: String,
+    depends: Vec<String>,
+}
+
+struct LegacyOs {
+    key: String,
+    description: Option<String>,
+    // `dependencies` first: v6 installed them before any application
+    entries: Vec<LegacyEntry>,
+    dotfiles: Option<Dotfiles>,
+}
+
+/// Converts a v2 JSON (`$schema`) or v6 TOML config into v7.
+pub fn migrate_config(content: &str) -> Result<Migration, Box<dyn Error>> {
+    let mut report = Vec::new();
+
+    let (from, common, sections) = if content.trim_start().starts_with('{') {
+        let (common, sections) = read_v2_json(content, &mut report)?;
+        ("v2 JSON", common, sections)
+    } else {
+        let (common, sections) = read_v6_toml(content, &mut report)?;
+        ("v6 TOML", common, sections)
+    };
+
+    let mut os_entries = HashMap::new();
+    for legacy in sections {
+        let key = legacy.key.clone();
+        if os_entries.contains_key(&key) {
+            report.push(format!("[{}]: a second OS section maps to this key and was skipped", key));
+            continue;
+        }
+        let os_config = convert_os(legacy, &mut report);
+        os_entries.insert(key, os_config);
+    }
+
+    Ok(Migration {
+        from,
+        config: Config {
+            version: 7,
+            common,
+            vars: HashMap::new(),
+            profiles: HashMap::new(),
+            hosts: HashMap::new(),
+            os_entries,
+        },
+        report,
+    })
+}
+
+fn read_v6_toml(
+    content: &str,
+    report: &mut Vec<String>,
+) -> Result<(Option<CommonConfig>, Vec<LegacyOs>), Box<dyn Error>> {
+    let root: toml::Table = content
+        .parse()
+        .map_err(|e| io::Error::other(format!("Invalid TOML: {}", e)))?;
+
+    if let Some(version) = root.get("version").and_then(toml::Value::as_integer)
+        && version >= 7
+    {
+        return Err(Box::new(io::Error::other(format!(
+            "Config is already version {}, nothing to migrate",
+            version
+        ))));
+    }
+
+    let mut common = None;
+    let mut sections = Vec::new();
+
+    for (key, value) in &root {
+        match (key.as_str(), value) {
+            ("version", _) => {}
+            ("common", value) => match value.clone().try_into::<CommonConfig>() {
+                Ok(parsed) => common = Some(parsed),
+                Err(e) => report.push(format!("[common]: not translated ({})", e.message().trim())),
+            },
+            (_, toml::Value::Table(table)) => sections.push(read_v6_os(key, table, report)),
+            _ => report.push(format!("`{}`: unknown top-level key, dropped", key)),
+        }
+    }
+
+    Ok((common, sections))
+}
+
+fn read_v6_os(key: &str, table: &toml::Table, report: &mut Vec<String>) -> LegacyOs {
+    let mut legacy = LegacyOs {
+        key: key.to_string(),
+        description: None,
+        entries: Vec::new(),
+        dotfiles: None,
+    };
+
+    let mut dependencies = Vec::new();
+    let mut applications = Vec::new();
+
+    for (field, value) in table {
+        let path = format!("{}.{}", key, field);
+        match field.as_str() {
+            "description" => legacy.description = value.as_str().map(str::to_string),
+            "applications" | "dependencies" => {
+                let Some(entries) = value.as_table() else {
+                    report.push(format!("[{}]: expected a table, dropped", path));
+                    continue;
+                };
+                for (name, entry) in entries {
+                    let Some(entry) = read_v6_entry(name, entry) else {
+                        report.push(format!("[{}] {}: no install command, dropped", path, name));
+                        continue;
+                    };
+                    if field == "dependencies" {
+                        dependencies.push(entry);
+                    } else {
+                        applications.push(entry);
+                    }
+                }
+            }
+            "dotfiles" => match value.clone().try_into::<Dotfiles>() {
+                Ok(dotfiles) => legacy.dotfiles = Some(dotfiles),
+                Err(e) => report.push(format!("[{}]: not translated ({})", path, e.message().trim())),
+            },
+            other => report.push(format!("[{}] `{}`: unknown key, dropped", key, other)),
+        }
+    }
+
+    dependencies.sort_by(|a, b| a.name.cmp(&b.name));
+    applications.sort_by(|a, b| a.name.cmp(&b.name));
+    legacy.entries = dependencies.into_iter().chain(applications).collect();
+    legacy
+}
+
+/// v6 entries are either `name = "command"` or `[os.applications.name]` with `install` and `depends`.
+fn read_v6_entry(name: &str, value: &toml::Value) -> Option<LegacyEntry> {
+    let (install, depends) = match value {
+        toml::Value::String(install) => (install.clone(), Vec::new()),
+        toml::Value::Table(table) => {
+            let install = table.get("install")?.as_str()?.to_string();
+            let depends = match table.get("depends").or_else(|| table.get("dependencies")) {
+                Some(toml::Value::String(dep)) => vec![dep.clone()],
+                Some(toml::Value::Array(deps)) => {
+                    deps.iter().filter_map(|d| d.as_str().map(str::to_string)).collect()
+                }
+                _ => Vec::new(),
+            };
+            (install, depends)
+        }
+        _ => return None,
+    };
+
+    Some(LegacyEntry {
+        name: name.to_string(),
+        install,
+        depends,
+    })
+}
+
+fn read_v2_json(
+    content: &str,
+    report: &mut Vec<String>,
+) -> Result<(Option<CommonConfig>, Vec<LegacyOs>), Box<dyn Error>> {
+    let root: serde_json::Value = serde_json::from_str(content)
+        .map_err(|e| io::Error::other(format!("Invalid JSON: {}", e)))?;
+
+    let Some(os_list) = root.get("os").and_then(|os| os.as_array()) else {
+        return Err(Box::new(io::Error::other(
+            "JSON config has no \"os\" array, is it a spinup v2 config?",
+        )));
+    };
+
+    let mut sections = Vec::new();
+    for (index, os) in os_list.iter().enumerate() {
+        let Some(name) = os.get("name").and_then(|n| n.as_str()) else {
+            report.push(format!("os[{}]: no \"name\", dropped", index));
+            continue;
+        };
+
+        let mut legacy = LegacyOs {
+            key: os_key_for(name),
+            description: Some(name.to_string()),
+            entries: Vec::new(),
+            dotfiles: None,
+        };
+
+        for field in ["dependencies", "applications"] {
+            let Some(entries) = os.get(field).and_then(|e| e.as_array()) else {
+                continue;
+            };
+            for entry in entries {
+                match read_v2_entry(entry) {
+                    Some(entry) => legacy.entries.push(entry),
+                    None => report.push(format!(
+                        "{} {}: entry without \"name\" and \"install\", dropped",
+                        name, field
+                    )),
+                }
+            }
+        }
+
+        if let Some(dotfiles) = os.get("dotfiles") {
+            match serde_json::from_value::<Dotfiles>(dotfiles.clone()) {
+                Ok(dotfiles) => legacy.dotfiles = Some(dotfiles),
+                Err(e) => report.push(format!("{} dotfiles: not translated ({})", name, e)),
+            }
+        }
+
+        sections.push(legacy);
+    }
+
+    Ok((None, sections))
+}
+
+fn read_v2_entry(value: &serde_json::Value) -> Option<LegacyEntry> {
+    let name = value.get("name")?.as_str()?.to_string();
+    let install = value.get("install")?.as_str()?.to_string();
+    let depends = value
+        .get("dependencies")
+        .or_else(|| value.get("depends"))
+        .and_then(|d| d.as_array())
+        .map(|deps| deps.iter().filter_map(|d| d.as_str().map(str::to_string)).collect())
+        .unwrap_or_default();
+
+    Some(LegacyEntry {
+        name,
+        install,
+        depends,
+    })
+}
+
+/// "Fedora Linux" -> "fedora", "macOS Sonoma" -> "macos"
+fn os_key_for(name: &str) -> String {
+    let lower = name.to_lowercase();
+    if lower.contains("mac") || lower.contains("darwin") {
+        return "macos".to_string();
+    }
+    lower
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .find(|word| !word.is_empty())
+        .unwrap_or("linux")
+        .to_string()
+}
+
+/// Splits a plain `[sudo] <manager> install [flags] <packages>` command into its parts.
+/// Returns `None` for anything that needs a shell or uses an unknown manager.
+pub fn parse_install_command(command: &str) -> Option<(String, Vec<String>, Vec<String>)> {
+    if command.contains(SHELL_CHARS) {
+        return None;
+    }
+
+    let mut words = command.split_whitespace().peekable();
+    if words.peek() == Some(&"sudo") {
+        words.next();
+    }
+
+    let manager = words.next()?;
+    // `flatpak install <remote> <app>` can't be told apart from two packages
+    if !KNOWN_MANAGERS.contains(&manager) || manager == "flatpak" {
+        return None;
+    }
+
+    let subcommand = if manager == "pacman" { "-S" } else { "install" };
+    if words.next()? != subcommand {
+        return None;
+    }
+
+    let (flags, packages): (Vec<String>, Vec<String>) = words
+        .filter(|word| !(manager == "pacman" && *word == "--needed"))
+        .map(str::to_string)
+        .partition(|word| word.starts_with('-'));
+
+    if packages.is_empty() {
+        return None;
+    }
+    Some((manager.to_string(), flags, packages))
+}
+
+fn convert_os(legacy: LegacyOs, report: &mut Vec<String>) -> OsConfig {
+    let key = legacy.key;
+    let mut manager: HashMap<String, ManagerConfig> = HashMap::new();
+    let mut tasks: HashMap<String, TaskConfig> = HashMap::new();
+    // Legacy entry name -> the v7 node that now installs it
+    let mut nodes: HashMap<String, Node> = HashMap::new();
+
+    for entry in &legacy.entries {
+        if nodes.contains_key(&entry.name) {
+            report.push(format!(
+                "[{}] {}: defined twice, kept the first definition",
+                key, entry.name
+            ));
+            continue;
+        }
+
+        let node = match parse_install_command(&entry.install) {
+            Some((manager_name, flags, packages)) => {
+                match add_packages(&mut manager, &manager_name, flags, packages) {
+                    Ok(()) => Node::Manager(manager_name),
+                    Err(reason) => {
+                        report.push(format!("[{}] {}: kept as a task, {}", key, entry.name, reason));
+                        add_task(&mut tasks, entry)
+                    }
+                }
+            }
+            None => add_task(&mut tasks, entry),
+        };
+        nodes.insert(entry.name.clone(), node);
+    }
+
+    // Each v7 node has a single depends_on, so merge what its legacy entries needed
+    let mut dependencies: BTreeMap<Node, Vec<Node>> = BTreeMap::new();
+    for entry in &legacy.entries {
+        let Some(owner) = nodes.get(&entry.name) else {
+            continue;
+        };
+        for dependency in &entry.depends {
+            let Some(target) = nodes.get(dependency) else {
+                report.push(format!(
+                    "[{}] {}: depends on '{}', which isn't defined, dropped",
+                    key, entry.name, dependency
+                ));
+                continue;
+            };
+            let targets = dependencies.entry(owner.clone()).or_default();
+            if target != owner && !targets.contains(target) {
+                targets.push(target.clone());
+            }
+        }
+    }
+
+    for (node, targets) in dependencies {
+        let Some(first) = targets.first() else {
+            continue;
+        };
+        if targets.len() > 1 {
+            let dropped: Vec<String> = targets[1..].iter().map(|t| t.to_string()).collect();
+            report.push(format!(
+                "[{}] {}: v7 allows one depends_on, kept {} and dropped {}",
+                key,
+                node,
+                first,
+                dropped.join(", ")
+            ));
+        }
+        let depends_on = depends_on_value(first, &manager, &tasks);
+        match &node {
+            Node::Manager(name) => manager.get_mut(name).unwrap().depends_on = Some(depends_on),
+            Node::Task(name) => tasks.get_mut(name).unwrap().depends_on = Some(depends_on),
+        }
+    }
+
+    // v6 ran every dependency first; keep that for bootstrap tasks named after their manager
+    for (name, config) in manager.iter_mut() {
+        if config.depends_on.is_none() && tasks.contains_key(name) {
+            config.depends_on = Some(format!("task:{}", name));
+        }
+    }
+
+    let os_config = OsConfig {
+        description: legacy.description,
+        manager,
+        tasks,
+        dotfiles: legacy.dotfiles,
+    };
+
+    if let Err(e) = DependencyGraph::build(&os_config).and_then(|graph| graph.order()) {
+        report.push(format!("[{}]: {}, fix depends_on before applying", key, e));
+    }
+
+    os_config
+}
+
+fn add_packages(
+    managers: &mut HashMap<String, ManagerConfig>,
+    manager_name: &str,
+    flags: Vec<String>,
+    packages: Vec<String>,
+) -> Result<(), String> {
+    let Some(existing) = managers.get_mut(manager_name) else {
+        managers.insert(
+            manager_name.to_string(),
+            ManagerConfig {
+                packages: packages.into_iter().map(Package::from).collect(),
+                flags,
+                depends_on: None,
+            },
+        );
+        return Ok(());
+    };
+
+    // Flags apply to the whole transaction, so only prompt answers may differ
+    if significant_flags(&flags) != significant_flags(&existing.flags) {
+        let shown = if flags.is_empty() { "none".to_string() } else { flags.join(" ") };
+        return Err(format!(
+            "its flags ({}) differ from the other {} commands",
+            shown, manager_name
+        ));
+    }
+
+    for flag in flags {
+        if !existing.flags.contains(&flag) {
+            existing.flags.push(flag);
+        }
+    }
+    for package in packages {
+        if !existing.has_package(&package) {
+            existing.packages.push(package.into());
+        }
+    }
+    Ok(())
+}
+
+fn significant_flags(flags: &[String]) -> BTreeSet<&str> {
+    flags
+        .iter()
+        .map(String::as_str)
+        .filter(|flag| !ASSUME_YES_FLAGS.contains(flag))
+        .collect()
+}
+
+fn add_task(tasks: &mut HashMap<String, TaskConfig>, entry: &LegacyEntry) -> Node {
+    tasks.insert(
+        entry.name.clone(),
+        TaskConfig {
+            script: entry.install.clone(),
+            description: None,
+            depends_on: None,
+            check: None,
+            creates: None,
+            unless: None,
+            shell: None,
+            env: HashMap::new(),
+            cwd: None,
+            when: None,
+        },
+    );
+    Node::Task(entry.name.clone())
+}
+
+/// Writes a dependency so that `resolve_dependency` picks the same node back.
+fn depends_on_value(
+    target: &Node,
+    managers: &HashMap<String, ManagerConfig>,
+    tasks: &HashMap<String, TaskConfig>,
+) -> String {
+    match target {
+        Node::Task(name) if managers.contains_key(name) => format!("task:{}", name),
+        Node::Manager(name) if tasks.contains_key(name) => format!("manager:{}", name),
+        Node::Task(name) | Node::Manager(name) => name.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_install_command() {
+        assert_eq!(
+            parse_install_command("sudo dnf install -y git"),
+            Some(("dnf".to_string(), vec!["-y".to_string()], vec!["git".to_string()]))
+        );
+        assert_eq!(
+            parse_install_command("sudo pacman -S --needed --noconfirm git"),
+            Some(("pacman".to_string(), vec!["--noconfirm".to_string()], vec!["git".to_string()]))
+        );
+        assert_eq!(parse_install_command("curl -fsSL https://x | sh"), None);
+        assert_eq!(parse_install_command("cargo install ripgrep"), None);
+        assert_eq!(parse_install_command("sudo dnf install -y"), None);
+    }
+
+    #[test]
+    fn test_migrate_v6() {
+        let content = r#"
+version = 6
+
+[fedora]
+description = "Fedora"
+
+[fedora.applications]
+git = "sudo dnf install -y git"
+neovim = "sudo dnf install neovim"
+
+[fedora.applications.k9s]
+install = "brew install derailed/k9s/k9s"
+depends = ["brew"]
+
+[fedora.dependencies]
+brew = "/bin/bash -c \"$(curl -fsSL https://example.com/install.sh)\""
+zsh = "sudo dnf install -y zsh"
+
+[fedora.dotfiles]
+repository = "https://github.com/user/dotfiles.git"
+target = "dotfiles"
+"#;
+
+        let migration = migrate_config(content).unwrap();
+        let fedora = &migration.config.os_entries["fedora"];
+
+        let dnf = &fedora.manager["dnf"];
+        assert_eq!(dnf.packages, vec!["zsh", "git", "neovim"]);
+        assert_eq!(dnf.flags, vec!["-y"]);
+
+        // The brew manager waits for the brew bootstrap task
+        assert_eq!(fedora.manager["brew"].depends_on.as_deref(), Some("task:brew"));
+        assert!(fedora.tasks["brew"].script.contains("install.sh"));
+        assert_eq!(fedora.dotfiles.as_ref().unwrap().target_directory.as_deref(), Some("dotfiles"));
+        assert!(migration.report.is_empty(), "{:?}", migration.report);
+    }
+
+    #[test]
+    fn test_migrate_v2_json_reports_conflicts() {
+        let content = r#"{
+  "$schema": "./src/schema.json",
+  "version": 2,
+  "os": [
+    {
+      "name": "macOS",
+      "applications": [
+        { "name": "git", "install": "brew install git" },
+        { "name": "firacode", "install": "brew install --cask font-fira-code", "dependencies": ["missing"] }
+      ]
+    }
+  ]
+}"#;
+
+        let migration = migrate_config(content).unwrap();
+        let macos = &migration.config.os_entries["macos"];
+
+        assert_eq!(macos.manager["brew"].packages, vec!["git"]);
+        assert!(macos.tasks.contains_key("firacode"));
+        assert_eq!(migration.report.len(), 2);
+    }
+}
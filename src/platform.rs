@@ -0,0 +1,309 @@
+use std::io;
+use std::path::Path;
+use std::sync::OnceLock;
+use sysinfo::System;
+
+/// Searched in order, as described in os-release(5).
+const OS_RELEASE_PATHS: &[&str] = &["/etc/os-release", "/usr/lib/os-release"];
+
+/// Last resort section for any Linux distribution, e.g. [linux]
+const GENERIC_LINUX_KEY: &str = "linux";
+
+static OS_OVERRIDE: OnceLock<String> = OnceLock::new();
+static CURRENT: OnceLock<Platform> = OnceLock::new();
+
+/// The fields of /etc/os-release spinup cares about.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OsRelease {
+    pub id: Option<String>,
+    pub id_like: Vec<String>,
+    pub version_id: Option<String>,
+    pub name: Option<String>,
+    pub pretty_name: Option<String>,
+}
+
+impl OsRelease {
+    /// Parses the shell-style `KEY=value` format, with optional quotes around values.
+    pub fn parse(content: &str) -> Self {
+        let mut release = OsRelease::default();
+
+        for line in content.lines() {
+            let line = line.trim();
+            if line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim().trim_matches(|c| c == '"' || c == '\'').to_string();
+            if value.is_empty() {
+                continue;
+            }
+
+            match key.trim() {
+                "ID" => release.id = Some(value.to_lowercase()),
+                "ID_LIKE" => release.id_like = value.split_whitespace().map(str::to_lowercase).collect(),
+                "VERSION_ID" => release.version_id = Some(value),
+                "NAME" => release.name = Some(value),
+                "PRETTY_NAME" => release.pretty_name = Some(value),
+                _ => {}
+            }
+        }
+
+        release
+    }
+
+    pub fn read(path: &Path) -> io::Result<Self> {
+        Ok(Self::parse(&std::fs::read_to_string(path)?))
+    }
+}
+
+/// Splits a versioned section key such as `ubuntu-24.04` into `("ubuntu", "24.04")`.
+pub fn split_version(os_key: &str) -> Option<(&str, &str)> {
+    let (base, version) = os_key.rsplit_once('-')?;
+    let versioned = !base.is_empty()
+        && version.starts_with(|c: char| c.is_ascii_digit())
+        && version.chars().all(|c| c.is_ascii_digit() || c == '.');
+    versioned.then_some((base, version))
+}
+
+/// `rocky` at `9.3` gives rocky-9.3, rocky-9, rocky
+pub fn versioned_keys(id: &str, version: Option<&str>) -> Vec<String> {
+    let mut keys = Vec::new();
+    if let Some(version) = version {
+        keys.push(format!("{}-{}", id, version));
+        if let Some((major, _)) = version.split_once('.') {
+            keys.push(format!("{}-{}", id, major));
+        }
+    }
+    keys.push(id.to_string());
+    keys
+}
+
+/// The running OS and the config sections that may describe it.
+#[derive(Debug, Clone)]
+pub struct Platform {
+    pub description: String,
+    pub hostname: Option<String>,
+    /// VERSION_ID, e.g. 41 or 24.04
+    pub version_id: Option<String>,
+    /// Config section keys to try, most specific first, e.g.
+    /// pop-22.04 -> pop -> ubuntu -> debian -> linux
+    pub candidates: Vec<String>,
+    /// Set by `--os`; disables matching sections by description
+    pub overridden: bool,
+}
+
+impl Platform {
+    pub fn from_os_release(release: &OsRelease) -> Self {
+        let mut candidates = match &release.id {
+            Some(id) => versioned_keys(id, release.version_id.as_deref()),
+            None => Vec::new(),
+        };
+        candidates.extend(release.id_like.iter().cloned());
+        candidates.push(GENERIC_LINUX_KEY.to_string());
+        candidates.dedup();
+
+        let description = release
+            .pretty_name
+            .clone()
+            .or_else(|| {
+                let name = release.name.as_ref()?;
+                Some(match &release.version_id {
+                    Some(version) => format!("{} {}", name, version),
+                    None => name.clone(),
+                })
+            })
+            .unwrap_or_else(|| "Linux".to_string());
+
+        Platform {
+            description,
+            hostname: System::host_name(),
+            version_id: release.version_id.clone(),
+            candidates,
+            overridden: false,
+        }
+    }
+
+    /// Only `os_key` (and its unversioned base) is tried; used for `--os`.
+    pub fn with_override(self, os_key: &str) -> Self {
+        let candidates = match split_version(os_key) {
+            Some((id, version)) => versioned_keys(id, Some(version)),
+            None => vec![os_key.to_string()],
+        };
+        Platform {
+            candidates,
+            overridden: true,
+            ..self
+        }
+    }
+
+    /// Section to create when the config has none for this machine: the `--os` key as
+    /// given, else the unversioned ID (`fedora` rather than `fedora-41`).
+    pub fn section_key(&self) -> &str {
+        if self.overridden {
+            return &self.candidates[0];
+        }
+        self.candidates
+            .iter()
+            .find(|key| split_version(key).is_none())
+            .unwrap_or(&self.candidates[0])
+    }
+
+    pub fn is_like(&self, os_key: &str) -> bool {
+        self.candidates.iter().any(|candidate| candidate == os_key)
+    }
+
+    /// Whether a [hosts.*] key names this machine, exactly or as a glob like `build-*`.
+    /// Hostnames are compared case-insensitively, and `nas` matches `nas.local`.
+    pub fn host_matches(&self, pattern: &str) -> bool {
+        let Some(hostname) = &self.hostname else { return false };
+        let hostname = hostname.to_lowercase();
+        let short = hostname.split('.').next().unwrap_or(&hostname);
+        let pattern = pattern.to_lowercase();
+        glob_match(&pattern, &hostname) || glob_match(&pattern, short)
+    }
+}
+
+/// `*` matches any run of characters and `?` exactly one.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position after the last `*` and the text position it is currently matched up to
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_t)) => {
+                    backtrack = Some((star_p, star_t + 1));
+                    p = star_p;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Detects the OS. On Linux, `os_release` (or `$SPINUP_OS_RELEASE`, then the standard
+/// locations) is read; macOS is recognized at compile time.
+pub fn detect(os_release: Option<&Path>) -> Platform {
+    let long_os_version = System::long_os_version();
+
+    if cfg!(target_os = "macos") {
+        return Platform {
+            description: long_os_version.unwrap_or_else(|| "macOS".to_string()),
+            hostname: System::host_name(),
+            version_id: System::os_version(),
+            candidates: versioned_keys("macos", System::os_version().as_deref()),
+            overridden: false,
+        };
+    }
+
+    let env_path = std::env::var("SPINUP_OS_RELEASE").ok();
+    let paths: Vec<&Path> = match (os_release, &env_path) {
+        (Some(path), _) => vec![path],
+        (None, Some(path)) => vec![Path::new(path)],
+        (None, None) => OS_RELEASE_PATHS.iter().map(Path::new).collect(),
+    };
+
+    match paths.iter().find_map(|path| OsRelease::read(path).ok()) {
+        Some(release) => Platform::from_os_release(&release),
+        None => Platform {
+            description: long_os_version.unwrap_or_else(|| "Unknown OS".to_string()),
+            hostname: System::host_name(),
+            version_id: None,
+            candidates: vec![GENERIC_LINUX_KEY.to_string()],
+            overridden: false,
+        },
+    }
+}
+
+/// Forces the config section to use, from `--os`. Must be called before `current()`.
+pub fn set_override(os_key: &str) {
+    let _ = OS_OVERRIDE.set(os_key.to_string());
+}
+
+/// The platform of this machine, detected once per run.
+pub fn current() -> &'static Platform {
+    CURRENT.get_or_init(|| {
+        let platform = detect(None);
+        match OS_OVERRIDE.get() {
+            Some(os_key) => platform.with_override(os_key),
+            None => platform,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fallback_chain_from_id_like() {
+        let pop = OsRelease::parse(
+            r#"NAME="Pop!_OS"
+VERSION_ID="22.04"
+ID=pop
+ID_LIKE="ubuntu debian"
+PRETTY_NAME="Pop!_OS 22.04 LTS"
+"#,
+        );
+        let platform = Platform::from_os_release(&pop);
+
+        assert_eq!(
+            platform.candidates,
+            vec!["pop-22.04", "pop-22", "pop", "ubuntu", "debian", "linux"]
+        );
+        assert_eq!(platform.description, "Pop!_OS 22.04 LTS");
+        assert_eq!(platform.section_key(), "pop");
+
+        let overridden = platform.clone().with_override("fedora");
+        assert_eq!(overridden.candidates, vec!["fedora"]);
+        let overridden = platform.with_override("fedora-41");
+        assert_eq!(overridden.candidates, vec!["fedora-41", "fedora"]);
+        assert_eq!(overridden.section_key(), "fedora-41");
+    }
+
+    #[test]
+    fn test_detect_reads_given_path() {
+        let path = std::env::temp_dir().join(format!("spinup-os-release-{}", std::process::id()));
+        std::fs::write(&path, "ID=arch\nNAME='Arch Linux'\n# comment\nBUILD_ID=rolling\n").unwrap();
+
+        let platform = detect(Some(&path));
+        let _ = std::fs::remove_file(&path);
+
+        if !cfg!(target_os = "macos") {
+            assert_eq!(platform.candidates, vec!["arch", "linux"]);
+            assert_eq!(platform.description, "Arch Linux");
+        }
+    }
+
+    #[test]
+    fn test_host_patterns() {
+        let platform = Platform {
+            hostname: Some("Build-07.lab.example.com".to_string()),
+            ..Platform::from_os_release(&OsRelease::default())
+        };
+
+        assert!(platform.host_matches("build-*"));
+        assert!(platform.host_matches("build-0?"));
+        assert!(platform.host_matches("*.example.com"));
+        assert!(platform.host_matches("build-07"));
+        assert!(!platform.host_matches("build-1*"));
+        assert!(!platform.host_matches("nas"));
+        assert!(glob_match("a*b*c", "axxbyyc"));
+        assert!(!glob_match("a*b", "ab-c"));
+    }
+}
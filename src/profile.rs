@@ -0,0 +1,62 @@
+use crate::structs::{Config, ManagedState};
+use colored::Colorize;
+use std::error::Error;
+use std::sync::OnceLock;
+
+static PROFILE_OVERRIDE: OnceLock<String> = OnceLock::new();
+static SELECTED: OnceLock<Option<String>> = OnceLock::new();
+
+/// Uses `name` instead of the saved profile, from `--profile`. Must be called before `selected()`.
+pub fn set_override(name: &str) {
+    let _ = PROFILE_OVERRIDE.set(name.to_string());
+}
+
+/// The profile to apply: `--profile`, else the one saved with `spinup profile use`.
+pub fn selected() -> Option<&'static str> {
+    SELECTED
+        .get_or_init(|| match PROFILE_OVERRIDE.get() {
+            Some(name) => Some(name.clone()),
+            None => saved(),
+        })
+        .as_deref()
+}
+
+/// The profile saved for this machine, if any. A broken state file only warns.
+pub fn saved() -> Option<String> {
+    match ManagedState::load() {
+        Ok(state) => state.profile,
+        Err(e) => {
+            eprintln!("{} {}, ignoring it", "⚠".yellow(), e);
+            None
+        }
+    }
+}
+
+/// Fails when the selected profile isn't defined in `config`.
+pub fn check(config: &Config) -> Result<(), Box<dyn Error>> {
+    let Some(name) = selected() else { return Ok(()) };
+    if config.profiles.contains_key(name) {
+        return Ok(());
+    }
+
+    let mut available: Vec<&str> = config.profiles.keys().map(String::as_str).collect();
+    available.sort();
+    let hint = if PROFILE_OVERRIDE.get().is_some() {
+        "check --profile"
+    } else {
+        "it was saved with `spinup profile use`; run `spinup profile clear` to drop it"
+    };
+    Err(Box::new(std::io::Error::other(format!(
+        "Unknown profile `{}` (available: {}), {}",
+        name,
+        if available.is_empty() { "none".to_string() } else { available.join(", ") },
+        hint
+    ))))
+}
+
+/// Remembers `name` (or nothing) as this machine's profile in ~/.spinup/state.json.
+pub fn save(name: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let mut state = ManagedState::load()?;
+    state.profile = name.map(str::to_string);
+    state.save()
+}
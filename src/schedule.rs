@@ -0,0 +1,289 @@
+use crate::backends::{backend_for, KNOWN_MANAGERS, SYSTEM_LOCK};
+use crate::graph::{DependencyGraph, Node};
+use crate::structs::OsConfig;
+use futures::stream::{FuturesUnordered, StreamExt};
+use std::collections::{BTreeSet, HashSet};
+use std::error::Error;
+use std::future::Future;
+use std::sync::OnceLock;
+
+static JOBS: OnceLock<usize> = OnceLock::new();
+
+/// Installs wait on the network and package databases more than on the CPU, so the
+/// default doesn't depend on the core count.
+const DEFAULT_JOBS: usize = 4;
+
+/// Limits how many installs and probes run at once, from `--jobs`. Must be called before `jobs()`.
+pub fn set_jobs(jobs: usize) {
+    let _ = JOBS.set(jobs.max(1));
+}
+
+/// How many installs and probes may run at once: `--jobs`, else `DEFAULT_JOBS`.
+pub fn jobs() -> usize {
+    *JOBS.get_or_init(|| DEFAULT_JOBS)
+}
+
+/// Locks a node holds while it runs; nodes sharing a lock never run at the same time.
+/// A manager holds its backend's lock. A task holds the locks of the managers its
+/// script calls, and the system lock when it uses sudo, so it can't race apt or dnf
+/// for the package database or ask for a password while another sudo is prompting.
+/// Anything that may prompt (a manager without its noninteractive flag, e.g. flatpak
+/// without `-y`) holds the system lock as well, so prompts never share the terminal.
+pub fn locks(node: &Node, os_config: &OsConfig) -> BTreeSet<&'static str> {
+    match node {
+        Node::Manager(name) => {
+            let flags = os_config.manager.get(name).map(|manager| manager.flags.as_slice()).unwrap_or_default();
+            manager_locks(name, flags)
+        }
+        Node::Task(name) => os_config
+            .tasks
+            .get(name)
+            .map(|task| script_locks(&task.script))
+            .unwrap_or_default(),
+    }
+}
+
+fn manager_locks<S: AsRef<str>>(manager: &str, flags: &[S]) -> BTreeSet<&'static str> {
+    let backend = backend_for(manager);
+    let quiet = flags.iter().any(|flag| backend.noninteractive_flags().contains(&flag.as_ref()));
+    let mut locks = BTreeSet::from([backend.lock()]);
+    if !quiet {
+        locks.insert(SYSTEM_LOCK);
+    }
+    locks
+}
+
+fn script_locks(script: &str) -> BTreeSet<&'static str> {
+    let words: Vec<&str> = script
+        .split(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+        .collect();
+    words
+        .iter()
+        .flat_map(|word| match *word {
+            "sudo" | "doas" | "pkexec" => BTreeSet::from([SYSTEM_LOCK]),
+            // The script's own flags are anywhere among its words
+            manager if KNOWN_MANAGERS.contains(&manager) => manager_locks(manager, &words),
+            _ => BTreeSet::new(),
+        })
+        .collect()
+}
+
+/// Runs `nodes`, given in dependency order, with up to `jobs` at a time. A node starts
+/// once the nodes it depends on have finished and none of its locks is held; with one
+/// job this is exactly the given order.
+///
+/// After a failure nothing new starts, the running nodes finish and the first error
+/// is returned. With `keep_going`, everything that doesn't depend on a failed node
+/// still runs, and the error lists every failure and what was skipped because of it.
+pub async fn run_nodes<'a, F, Fut>(
+    nodes: &'a [Node],
+    graph: &DependencyGraph,
+    os_config: &OsConfig,
+    jobs: usize,
+    keep_going: bool,
+    mut run: F,
+) -> Result<(), Box<dyn Error>>
+where
+    F: FnMut(&'a Node) -> Fut,
+    Fut: Future<Output = Result<(), Box<dyn Error>>>,
+{
+    let waits_for: Vec<HashSet<&Node>> = nodes.iter().map(|node| dependencies_in(graph, node, nodes)).collect();
+    let node_locks: Vec<BTreeSet<&str>> = nodes.iter().map(|node| locks(node, os_config)).collect();
+
+    let mut started = vec![false; nodes.len()];
+    let mut finished: HashSet<&Node> = HashSet::new();
+    let mut held: BTreeSet<&str> = BTreeSet::new();
+    let mut running = FuturesUnordered::new();
+    let mut failures: Vec<(&Node, Box<dyn Error>)> = Vec::new();
+
+    loop {
+        if failures.is_empty() || keep_going {
+            for (i, node) in nodes.iter().enumerate() {
+                if running.len() >= jobs.max(1) {
+                    break;
+                }
+                let ready = !started[i]
+                    && waits_for[i].iter().all(|dep| finished.contains(dep))
+                    && node_locks[i].is_disjoint(&held);
+                if ready {
+                    started[i] = true;
+                    held.extend(node_locks[i].iter().copied());
+                    let future = run(node);
+                    running.push(async move { (i, future.await) });
+                }
+            }
+        }
+
+        let Some((i, result)) = running.next().await else { break };
+        for lock in &node_locks[i] {
+            held.remove(lock);
+        }
+        match result {
+            Ok(()) => {
+                finished.insert(&nodes[i]);
+            }
+            Err(e) => failures.push((&nodes[i], e)),
+        }
+    }
+
+    let skipped: Vec<String> = nodes
+        .iter()
+        .zip(&started)
+        .filter(|(_, started)| !**started)
+        .map(|(node, _)| node.to_string())
+        .collect();
+    if !keep_going || failures.len() <= 1 && skipped.is_empty() {
+        return match failures.into_iter().next() {
+            Some((_, e)) => Err(e),
+            None => Ok(()),
+        };
+    }
+
+    let mut summary = format!("{} of {} steps failed:", failures.len(), nodes.len());
+    for (node, e) in &failures {
+        summary.push_str(&format!("\n  {}: {}", node, e));
+    }
+    if !skipped.is_empty() {
+        summary.push_str(&format!("\nSkipped because a dependency failed: {}", skipped.join(", ")));
+    }
+    Err(Box::new(std::io::Error::other(summary)))
+}
+
+/// The nodes of `nodes` that `node` waits for, looking through dependencies that have
+/// nothing to do in this run (e.g. a task that's already done).
+fn dependencies_in<'a>(graph: &DependencyGraph, node: &Node, nodes: &'a [Node]) -> HashSet<&'a Node> {
+    let mut found = HashSet::new();
+    let mut seen = HashSet::new();
+    let mut stack: Vec<&Node> = graph.dependencies(node).iter().collect();
+    while let Some(dep) = stack.pop() {
+        if !seen.insert(dep) {
+            continue;
+        }
+        match nodes.iter().find(|n| *n == dep) {
+            Some(pending) => {
+                found.insert(pending);
+            }
+            None => stack.extend(graph.dependencies(dep)),
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::parse_config;
+    use std::cell::RefCell;
+
+    #[tokio::test]
+    async fn test_independent_nodes_overlap_but_locks_serialize() {
+        let config = parse_config(
+            r#"
+version = 7
+
+[fedora.manager.dnf]
+packages = ["git"]
+
+[fedora.manager.flatpak]
+packages = ["org.mozilla.firefox"]
+flags = ["-y"]
+
+[fedora.tasks.rust]
+script = "curl -sSf https://sh.rustup.rs | sh -s -- -y"
+
+[fedora.tasks.kubectl]
+script = "sudo dnf install -y kubectl"
+
+[fedora.tasks.cargo-tools]
+script = "cargo install ripgrep"
+depends_on = "rust"
+"#,
+        )
+        .unwrap();
+        let os_config = &config.os_entries["fedora"];
+        let graph = DependencyGraph::build(os_config).unwrap();
+        let order = graph.order().unwrap();
+
+        let log = RefCell::new(Vec::new());
+        run_nodes(&order, &graph, os_config, 4, false, |node| {
+            let log = &log;
+            async move {
+                log.borrow_mut().push(format!("start {}", node));
+                tokio::task::yield_now().await;
+                log.borrow_mut().push(format!("end {}", node));
+                Ok(())
+            }
+        })
+        .await
+        .unwrap();
+
+        let log = log.into_inner();
+        let position = |entry: &str| log.iter().position(|e| e == entry).unwrap();
+        // dnf, flatpak and rust start together; kubectl waits for dnf's lock,
+        // cargo-tools for rust
+        assert!(position("start manager:flatpak") < position("end manager:dnf"));
+        assert!(position("start task:rust") < position("end manager:dnf"));
+        assert!(position("start task:kubectl") > position("end manager:dnf"));
+        assert!(position("start task:cargo-tools") > position("end task:rust"));
+    }
+
+    #[test]
+    fn test_steps_that_may_prompt_hold_the_system_lock() {
+        let config = parse_config(
+            r#"
+version = 7
+
+[fedora.manager.flatpak]
+packages = ["org.mozilla.firefox"]
+
+[fedora.manager.brew]
+packages = ["k9s"]
+
+[fedora.tasks.gimp]
+script = "flatpak install flathub org.gimp.GIMP"
+
+[fedora.tasks.obs]
+script = "flatpak install -y flathub com.obsproject.Studio"
+"#,
+        )
+        .unwrap();
+        let os_config = &config.os_entries["fedora"];
+        let locks_of = |node: Node| locks(&node, os_config).into_iter().collect::<Vec<_>>();
+
+        assert_eq!(locks_of(Node::Manager("flatpak".to_string())), vec!["flatpak", SYSTEM_LOCK]);
+        assert_eq!(locks_of(Node::Manager("brew".to_string())), vec!["brew", SYSTEM_LOCK]);
+        assert_eq!(locks_of(Node::Task("gimp".to_string())), vec!["flatpak", SYSTEM_LOCK]);
+        assert_eq!(locks_of(Node::Task("obs".to_string())), vec!["flatpak"]);
+        assert_eq!(manager_locks("flatpak", &["--noninteractive"]), BTreeSet::from(["flatpak"]));
+        assert_eq!(manager_locks("apt", &["-y"]), BTreeSet::from([SYSTEM_LOCK]));
+    }
+
+    #[tokio::test]
+    async fn test_keep_going_skips_only_dependents_of_failures() {
+        let config = parse_config(
+            "version = 7\n[fedora.tasks.flaky]\nscript = \"false\"\n[fedora.tasks.after]\nscript = \"true\"\ndepends_on = \"flaky\"\n[fedora.tasks.other]\nscript = \"true\"\n",
+        )
+        .unwrap();
+        let os_config = &config.os_entries["fedora"];
+        let graph = DependencyGraph::build(os_config).unwrap();
+        let order = graph.order().unwrap();
+
+        let ran = RefCell::new(Vec::new());
+        let result = run_nodes(&order, &graph, os_config, 1, true, |node| {
+            let ran = &ran;
+            async move {
+                ran.borrow_mut().push(node.to_string());
+                match node {
+                    Node::Task(name) if name == "flaky" => Err("exit 1".into()),
+                    _ => Ok(()),
+                }
+            }
+        })
+        .await;
+
+        assert_eq!(ran.into_inner(), vec!["task:flaky", "task:other"]);
+        let message = result.unwrap_err().to_string();
+        assert!(message.contains("task:flaky: exit 1"));
+        assert!(message.contains("Skipped because a dependency failed: task:after"));
+    }
+}
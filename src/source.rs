@@ -0,0 +1,292 @@
+use crate::auth::{self, AuthError};
+use crate::config;
+use crate::crypto::EncryptionAPI;
+use crate::helpers;
+use crate::structs::{Config, Gist};
+use colored::Colorize;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::process::Command;
+
+pub const DEFAULT_SOURCE: &str = "gist:config.toml";
+
+/// Where a config is loaded from, selected by a URI-like argument:
+///
+/// - `gist:<id>` or `gist:<filename>` - a gist by ID, or the first gist containing that file
+/// - `file:<path>` or a bare path - a local file
+/// - `https://...` - a plain HTTP(S) URL
+/// - `git+<repository>#<ref>:<path>` - a file inside a git repository at a ref (ref defaults to HEAD)
+/// - `-` or `stdin:` - standard input
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigSource {
+    Gist(String),
+    File(PathBuf),
+    Url(String),
+    Git {
+        repository: String,
+        reference: String,
+        path: String,
+    },
+    Stdin,
+}
+
+impl ConfigSource {
+    pub fn parse(spec: &str) -> Result<Self, Box<dyn Error>> {
+        let spec = spec.trim();
+
+        if spec.is_empty() {
+            return Err(Box::new(io::Error::other("Empty config source")));
+        }
+
+        if spec == "-" || spec == "stdin:" {
+            return Ok(ConfigSource::Stdin);
+        }
+
+        if let Some(gist) = spec.strip_prefix("gist:") {
+            if gist.is_empty() {
+                return Err(Box::new(io::Error::other(
+                    "Gist source needs an ID or file name, e.g. gist:config.toml",
+                )));
+            }
+            return Ok(ConfigSource::Gist(gist.to_string()));
+        }
+
+        if let Some(path) = spec.strip_prefix("file:") {
+            let path = path.strip_prefix("//").unwrap_or(path);
+            return Ok(ConfigSource::File(PathBuf::from(path)));
+        }
+
+        if let Some(rest) = spec.strip_prefix("git+") {
+            let Some((repository, fragment)) = rest.rsplit_once('#') else {
+                return Err(Box::new(io::Error::other(format!(
+                    "Git source must name a file: git+<repository>#<ref>:<path>, got '{}'",
+                    spec
+                ))));
+            };
+            let (reference, path) = match fragment.split_once(':') {
+                Some((reference, path)) if !reference.is_empty() => (reference, path),
+                Some((_, path)) => ("HEAD", path),
+                None => ("HEAD", fragment),
+            };
+            if repository.is_empty() || path.is_empty() {
+                return Err(Box::new(io::Error::other(format!(
+                    "Git source must name a repository and a file: git+<repository>#<ref>:<path>, got '{}'",
+                    spec
+                ))));
+            }
+            return Ok(ConfigSource::Git {
+                repository: repository.to_string(),
+                reference: reference.to_string(),
+                path: path.to_string(),
+            });
+        }
+
+        if spec.starts_with("https://") || spec.starts_with("http://") {
+            return Ok(ConfigSource::Url(spec.to_string()));
+        }
+
+        Ok(ConfigSource::File(PathBuf::from(spec)))
+    }
+
+    /// Fetches the raw config text without parsing it.
+    pub async fn read(&self) -> Result<String, Box<dyn Error>> {
+        match self {
+            ConfigSource::Gist(gist) => read_from_gist(gist).await,
+            ConfigSource::File(path) => std::fs::read_to_string(path).map_err(|e| {
+                Box::new(io::Error::new(
+                    e.kind(),
+                    format!("Failed to read {}: {}", path.display(), e),
+                )) as Box<dyn Error>
+            }),
+            ConfigSource::Url(url) => {
+                let response = reqwest::get(url).await?.error_for_status()?;
+                Ok(response.text().await?)
+            }
+            ConfigSource::Git {
+                repository,
+                reference,
+                path,
+            } => read_from_git(repository, reference, path),
+            ConfigSource::Stdin => {
+                let mut content = String::new();
+                io::stdin().read_to_string(&mut content)?;
+                Ok(content)
+            }
+        }
+    }
+
+    pub async fn load(&self) -> Result<Config, Box<dyn Error>> {
+        let content = self.read().await?;
+        config::parse_config(&content)
+    }
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigSource::Gist(gist) => write!(f, "gist:{}", gist),
+            ConfigSource::File(path) => write!(f, "{}", path.display()),
+            ConfigSource::Url(url) => write!(f, "{}", url),
+            ConfigSource::Git {
+                repository,
+                reference,
+                path,
+            } => write!(f, "git+{}#{}:{}", repository, reference, path),
+            ConfigSource::Stdin => write!(f, "stdin"),
+        }
+    }
+}
+
+/// Gist IDs are hex strings; anything else is treated as a file name to search for.
+fn is_gist_id(gist: &str) -> bool {
+    gist.len() >= 20 && gist.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// The gist and file a `gist:` source resolves to, so edits can be written back.
+pub struct GistLocation {
+    pub id: String,
+    pub file_name: String,
+    raw_url: String,
+}
+
+pub async fn locate_gist(token: &str, gist: &str) -> Result<GistLocation, Box<dyn Error>> {
+    if is_gist_id(gist) {
+        let found = fetch_with_token_check(helpers::get_gist(token, gist).await)?;
+        config_file(&found, "config.toml").ok_or_else(|| {
+            Box::new(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Gist {} has no files", gist),
+            )) as Box<dyn Error>
+        })
+    } else {
+        let gists = fetch_with_token_check(helpers::get_gists(token).await)?;
+        let found = gists.find_by_file_name(gist).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "No {} found in gists. Create a {} file in a private gist or pass --config <source>",
+                    gist, gist
+                ),
+            )
+        })?;
+        Ok(config_file(found, gist).unwrap())
+    }
+}
+
+async fn read_from_gist(gist: &str) -> Result<String, Box<dyn Error>> {
+    let token = auth::authenticate_with_caching().await?;
+    let location = locate_gist(&token, gist).await?;
+
+    let response = reqwest::get(&location.raw_url).await?.error_for_status()?;
+    Ok(response.text().await?)
+}
+
+/// Prefers `file_name` when the gist holds several files, otherwise takes the first one.
+fn config_file(gist: &Gist, file_name: &str) -> Option<GistLocation> {
+    gist.files
+        .get_key_value(file_name)
+        .or_else(|| gist.files.iter().next())
+        .map(|(name, file)| GistLocation {
+            id: gist.id.clone(),
+            file_name: name.clone(),
+            raw_url: file.raw_url.clone(),
+        })
+}
+
+fn fetch_with_token_check<T>(result: Result<T, Box<dyn Error>>) -> Result<T, Box<dyn Error>> {
+    if let Err(e) = &result
+        && e.to_string().contains("401 Unauthorized")
+    {
+        eprintln!(
+            "{} Clearing cached credentials. Please run spinup again to re-authenticate.",
+            "ℹ".blue()
+        );
+        let mut crypto = EncryptionAPI::new();
+        if let Err(c_err) = crypto.clear_cached_token() {
+            eprintln!("Failed to clear token cache: {}", c_err);
+        }
+        return result.map_err(|e| Box::new(AuthError(e.to_string())) as Box<dyn Error>);
+    }
+    result
+}
+
+fn read_from_git(repository: &str, reference: &str, path: &str) -> Result<String, Box<dyn Error>> {
+    // A fresh directory only this process can use, removed when dropped
+    let work_dir = tempfile::Builder::new().prefix("spinup-config-").tempdir()?;
+    fetch_git_file(work_dir.path(), repository, reference, path)
+}
+
+fn fetch_git_file(
+    work_dir: &std::path::Path,
+    repository: &str,
+    reference: &str,
+    path: &str,
+) -> Result<String, Box<dyn Error>> {
+    run_git(work_dir, &["init", "-q"])?;
+    run_git(work_dir, &["fetch", "-q", "--depth", "1", repository, reference])?;
+    let content = run_git(work_dir, &["show", &format!("FETCH_HEAD:{}", path)])?;
+    Ok(content)
+}
+
+fn run_git(work_dir: &std::path::Path, args: &[&str]) -> Result<String, Box<dyn Error>> {
+    let output = Command::new("git").arg("-C").arg(work_dir).args(args).output()?;
+
+    if output.status.success() {
+        Ok(String::from_utf8(output.stdout)?)
+    } else {
+        Err(Box::new(io::Error::other(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sources() {
+        assert_eq!(
+            ConfigSource::parse("gist:config.toml").unwrap(),
+            ConfigSource::Gist("config.toml".to_string())
+        );
+        assert_eq!(
+            ConfigSource::parse("./config.toml").unwrap(),
+            ConfigSource::File(PathBuf::from("./config.toml"))
+        );
+        assert_eq!(
+            ConfigSource::parse("file:///etc/spinup.toml").unwrap(),
+            ConfigSource::File(PathBuf::from("/etc/spinup.toml"))
+        );
+        assert_eq!(
+            ConfigSource::parse("https://example.com/config.toml").unwrap(),
+            ConfigSource::Url("https://example.com/config.toml".to_string())
+        );
+        assert_eq!(ConfigSource::parse("-").unwrap(), ConfigSource::Stdin);
+    }
+
+    #[test]
+    fn test_parse_git_source() {
+        assert_eq!(
+            ConfigSource::parse("git+https://github.com/org/configs.git#v2:spinup/config.toml").unwrap(),
+            ConfigSource::Git {
+                repository: "https://github.com/org/configs.git".to_string(),
+                reference: "v2".to_string(),
+                path: "spinup/config.toml".to_string(),
+            }
+        );
+        assert_eq!(
+            ConfigSource::parse("git+git@github.com:org/configs.git#config.toml").unwrap(),
+            ConfigSource::Git {
+                repository: "git@github.com:org/configs.git".to_string(),
+                reference: "HEAD".to_string(),
+                path: "config.toml".to_string(),
+            }
+        );
+        assert!(ConfigSource::parse("git+https://github.com/org/configs.git").is_err());
+    }
+}
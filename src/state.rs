@@ -0,0 +1,171 @@
+use crate::structs::ManagedState;
+use colored::Colorize;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const STATE_DIR: &str = ".spinup";
+const STATE_FILE: &str = "state.json";
+
+/// `~/.spinup`, shared with the token cache.
+#[cfg(not(test))]
+pub fn state_dir() -> PathBuf {
+    let home = std::env::var("HOME")
+        .unwrap_or_else(|_| std::env::current_dir().unwrap().display().to_string());
+    PathBuf::from(home).join(STATE_DIR)
+}
+
+#[cfg(test)]
+static STATE_DIR_OVERRIDE: std::sync::Mutex<Option<PathBuf>> = std::sync::Mutex::new(None);
+
+/// Tests never see the real `~/.spinup`: they get the `TestStateDir` they hold, else
+/// a directory of their own that nothing creates.
+#[cfg(test)]
+pub fn state_dir() -> PathBuf {
+    STATE_DIR_OVERRIDE
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+        .unwrap_or_else(|| std::env::temp_dir().join(format!("spinup-test{}-{}", STATE_DIR, std::process::id())))
+}
+
+/// Keeps state, journals and backups in an empty temporary directory until dropped.
+/// That state is global, so tests that write it hold this to run one at a time.
+#[cfg(test)]
+pub(crate) struct TestStateDir {
+    dir: PathBuf,
+    _lock: std::sync::MutexGuard<'static, ()>,
+}
+
+#[cfg(test)]
+impl TestStateDir {
+    pub(crate) fn new(name: &str) -> Self {
+        static LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+        let lock = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = std::env::temp_dir().join(format!("spinup-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        *STATE_DIR_OVERRIDE.lock().unwrap_or_else(|e| e.into_inner()) = Some(dir.clone());
+        crate::journal::reset();
+        TestStateDir { dir, _lock: lock }
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestStateDir {
+    fn drop(&mut self) {
+        crate::journal::reset();
+        STATE_DIR_OVERRIDE.lock().unwrap_or_else(|e| e.into_inner()).take();
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+impl ManagedState {
+    pub fn load() -> Result<Self, Box<dyn Error>> {
+        Self::load_from(&state_dir().join(STATE_FILE))
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self, Box<dyn Error>> {
+        if !path.exists() {
+            return Ok(ManagedState::default());
+        }
+        let content = fs::read_to_string(path)?;
+        serde_json::from_str(&content).map_err(|e| {
+            Box::new(std::io::Error::other(format!(
+                "Corrupt state file {}: {}",
+                path.display(),
+                e
+            ))) as Box<dyn Error>
+        })
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        self.save_to(&state_dir().join(STATE_FILE))
+    }
+
+    /// Writes to a temporary file first so an interrupted run never leaves half a state file.
+    pub fn save_to(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    pub fn record_installed(&mut self, manager: &str, packages: &[String]) {
+        self.packages
+            .entry(manager.to_string())
+            .or_default()
+            .extend(packages.iter().cloned());
+    }
+
+    pub fn forget(&mut self, manager: &str, packages: &[String]) {
+        if let Some(managed) = self.packages.get_mut(manager) {
+            for package in packages {
+                managed.remove(package);
+            }
+            if managed.is_empty() {
+                self.packages.remove(manager);
+            }
+        }
+    }
+}
+
+/// Remembers packages spinup just installed. Failing to write state only warns:
+/// it must never turn a successful install into an error.
+pub fn record_installed(manager: &str, packages: &[String]) {
+    update(|state| state.record_installed(manager, packages));
+}
+
+pub fn forget_removed(manager: &str, packages: &[String]) {
+    update(|state| state.forget(manager, packages));
+}
+
+fn update(change: impl FnOnce(&mut ManagedState)) {
+    let result = ManagedState::load().and_then(|mut state| {
+        change(&mut state);
+        state.save()
+    });
+    if let Err(e) = result {
+        eprintln!("{} Could not update {}: {}", "⚠".yellow(), state_dir().join(STATE_FILE).display(), e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_round_trip() {
+        let path = std::env::temp_dir().join(format!("spinup-state-{}/state.json", std::process::id()));
+        let packages = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+
+        let mut state = ManagedState::load_from(&path).unwrap();
+        assert!(state.packages.is_empty());
+
+        state.record_installed("dnf", &packages(&["git", "tmux"]));
+        state.record_installed("brew", &packages(&["k9s"]));
+        state.forget("brew", &packages(&["k9s"]));
+        state.save_to(&path).unwrap();
+
+        let loaded = ManagedState::load_from(&path).unwrap();
+        assert_eq!(loaded.packages.len(), 1);
+        assert!(loaded.packages["dnf"].contains("tmux"));
+
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn test_tests_never_touch_the_real_state() {
+        let real = std::env::var("HOME").map(|home| PathBuf::from(home).join(STATE_DIR));
+        assert_ne!(Ok(state_dir()), real);
+
+        let held = TestStateDir::new("state-override");
+        crate::profile::save(Some("server")).unwrap();
+        assert_eq!(ManagedState::load().unwrap().profile.as_deref(), Some("server"));
+        assert!(state_dir().join(STATE_FILE).exists());
+        drop(held);
+        assert!(ManagedState::load().unwrap().profile.is_none());
+    }
+}
@@ -1,5 +1,7 @@
+use crate::graph::Node;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 // ----------------------------------------------------------------------------
 // GIST & GITHUB API STRUCTS (Unchanged)
@@ -61,7 +63,6 @@ pub struct GistFile {
 
 #[derive(Debug, Deserialize)]
 pub struct Gist {
-    #[allow(dead_code)]
     pub id: String,
     #[allow(dead_code)]
     pub node_id: String,
@@ -114,74 +115,412 @@ impl GistList {
 // NEW CONFIGURATION SCHEMA (v7)
 // ----------------------------------------------------------------------------
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// SpinUp configuration (v7).
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 pub struct Config {
+    /// Configuration format version, currently 7
     pub version: u8,
 
-    // The [common] section for cross-platform tools
+    /// The [common] section for cross-platform tools
     #[serde(default)]
     pub common: Option<CommonConfig>,
 
+    /// User variables for `{{name}}` placeholders in task scripts and dotfiles paths
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub vars: HashMap<String, String>,
+
+    /// Named overlays such as [profiles.server], selected with --profile
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub profiles: HashMap<String, Profile>,
+
+    /// Overlays for machines by hostname or glob, e.g. [hosts."build-*"], applied last
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub hosts: HashMap<String, Profile>,
+
     // Captures [fedora], [macos], etc.
     #[serde(flatten)]
     pub os_entries: HashMap<String, OsConfig>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 pub struct CommonConfig {
+    /// Tools expected on every OS, checked with `which`; entries can carry a `when`
+    /// condition
     #[serde(default)]
-    pub packages: Vec<String>,
+    pub packages: Vec<Package>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+impl CommonConfig {
+    /// Drops packages whose `when` condition doesn't hold.
+    pub fn retain_applicable(&mut self, holds: impl Fn(&str) -> bool) {
+        self.packages.retain(|package| package.when().is_none_or(&holds));
+    }
+}
+
+/// An OS section such as [fedora] or [macos].
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 pub struct OsConfig {
+    /// Human-readable description, also used to match the running OS
     pub description: Option<String>,
 
     // Replaces the old "applications" mixed bag.
     // Now separated into batchable managers (dnf/brew) and custom tasks.
-    #[serde(default)]
+    /// Packages to install per package manager, e.g. [fedora.manager.dnf]
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
     pub manager: HashMap<String, ManagerConfig>,
 
-    #[serde(default)]
+    /// Scripts for anything a package manager can't install, e.g. [fedora.tasks.kubectl]
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
     pub tasks: HashMap<String, TaskConfig>,
 
+    /// Dotfiles repository applied with GNU stow
     pub dotfiles: Option<Dotfiles>,
 }
 
-// Represents [os.manager.dnf] or [os.manager.brew]
-#[derive(Serialize, Deserialize, Debug, Clone)]
+impl OsConfig {
+    /// Layers a more specific section (e.g. [fedora-41]) over this one: packages are
+    /// added to the same manager and dotfiles are merged, while tasks and set fields
+    /// are replaced.
+    pub fn layer(&mut self, over: &OsConfig) {
+        if over.description.is_some() {
+            self.description = over.description.clone();
+        }
+
+        for (name, manager) in &over.manager {
+            let Some(base) = self.manager.get_mut(name) else {
+                self.manager.insert(name.clone(), manager.clone());
+                continue;
+            };
+            for package in &manager.packages {
+                if !base.has_package(package.name()) {
+                    base.packages.push(package.clone());
+                }
+            }
+            if !manager.flags.is_empty() {
+                base.flags = manager.flags.clone();
+            }
+            if manager.depends_on.is_some() {
+                base.depends_on = manager.depends_on.clone();
+            }
+        }
+
+        for (name, task) in &over.tasks {
+            self.tasks.insert(name.clone(), task.clone());
+        }
+
+        match (&mut self.dotfiles, &over.dotfiles) {
+            (Some(dotfiles), Some(over)) => dotfiles.layer(over),
+            (None, Some(over)) => self.dotfiles = Some(over.clone()),
+            _ => {}
+        }
+    }
+
+    /// Applies a profile (or host overlay). A manager this section doesn't declare is
+    /// added when the profile gives it packages; `when` conditions keep those to the
+    /// machines that have the manager.
+    pub fn apply_profile(&mut self, profile: &Profile) {
+        for (name, changes) in &profile.manager {
+            match self.manager.get_mut(name) {
+                Some(manager) => changes.apply_to(&mut manager.packages),
+                None if !changes.packages.is_empty() => {
+                    let mut manager = ManagerConfig::default();
+                    changes.apply_to(&mut manager.packages);
+                    self.manager.insert(name.clone(), manager);
+                }
+                None => {}
+            }
+        }
+
+        for name in &profile.remove_tasks {
+            self.tasks.remove(name);
+        }
+        for (name, task) in &profile.tasks {
+            self.tasks.insert(name.clone(), task.clone());
+        }
+
+        if let (Some(dotfiles), Some(changes)) = (&mut self.dotfiles, &profile.dotfiles) {
+            match &mut dotfiles.packages {
+                Some(packages) => changes.apply_to(packages),
+                // Auto-discovered: nothing to add to, but removals still apply
+                None => dotfiles.exclude.extend(changes.remove.iter().cloned()),
+            }
+        }
+    }
+
+    /// Drops packages, tasks and dotfiles packages whose `when` condition doesn't hold.
+    /// Whatever depended on a dropped task no longer waits for it.
+    pub fn retain_applicable(&mut self, holds: impl Fn(&str) -> bool) {
+        let applies = |when: Option<&str>| when.is_none_or(&holds);
+
+        for manager in self.manager.values_mut() {
+            manager.packages.retain(|package| applies(package.when()));
+        }
+
+        let skipped: Vec<String> = self
+            .tasks
+            .iter()
+            .filter(|(_, task)| !applies(task.when.as_deref()))
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in &skipped {
+            self.tasks.remove(name);
+        }
+        let managers: Vec<String> = self.manager.keys().cloned().collect();
+        let on_skipped = |depends_on: &Option<String>| {
+            depends_on.as_deref().is_some_and(|target| {
+                skipped.iter().any(|name| {
+                    target.strip_prefix("task:") == Some(name.as_str())
+                        || (target == name && !managers.contains(name))
+                })
+            })
+        };
+        for manager in self.manager.values_mut() {
+            if on_skipped(&manager.depends_on) {
+                manager.depends_on = None;
+            }
+        }
+        for task in self.tasks.values_mut() {
+            if on_skipped(&task.depends_on) {
+                task.depends_on = None;
+            }
+        }
+
+        if let Some(packages) = self.dotfiles.as_mut().and_then(|dotfiles| dotfiles.packages.as_mut()) {
+            packages.retain(|package| applies(package.when()));
+        }
+    }
+
+    /// Runs `expand` over the fields that take `{{name}}` placeholders: task scripts,
+    /// guards and working directories, and the dotfiles repository and target.
+    pub fn interpolate(&mut self, expand: impl Fn(&str) -> String) {
+        for task in self.tasks.values_mut() {
+            task.script = expand(&task.script);
+            for value in [&mut task.check, &mut task.creates, &mut task.unless, &mut task.cwd].into_iter().flatten() {
+                *value = expand(value);
+            }
+        }
+        if let Some(dotfiles) = self.dotfiles.as_mut() {
+            dotfiles.repository = expand(&dotfiles.repository);
+            if let Some(target) = dotfiles.target_directory.as_mut() {
+                *target = expand(target);
+            }
+        }
+    }
+}
+
+impl ProfileChanges {
+    fn apply_to(&self, packages: &mut Vec<Package>) {
+        packages.retain(|package| !self.remove.iter().any(|name| name == package.name()));
+        for package in &self.packages {
+            if !packages.iter().any(|p| p.name() == package.name()) {
+                packages.push(package.clone());
+            }
+        }
+    }
+}
+
+/// A package name, or a table with a `when` condition:
+/// `"git"` or `{ name = "nvidia-driver", when = "arch == x86_64 && display" }`
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Package {
+    Name(String),
+    Conditional { name: String, when: String },
+}
+
+impl Package {
+    pub fn name(&self) -> &str {
+        match self {
+            Package::Name(name) | Package::Conditional { name, .. } => name,
+        }
+    }
+
+    pub fn when(&self) -> Option<&str> {
+        match self {
+            Package::Name(_) => None,
+            Package::Conditional { when, .. } => Some(when),
+        }
+    }
+}
+
+impl PartialEq<&str> for Package {
+    fn eq(&self, other: &&str) -> bool {
+        self.name() == *other
+    }
+}
+
+impl From<&str> for Package {
+    fn from(name: &str) -> Self {
+        Package::Name(name.to_string())
+    }
+}
+
+impl From<String> for Package {
+    fn from(name: String) -> Self {
+        Package::Name(name)
+    }
+}
+
+/// Names of `packages`, conditions left out
+pub fn package_names(packages: &[Package]) -> Vec<String> {
+    packages.iter().map(|package| package.name().to_string()).collect()
+}
+
+/// Represents [os.manager.dnf] or [os.manager.brew]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
 pub struct ManagerConfig {
+    /// Packages installed in one transaction; entries can carry a `when` condition
     #[serde(default)]
-    pub packages: Vec<String>,
+    pub packages: Vec<Package>,
 
-    #[serde(default)]
+    /// Extra flags passed to the install command, e.g. ["-y"]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub flags: Vec<String>,
 
-    // If this manager itself needs setup (e.g. brew on linux), link to a task
+    /// Task or manager that must run first (e.g. the brew bootstrap task on Linux)
     pub depends_on: Option<String>,
 }
 
-// Represents [os.tasks.kubectl] - complex scripts
-#[derive(Serialize, Deserialize, Debug, Clone)]
+impl ManagerConfig {
+    pub fn has_package(&self, name: &str) -> bool {
+        self.packages.iter().any(|package| package.name() == name)
+    }
+
+    pub fn package_names(&self) -> Vec<String> {
+        package_names(&self.packages)
+    }
+}
+
+/// Represents [os.tasks.kubectl] - complex scripts
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 pub struct TaskConfig {
+    /// Script executed by `shell`
     pub script: String,
     pub description: Option<String>,
+
+    /// Task or manager that must run first
     pub depends_on: Option<String>,
+
+    // Idempotency guards. Without any of them the task counts as done when
+    // a binary with the task's name is on $PATH.
+
+    /// Command whose success means the task is done; re-checked after the task runs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub check: Option<String>,
+
+    /// Path that exists once the task is done (`~/` is expanded)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub creates: Option<String>,
+
+    /// Command whose success means the task should be skipped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unless: Option<String>,
+
+    /// Interpreter for `script`: "sh" (default), "bash" (runs with -euo pipefail),
+    /// "zsh", "fish", "python3" and the like get `-c <script>`; any other command line
+    /// gets the script appended, e.g. "pwsh -NoProfile -Command"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shell: Option<String>,
+
+    /// Extra environment variables for the script and its guards
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
+
+    /// Working directory for the script and its guards (`~/` is expanded)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+
+    /// Condition on machine facts, e.g. "display && !wsl"; the task is skipped when false
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub when: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 pub struct Dotfiles {
+    /// Git URL or local path of the dotfiles repository; a versioned section can leave it
+    /// to its base section
+    #[serde(default)]
     pub repository: String,
 
+    /// Stow packages to apply; all top-level directories when omitted. Entries can
+    /// carry a `when` condition
     #[serde(default)]
-    pub packages: Option<Vec<String>>,
+    pub packages: Option<Vec<Package>>,
 
     // 'alias' allows TOML to use "target" while Rust uses "target_directory"
+    /// Checkout directory relative to $HOME (default "dotfiles"); `target` is accepted too
     #[serde(alias = "target")]
     pub target_directory: Option<String>,
 
+    /// Only preview stow changes
     #[serde(alias = "dry-run")]
     pub dry_run: Option<bool>,
+
+    /// Stow packages to skip when `packages` is omitted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+}
+
+impl Dotfiles {
+    /// Merges a versioned section's dotfiles into these: fields it sets win, and its
+    /// `packages` and `exclude` are added. Auto-discovery (no `packages`) already covers
+    /// every package, so it stays on.
+    fn layer(&mut self, over: &Dotfiles) {
+        if !over.repository.is_empty() {
+            self.repository = over.repository.clone();
+        }
+        if over.target_directory.is_some() {
+            self.target_directory = over.target_directory.clone();
+        }
+        if over.dry_run.is_some() {
+            self.dry_run = over.dry_run;
+        }
+        if let (Some(packages), Some(added)) = (&mut self.packages, &over.packages) {
+            for package in added {
+                if !packages.iter().any(|p| p.name() == package.name()) {
+                    packages.push(package.clone());
+                }
+            }
+        }
+        for name in &over.exclude {
+            if !self.exclude.contains(name) {
+                self.exclude.push(name.clone());
+            }
+        }
+    }
+}
+
+/// A profile such as [profiles.server] or a host overlay such as [hosts.nas], applied
+/// on top of the OS section
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct Profile {
+    pub description: Option<String>,
+
+    /// Changes to managers, e.g. [profiles.server.manager.dnf]; a manager the OS section
+    /// doesn't declare is added with the packages given
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub manager: HashMap<String, ProfileChanges>,
+
+    /// Tasks added to (or replacing those of) the OS section
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub tasks: HashMap<String, TaskConfig>,
+
+    /// Tasks of the OS section to drop
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub remove_tasks: Vec<String>,
+
+    /// Changes to the stow packages of the OS section's dotfiles
+    pub dotfiles: Option<ProfileChanges>,
+}
+
+/// Packages a profile adds to and removes from a list
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct ProfileChanges {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub packages: Vec<Package>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub remove: Vec<String>,
 }
 
 // ----------------------------------------------------------------------------
@@ -220,10 +559,16 @@ pub struct TokenResponse {
 #[derive(Debug, Clone, Serialize)]
 pub struct SystemStatus {
     pub os_name: String,
+    // Active profile, if any
+    pub profile: Option<String>,
+    // [hosts.*] sections matching this machine's hostname, in the order applied
+    pub hosts: Vec<String>,
     // Generalized: "dnf: git, neovim"
     pub installed_packages: HashMap<String, Vec<String>>,
     pub missing_packages: HashMap<String, Vec<String>>,
     pub dotfiles_status: DotfilesStatus,
+    // Stow packages linked and still to link, as `spinup diff` shows them
+    pub dotfiles_diff: DotfilesDiff,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -237,13 +582,29 @@ pub struct DotfilesStatus {
 pub struct ConfigDiff {
     pub os_name: String,
 
+    // Key of the [os] section the diff was computed against
+    pub os_key: String,
+
+    // Profile applied on top of the OS section, if any
+    pub profile: Option<String>,
+
+    // [hosts.*] sections applied after the profile, in order
+    pub hosts: Vec<String>,
+
     // Batch install commands to run
     // Key = Manager (dnf), Value = List of packages to install
     pub packages_to_install: HashMap<String, Vec<String>>,
 
-    // Complex tasks that need to run
+    // Complex tasks that need to run, in dependency order
     pub tasks_to_run: Vec<String>,
 
+    // Managers and tasks with pending work, dependencies first
+    pub install_order: Vec<Node>,
+
+    // Packages spinup installed that are no longer in the config and still installed.
+    // Only removed by `spinup prune` / `apply --prune`, and empty for a plain apply.
+    pub packages_to_remove: HashMap<String, Vec<String>>,
+
     pub dotfiles_diff: DotfilesDiff,
 }
 
@@ -253,3 +614,109 @@ pub struct DotfilesDiff {
     pub packages_to_apply: Vec<String>,
     pub packages_already_applied: Vec<String>,
 }
+
+// ----------------------------------------------------------------------------
+// VALIDATION STRUCTS
+// ----------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    // 1-based position in the config file
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+    pub help: Option<String>,
+}
+
+// ----------------------------------------------------------------------------
+// LOCAL STATE STRUCTS
+// ----------------------------------------------------------------------------
+
+/// What spinup has installed on this machine, kept in ~/.spinup/state.json so
+/// prune only ever removes packages spinup put there.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ManagedState {
+    // Manager name -> packages installed through it
+    #[serde(default)]
+    pub packages: BTreeMap<String, BTreeSet<String>>,
+
+    // Profile saved with `spinup profile use`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+}
+
+/// One `apply`, `prune` or `run`, kept in ~/.spinup/runs/<id>.json and listed by
+/// `spinup history`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RunJournal {
+    // Start time, e.g. 20261017-143012 (UTC)
+    pub id: String,
+    pub command: String,
+    pub started_at: String,
+    // None while running, or when spinup was interrupted
+    pub finished_at: Option<String>,
+    pub duration_ms: Option<u64>,
+    pub config_source: Option<String>,
+    // BLAKE2s of the config text
+    pub config_hash: Option<String>,
+    pub os_key: Option<String>,
+    pub profile: Option<String>,
+    pub steps: Vec<JournalStep>,
+    // Managers, tasks and dotfiles (e.g. task:rustup) that finished, for `apply --resume`
+    #[serde(default)]
+    pub completed: Vec<String>,
+    pub error: Option<String>,
+
+    // What `spinup undo` can reverse: manager -> packages this run installed
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub installed: BTreeMap<String, Vec<String>>,
+    // Stow packages this run linked
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stowed: Vec<StowedPackage>,
+    // Files this run moved out of the way of stow
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub backed_up: Vec<BackedUpFile>,
+    // Dotfiles checkout this run cloned
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cloned: Option<String>,
+    // ID of the `spinup undo` run that reversed this one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub undone_by: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StowedPackage {
+    // The dotfiles checkout, i.e. the stow directory
+    pub directory: String,
+    pub package: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BackedUpFile {
+    // Where the file was, e.g. /home/me/.zshrc
+    pub original: String,
+    // Where it is now, under ~/.spinup/backups/<run id>
+    pub backup: String,
+}
+
+/// A command spinup ran: an install or remove transaction, a task, or a dotfiles step.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JournalStep {
+    // e.g. dnf, task:rustup, dotfiles:clone
+    pub name: String,
+    pub command: String,
+    pub started_at: String,
+    pub duration_ms: u64,
+    // None when the command couldn't start or was killed by a signal
+    pub exit_code: Option<i32>,
+    // Last lines of stderr
+    pub stderr_tail: Vec<String>,
+}
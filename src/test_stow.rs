@@ -12,9 +12,10 @@ pub async fn test_dotfiles_setup() {
     
     let test_dotfiles = Dotfiles {
         repository: "/home/rosnovsky/code/dotfiles".to_string(),
-        packages: Some(vec!["alacritty".to_string(), "kitty".to_string()]),
+        packages: Some(vec!["alacritty".into(), "kitty".into()]),
         target_directory: Some("test-dotfiles-spinup".to_string()),
         dry_run: Some(false),
+        exclude: Vec::new(),
     };
     
     match setup_dotfiles(&test_dotfiles).await {
@@ -31,6 +32,7 @@ pub async fn test_auto_discovery() {
         packages: None, // Should auto-discover all packages
         target_directory: Some("test-dotfiles-auto".to_string()),
         dry_run: Some(false),
+        exclude: Vec::new(),
     };
     
     match setup_dotfiles(&test_dotfiles).await {
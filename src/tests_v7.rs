@@ -1,6 +1,6 @@
 #[cfg(test)]
 mod tests {
-    use crate::helpers::get_all_configured_apps;
+    use crate::helpers::{get_all_configured_apps, is_task_done, shell_argv};
     use crate::structs::{CommonConfig, Config, ManagerConfig, OsConfig, TaskConfig};
     use std::collections::HashMap;
 
@@ -10,7 +10,7 @@ mod tests {
         manager.insert(
             "dnf".to_string(),
             ManagerConfig {
-                packages: vec!["git".to_string(), "vim".to_string()],
+                packages: vec!["git".into(), "vim".into()],
                 flags: vec![],
                 depends_on: None,
             },
@@ -23,6 +23,13 @@ mod tests {
                 script: "echo hello".to_string(),
                 description: None,
                 depends_on: None,
+                check: None,
+                creates: None,
+                unless: None,
+                shell: None,
+                env: HashMap::new(),
+                cwd: None,
+                when: None,
             },
         );
 
@@ -34,12 +41,15 @@ mod tests {
         };
 
         let common = CommonConfig {
-            packages: vec!["common_pkg".to_string()],
+            packages: vec!["common_pkg".into()],
         };
 
         let config = Config {
             version: 7,
             common: Some(common),
+            vars: HashMap::new(),
+            profiles: HashMap::new(),
+            hosts: HashMap::new(),
             os_entries: HashMap::new(),
         };
 
@@ -51,4 +61,523 @@ mod tests {
         assert!(apps.contains(&"common_pkg".to_string()));
         assert_eq!(apps.len(), 4);
     }
+
+    #[test]
+    fn test_task_guards() {
+        let task = |check: Option<&str>, creates: Option<&str>, unless: Option<&str>| TaskConfig {
+            script: "true".to_string(),
+            description: None,
+            depends_on: None,
+            check: check.map(|c| c.to_string()),
+            creates: creates.map(|c| c.to_string()),
+            unless: unless.map(|c| c.to_string()),
+            shell: None,
+            env: HashMap::new(),
+            cwd: None,
+            when: None,
+        };
+
+        assert!(is_task_done("fonts", &task(Some("true"), None, None)));
+        assert!(!is_task_done("fonts", &task(Some("false"), None, None)));
+        assert!(is_task_done("fonts", &task(None, Some("/"), None)));
+        assert!(!is_task_done("fonts", &task(None, Some("/nonexistent/spinup"), None)));
+        assert!(is_task_done("fonts", &task(None, Some("/nonexistent/spinup"), Some("test 1 -eq 1"))));
+        // Without guards the task name is looked up on $PATH
+        assert!(is_task_done("sh", &task(None, None, None)));
+        assert!(!is_task_done("custom_script", &task(None, None, None)));
+    }
+
+    #[test]
+    fn test_shell_argv() {
+        assert_eq!(shell_argv(None, "a | b"), vec!["sh", "-c", "a | b"]);
+        assert_eq!(
+            shell_argv(Some("bash"), "echo hi"),
+            vec!["bash", "-euo", "pipefail", "-c", "echo hi"]
+        );
+        assert_eq!(shell_argv(Some("python3"), "print(1)"), vec!["python3", "-c", "print(1)"]);
+        assert_eq!(shell_argv(Some("zsh -l"), "echo hi"), vec!["zsh", "-l", "-c", "echo hi"]);
+        assert_eq!(shell_argv(Some("/bin/fish"), "echo hi"), vec!["/bin/fish", "-c", "echo hi"]);
+        // Anything else is the full command line the script goes after
+        assert_eq!(
+            shell_argv(Some("pwsh -NoProfile -Command"), "Get-Date"),
+            vec!["pwsh", "-NoProfile", "-Command", "Get-Date"]
+        );
+        assert_eq!(shell_argv(Some("node -e"), "console.log(1)"), vec!["node", "-e", "console.log(1)"]);
+    }
+
+    #[test]
+    fn test_json_schema_covers_os_sections() {
+        let schema: serde_json::Value =
+            serde_json::from_str(&crate::config::config_json_schema().unwrap()).unwrap();
+
+        assert_eq!(schema["additionalProperties"]["$ref"], "#/definitions/OsConfig");
+        let os = &schema["definitions"]["OsConfig"]["properties"];
+        assert!(os.get("manager").is_some());
+        assert!(os.get("tasks").is_some());
+        assert!(os.get("applications").is_none());
+        assert!(schema["definitions"]["TaskConfig"]["properties"].get("creates").is_some());
+    }
+
+    #[test]
+    fn test_versioned_section_layers_over_base() {
+        let config = crate::config::parse_config(
+            r#"
+version = 7
+
+[fedora.manager.dnf]
+packages = ["git", "podman"]
+flags = ["-y"]
+
+[fedora.tasks.brew]
+script = "install-brew"
+
+[fedora-41.manager.dnf]
+packages = ["dnf5-plugins"]
+
+[fedora-41.manager.brew]
+packages = ["k9s"]
+depends_on = "task:brew"
+"#,
+        )
+        .unwrap();
+
+        let os = crate::helpers::layered_os_config(&config, "fedora-41").unwrap();
+        assert_eq!(os.manager["dnf"].packages, vec!["git", "podman", "dnf5-plugins"]);
+        assert_eq!(os.manager["dnf"].flags, vec!["-y"]);
+        assert!(os.manager.contains_key("brew"));
+        assert!(os.tasks.contains_key("brew"));
+
+        let base = crate::helpers::layered_os_config(&config, "fedora").unwrap();
+        assert_eq!(base.manager["dnf"].packages, vec!["git", "podman"]);
+        assert!(crate::helpers::layered_os_config(&config, "ubuntu-24.04").is_none());
+    }
+
+    #[test]
+    fn test_versioned_section_merges_dotfiles() {
+        let config = crate::config::parse_config(
+            r#"
+version = 7
+
+[fedora.dotfiles]
+repository = "https://github.com/user/dotfiles.git"
+packages = ["zsh", "git"]
+exclude = ["secrets"]
+
+[fedora-41.dotfiles]
+target = "dots"
+packages = ["git", "dnf5"]
+exclude = ["legacy"]
+"#,
+        )
+        .unwrap();
+
+        let dotfiles = crate::helpers::layered_os_config(&config, "fedora-41").unwrap().dotfiles.unwrap();
+        assert_eq!(dotfiles.repository, "https://github.com/user/dotfiles.git");
+        assert_eq!(dotfiles.target_directory.as_deref(), Some("dots"));
+        assert_eq!(dotfiles.packages.unwrap(), vec!["zsh", "git", "dnf5"]);
+        assert_eq!(dotfiles.exclude, vec!["secrets", "legacy"]);
+
+        let base = crate::helpers::layered_os_config(&config, "fedora").unwrap().dotfiles.unwrap();
+        assert_eq!(base.target_directory, None);
+        assert_eq!(base.packages.unwrap(), vec!["zsh", "git"]);
+    }
+
+    #[test]
+    fn test_profile_adds_and_removes() {
+        let config = crate::config::parse_config(
+            r#"
+version = 7
+
+[fedora.manager.dnf]
+packages = ["git", "firefox"]
+
+[fedora.tasks.gui]
+script = "setup-gui"
+
+[fedora.dotfiles]
+repository = "https://example.com/dotfiles.git"
+
+[profiles.server]
+remove_tasks = ["gui"]
+
+[profiles.server.manager.dnf]
+packages = ["tmux"]
+remove = ["firefox"]
+
+[profiles.server.manager.brew]
+packages = ["k9s"]
+
+[profiles.server.manager.flatpak]
+remove = ["org.gimp.GIMP"]
+
+[profiles.server.dotfiles]
+remove = ["alacritty"]
+"#,
+        )
+        .unwrap();
+
+        let mut os = config.os_entries["fedora"].clone();
+        os.apply_profile(&config.profiles["server"]);
+        assert_eq!(os.manager["dnf"].packages, vec!["git", "tmux"]);
+        // Managers the OS section doesn't declare are added with the profile's packages
+        assert_eq!(os.manager["brew"].packages, vec!["k9s"]);
+        assert!(!os.manager.contains_key("flatpak"));
+        assert!(os.tasks.is_empty());
+        assert_eq!(os.dotfiles.unwrap().exclude, vec!["alacritty"]);
+    }
+
+    #[test]
+    fn test_when_applies_to_common_packages() {
+        let config = crate::config::parse_config(
+            r#"
+version = 7
+
+[common]
+packages = ["git", { name = "nvidia-smi", when = "env.SPINUP_TEST_UNSET_GPU" }]
+
+[fedora.manager.dnf]
+packages = ["tmux"]
+"#,
+        )
+        .unwrap();
+
+        let apps = get_all_configured_apps(&config, &config.os_entries["fedora"]);
+        assert!(apps.contains(&"git".to_string()));
+        assert!(!apps.contains(&"nvidia-smi".to_string()));
+
+        let mut common = config.common.clone().unwrap();
+        common.retain_applicable(|_| true);
+        assert_eq!(common.packages, vec!["git", "nvidia-smi"]);
+    }
+
+    #[test]
+    fn test_when_drops_entries_and_dependencies() {
+        let config = crate::config::parse_config(
+            r#"
+version = 7
+
+[fedora.manager.dnf]
+packages = ["git", { name = "nvidia-driver", when = "env.GPU == nvidia" }]
+
+[fedora.tasks.drivers]
+script = "setup-nvidia"
+when = "env.GPU == nvidia"
+
+[fedora.tasks.desktop]
+script = "setup-desktop"
+depends_on = "task:drivers"
+"#,
+        )
+        .unwrap();
+
+        let facts = crate::condition::Facts::default();
+        let mut os = config.os_entries["fedora"].clone();
+        os.retain_applicable(|when| crate::condition::evaluate(when, &facts).unwrap());
+        assert_eq!(os.manager["dnf"].packages, vec!["git"]);
+        assert!(!os.tasks.contains_key("drivers"));
+        assert_eq!(os.tasks["desktop"].depends_on, None);
+    }
+
+    #[test]
+    fn test_stowed_packages_are_not_reapplied() {
+        use crate::helpers::dotfiles_diff;
+        use crate::structs::Dotfiles;
+        use std::os::unix::fs::symlink;
+
+        let home = std::env::temp_dir().join(format!("spinup-stow-{}", std::process::id()));
+        let checkout = home.join("dotfiles");
+        for (file, content) in [("zsh/.zshrc", "zsh"), ("git/.gitconfig", "git"), ("nvim/.config/nvim/init.lua", "nvim")] {
+            std::fs::create_dir_all(checkout.join(file).parent().unwrap()).unwrap();
+            std::fs::write(checkout.join(file), content).unwrap();
+        }
+        std::fs::write(checkout.join("zsh/README.md"), "ignored by stow").unwrap();
+        symlink("dotfiles/zsh/.zshrc", home.join(".zshrc")).unwrap();
+        // Folded: stow links the whole directory when nothing else is in it
+        std::fs::create_dir_all(home.join(".config")).unwrap();
+        symlink("../dotfiles/nvim/.config/nvim", home.join(".config/nvim")).unwrap();
+        // A plain file in the way isn't a link into the checkout
+        std::fs::write(home.join(".gitconfig"), "local").unwrap();
+
+        let dotfiles = Dotfiles {
+            repository: "https://example.com/dotfiles.git".to_string(),
+            packages: None,
+            target_directory: None,
+            dry_run: None,
+            exclude: vec![],
+        };
+        let mut diff = dotfiles_diff(&dotfiles, &home);
+        diff.packages_already_applied.sort();
+        assert!(!diff.needs_clone);
+        assert_eq!(diff.packages_to_apply, vec!["git"]);
+        assert_eq!(diff.packages_already_applied, vec!["nvim", "zsh"]);
+
+        let missing = dotfiles_diff(&Dotfiles { target_directory: Some("elsewhere".to_string()), ..dotfiles }, &home);
+        assert!(missing.needs_clone);
+        assert!(missing.packages_already_applied.is_empty());
+
+        let _ = std::fs::remove_dir_all(&home);
+    }
+
+    #[test]
+    fn test_status_and_diff_agree_on_sync() {
+        use crate::commands::diff::is_diff_empty;
+        use crate::helpers::{dotfiles_diff, is_in_sync};
+        use crate::structs::Dotfiles;
+        use std::os::unix::fs::symlink;
+
+        let home = std::env::temp_dir().join(format!("spinup-sync-{}", std::process::id()));
+        std::fs::create_dir_all(home.join("dotfiles/zsh")).unwrap();
+        std::fs::write(home.join("dotfiles/zsh/.zshrc"), "zsh").unwrap();
+        let dotfiles = Dotfiles {
+            repository: "https://example.com/dotfiles.git".to_string(),
+            packages: None,
+            target_directory: None,
+            dry_run: None,
+            exclude: vec![],
+        };
+        let nothing_missing: HashMap<String, Vec<String>> = HashMap::from([("dnf".to_string(), vec![])]);
+
+        let pending = dotfiles_diff(&dotfiles, &home);
+        assert!(!is_in_sync(nothing_missing.values(), &pending));
+
+        // A machine that already has everything, dotfiles stowed included, is in sync
+        symlink("dotfiles/zsh/.zshrc", home.join(".zshrc")).unwrap();
+        let stowed = dotfiles_diff(&dotfiles, &home);
+        assert!(is_in_sync(nothing_missing.values(), &stowed));
+        let missing_task = HashMap::from([("tasks".to_string(), vec!["rustup".to_string()])]);
+        assert!(!is_in_sync(missing_task.values(), &stowed));
+
+        let mut diff = crate::structs::ConfigDiff {
+            os_name: "Fedora".to_string(),
+            os_key: "fedora".to_string(),
+            profile: None,
+            hosts: vec![],
+            packages_to_install: HashMap::new(),
+            tasks_to_run: vec![],
+            install_order: vec![],
+            packages_to_remove: HashMap::from([("dnf".to_string(), vec!["htop".to_string()])]),
+            dotfiles_diff: stowed,
+        };
+        assert!(is_diff_empty(&diff, false));
+        assert!(!is_diff_empty(&diff, true));
+        diff.dotfiles_diff = pending;
+        assert!(!is_diff_empty(&diff, false));
+
+        let _ = std::fs::remove_dir_all(&home);
+    }
+
+    #[test]
+    fn test_stow_conflicts_are_backed_up_and_restored() {
+        use crate::helpers::{back_up_conflicts, restore_backup, stow_conflicts};
+        use std::os::unix::fs::symlink;
+        use std::path::PathBuf;
+
+        let _state = crate::state::TestStateDir::new("backups-state");
+        let home = std::env::temp_dir().join(format!("spinup-backups-{}", std::process::id()));
+        let checkout = home.join("dotfiles");
+        for file in ["zsh/.zshrc", "zsh/.zshenv", "nvim/.config/nvim/init.lua", "git/.gitconfig"] {
+            std::fs::create_dir_all(checkout.join(file).parent().unwrap()).unwrap();
+            std::fs::write(checkout.join(file), file).unwrap();
+        }
+        std::fs::write(home.join(".zshrc"), "local").unwrap();
+        symlink("dotfiles/zsh/.zshenv", home.join(".zshenv")).unwrap();
+        // stow links inside an existing directory, so only the clashing file counts
+        std::fs::create_dir_all(home.join(".config/nvim")).unwrap();
+        std::fs::write(home.join(".config/nvim/init.lua"), "local").unwrap();
+        std::fs::write(home.join(".config/nvim/lazy-lock.json"), "{}").unwrap();
+
+        assert_eq!(stow_conflicts(&checkout.join("zsh"), &home), vec![PathBuf::from(".zshrc")]);
+        assert_eq!(stow_conflicts(&checkout.join("nvim"), &home), vec![PathBuf::from(".config/nvim/init.lua")]);
+        assert!(stow_conflicts(&checkout.join("git"), &home).is_empty());
+
+        crate::journal::start("apply");
+        let backed_up = back_up_conflicts(&checkout.join("zsh"), &home).unwrap();
+        crate::journal::finish(None);
+        assert_eq!(backed_up.len(), 1);
+        assert!(!home.join(".zshrc").exists());
+        assert_eq!(std::fs::read_to_string(&backed_up[0].backup).unwrap(), "local");
+        assert_eq!(crate::journal::load("last").unwrap().backed_up[0].original, backed_up[0].original);
+
+        // A file that reappeared is never overwritten
+        std::fs::write(home.join(".zshrc"), "new").unwrap();
+        assert!(restore_backup(&backed_up[0]).is_err());
+        std::fs::remove_file(home.join(".zshrc")).unwrap();
+
+        assert!(restore_backup(&backed_up[0]).unwrap());
+        assert_eq!(std::fs::read_to_string(home.join(".zshrc")).unwrap(), "local");
+        assert!(!restore_backup(&backed_up[0]).unwrap());
+
+        let _ = std::fs::remove_dir_all(&home);
+    }
+
+    #[tokio::test]
+    async fn test_failed_batch_retries_each_package() {
+        use crate::helpers::install_batch_or_each;
+        use std::cell::RefCell;
+
+        let packages: Vec<String> = ["git", "typo-pkg", "tmux"].iter().map(|p| p.to_string()).collect();
+        let calls = RefCell::new(Vec::new());
+        let outcome = install_batch_or_each(&packages, "dnf", |batch: Vec<String>| {
+            calls.borrow_mut().push(batch.clone());
+            async move {
+                if batch.iter().any(|p| p == "typo-pkg") {
+                    Err(Box::new(std::io::Error::other("No match for typo-pkg")) as Box<dyn std::error::Error>)
+                } else {
+                    Ok(())
+                }
+            }
+        })
+        .await;
+
+        // One batch, then each package on its own
+        assert_eq!(calls.borrow().len(), 4);
+        assert_eq!(outcome.installed, vec!["git", "tmux"]);
+        assert_eq!(outcome.failed, vec![("typo-pkg".to_string(), "No match for typo-pkg".to_string())]);
+
+        // A batch that succeeds, or a single package that fails, isn't retried
+        calls.borrow_mut().clear();
+        let ok = install_batch_or_each(&packages[..1], "dnf", |batch: Vec<String>| {
+            calls.borrow_mut().push(batch);
+            async { Ok(()) }
+        })
+        .await;
+        assert_eq!(ok.installed, vec!["git"]);
+        let single = install_batch_or_each(&packages[1..2], "dnf", |batch: Vec<String>| {
+            calls.borrow_mut().push(batch);
+            async { Err(Box::new(std::io::Error::other("No match")) as Box<dyn std::error::Error>) }
+        })
+        .await;
+        assert_eq!(single.failed.len(), 1);
+        assert_eq!(calls.borrow().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_prune_only_touches_dropped_packages_spinup_installed() {
+        use crate::helpers::packages_to_prune;
+        use crate::structs::ManagedState;
+
+        let config = crate::config::parse_config(
+            r#"
+version = 7
+
+[fedora.manager.dnf]
+packages = ["git", "tmux"]
+"#,
+        )
+        .unwrap();
+
+        let mut managed = ManagedState::default();
+        // tmux: still declared. htop: dropped. ripgrep: dropped, removed by hand since
+        managed.record_installed("dnf", &["tmux".to_string(), "htop".to_string(), "ripgrep".to_string()]);
+        // A whole manager dropped from the config
+        managed.record_installed("flatpak", &["org.gimp.GIMP".to_string()]);
+
+        // Everything is installed except ripgrep, including git and vim that spinup never recorded
+        let mut to_remove = packages_to_prune(&config, "fedora", &managed, |_, package| package != "ripgrep")
+            .await
+            .unwrap();
+        to_remove.values_mut().for_each(|packages| packages.sort());
+
+        assert_eq!(to_remove.len(), 2);
+        assert_eq!(to_remove["dnf"], vec!["htop"]);
+        assert_eq!(to_remove["flatpak"], vec!["org.gimp.GIMP"]);
+
+        let nothing = packages_to_prune(&config, "fedora", &ManagedState::default(), |_, _| true).await.unwrap();
+        assert!(nothing.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_prune_keeps_packages_the_config_only_hides() {
+        use crate::helpers::packages_to_prune;
+        use crate::structs::ManagedState;
+
+        let config = crate::config::parse_config(
+            r#"
+version = 7
+
+[common]
+packages = ["jq"]
+
+[profiles.work.manager.dnf]
+packages = ["awscli"]
+
+[fedora.manager.dnf]
+packages = [{ name = "nvidia-driver", when = "os == plan9" }]
+
+[ubuntu.manager.apt]
+packages = ["curl"]
+"#,
+        )
+        .unwrap();
+
+        let mut managed = ManagedState::default();
+        managed.record_installed(
+            "dnf",
+            &["nvidia-driver".to_string(), "awscli".to_string(), "jq".to_string(), "htop".to_string()],
+        );
+
+        // The condition is false and no profile is active, yet only htop is gone from the config
+        let to_remove = packages_to_prune(&config, "fedora", &managed, |_, _| true).await.unwrap();
+        assert_eq!(to_remove.len(), 1);
+        assert_eq!(to_remove["dnf"], vec!["htop"]);
+
+        // `--os ubuntu` on this machine: dnf belongs to another section, so it's left alone
+        let elsewhere = packages_to_prune(&config, "ubuntu", &managed, |_, _| true).await.unwrap();
+        assert!(elsewhere.is_empty());
+    }
+
+    #[test]
+    fn test_host_overlay_adds_a_manager() {
+        let config = crate::config::parse_config(
+            r#"
+version = 7
+
+[fedora.manager.dnf]
+packages = ["git"]
+
+[hosts."*".manager.flatpak]
+packages = ["com.nvidia.Settings"]
+"#,
+        )
+        .unwrap();
+
+        // "*" matches whatever machine runs the tests
+        let os = crate::helpers::resolved_os_config(&config, "fedora").unwrap();
+        assert_eq!(os.manager["dnf"].packages, vec!["git"]);
+        assert_eq!(os.manager["flatpak"].packages, vec!["com.nvidia.Settings"]);
+    }
+
+    #[test]
+    fn test_unresolved_variables_are_listed_once() {
+        let script = "cp {{src}}/a {{dest}} && cp {{src}}/b {{dest}}".to_string();
+        let cwd = "{{src}}".to_string();
+        let error = crate::helpers::check_unresolved("Task copy", [&script, &cwd].into_iter()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Task copy uses undefined variables {{dest}}, {{src}}, see `spinup validate`"
+        );
+    }
+
+    #[test]
+    fn test_only_os_sections_are_checked_for_v6_tables() {
+        let v6 = crate::config::parse_config("[fedora.applications]\ngit = \"dnf install git\"\n");
+        assert!(v6.unwrap_err().to_string().starts_with("Detected a v6 config"));
+
+        let config = crate::config::parse_config(
+            r#"
+version = 7
+
+[vars]
+applications = "~/Applications"
+
+[profiles.applications.manager.flatpak]
+packages = ["org.gimp.GIMP"]
+
+[fedora.manager.dnf]
+packages = ["git"]
+"#,
+        )
+        .unwrap();
+        assert!(config.profiles.contains_key("applications"));
+    }
 }
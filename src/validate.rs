@@ -0,0 +1,655 @@
+use crate::backends::KNOWN_MANAGERS;
+use crate::condition::{Condition, FACTS};
+use crate::config;
+use crate::graph::{resolve_dependency, DependencyGraph, Node};
+use crate::helpers::{layered_os_config, takes_c_flag, C_FLAG_SHELLS};
+use crate::platform;
+use crate::structs::{Diagnostic, Severity};
+use crate::vars::{self, BUILTINS};
+use std::ops::Range;
+use toml_edit::{ImDocument, Item, Key, TableLike, Value};
+
+pub(crate) const ROOT_KEYS: &[&str] = &["version", "common", "vars", "profiles", "hosts"];
+const COMMON_KEYS: &[&str] = &["packages"];
+const OS_KEYS: &[&str] = &["description", "manager", "tasks", "dotfiles"];
+const MANAGER_KEYS: &[&str] = &["packages", "flags", "depends_on"];
+const TASK_KEYS: &[&str] = &[
+    "script",
+    "description",
+    "depends_on",
+    "check",
+    "creates",
+    "unless",
+    "shell",
+    "env",
+    "cwd",
+    "when",
+];
+const DOTFILES_KEYS: &[&str] = &[
+    "repository",
+    "packages",
+    "target_directory",
+    "target",
+    "dry_run",
+    "dry-run",
+    "exclude",
+];
+const PROFILE_KEYS: &[&str] = &["description", "manager", "tasks", "remove_tasks", "dotfiles"];
+const PROFILE_CHANGES_KEYS: &[&str] = &["packages", "remove"];
+const PACKAGE_KEYS: &[&str] = &["name", "when"];
+// Fields that take `{{name}}` placeholders
+const TASK_VAR_KEYS: &[&str] = &["script", "check", "creates", "unless", "cwd"];
+const DOTFILES_VAR_KEYS: &[&str] = &["repository", "target", "target_directory"];
+const V6_KEYS: &[&str] = &["applications", "dependencies"];
+
+pub const KNOWN_OS_KEYS: &[&str] = &[
+    "fedora", "rhel", "centos", "rocky", "almalinux", "ubuntu", "debian", "pop", "linuxmint",
+    "arch", "manjaro", "endeavouros", "opensuse", "opensuse-tumbleweed", "opensuse-leap", "macos",
+    "linux",
+];
+
+/// Checks a TOML config and returns every problem found, each located by line and column.
+pub fn validate_config(content: &str) -> Vec<Diagnostic> {
+    let mut validator = Validator {
+        content,
+        vars: Vec::new(),
+        diagnostics: Vec::new(),
+    };
+
+    if content.trim_start().starts_with('{') {
+        validator.push(
+            Severity::Error,
+            Some(0..1),
+            "JSON configs are not supported, spinup v7 reads TOML".to_string(),
+            Some("run `spinup migrate` to convert it".to_string()),
+        );
+        return validator.diagnostics;
+    }
+
+    let document = match ImDocument::parse(content) {
+        Ok(document) => document,
+        Err(e) => {
+            validator.push(Severity::Error, e.span(), e.message().trim().to_string(), None);
+            return validator.diagnostics;
+        }
+    };
+
+    if let Some(declared) = document.get("vars").and_then(Item::as_table_like) {
+        validator.vars = declared.iter().map(|(name, _)| name.to_string()).collect();
+    }
+    validator.check_root(document.as_table());
+
+    // Type errors (e.g. a string where a list is expected) come from the real deserializer.
+    if let Err(e) = toml::from_str::<crate::structs::Config>(content) {
+        let already_reported = validator.diagnostics.iter().any(|d| d.severity == Severity::Error);
+        if !already_reported {
+            validator.push(Severity::Error, e.span(), e.message().trim().to_string(), None);
+        }
+    } else if let Ok(config) = config::parse_config(content) {
+        for os_key in config.os_entries.keys() {
+            let Some(mut os_config) = layered_os_config(&config, os_key) else { continue };
+            // Dangling references were reported above; drop them so cycles still surface
+            let snapshot = os_config.clone();
+            for (name, manager) in os_config.manager.iter_mut() {
+                let node = Node::Manager(name.clone());
+                if resolve_dependency(&snapshot, &node, manager.depends_on.as_deref()).is_err() {
+                    manager.depends_on = None;
+                }
+            }
+            for (name, task) in os_config.tasks.iter_mut() {
+                let node = Node::Task(name.clone());
+                if resolve_dependency(&snapshot, &node, task.depends_on.as_deref()).is_err() {
+                    task.depends_on = None;
+                }
+            }
+
+            if let Err(e) = DependencyGraph::build(&os_config).and_then(|graph| graph.order()) {
+                let span = document
+                    .as_table()
+                    .get_key_value(os_key)
+                    .and_then(|(key, _)| key.span());
+                validator.push(Severity::Error, span, format!("[{}]: {}", os_key, e), None);
+                continue;
+            }
+
+            // An overlay can break a section that is fine on its own, e.g. by removing a
+            // task something depends on
+            for (kind, overlays) in [("profiles", &config.profiles), ("hosts", &config.hosts)] {
+                let mut overlays: Vec<_> = overlays.iter().collect();
+                overlays.sort_by_key(|(name, _)| name.as_str());
+                for (name, overlay) in overlays {
+                    let mut applied = os_config.clone();
+                    applied.apply_profile(overlay);
+                    if let Err(e) = DependencyGraph::build(&applied).and_then(|graph| graph.order()) {
+                        let span = document
+                            .get(kind)
+                            .and_then(Item::as_table_like)
+                            .and_then(|overlays| overlays.get_key_value(name))
+                            .and_then(|(key, _)| key.span());
+                        validator.push(Severity::Error, span, format!("[{}] with [{}.{}]: {}", os_key, kind, name, e), None);
+                    }
+                }
+            }
+        }
+    }
+
+    validator.diagnostics.sort_by_key(|d| (d.line, d.column));
+    validator.diagnostics
+}
+
+struct Validator<'a> {
+    content: &'a str,
+    // Names declared in [vars]
+    vars: Vec<String>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Validator<'_> {
+    fn push(&mut self, severity: Severity, span: Option<Range<usize>>, message: String, help: Option<String>) {
+        let (line, column) = span
+            .map(|span| line_column(self.content, span.start))
+            .unwrap_or((1, 1));
+        self.diagnostics.push(Diagnostic {
+            severity,
+            line,
+            column,
+            message,
+            help,
+        });
+    }
+
+    fn check_root(&mut self, root: &toml_edit::Table) {
+        for (name, item) in root.iter() {
+            let key = root.key(name);
+            match name {
+                "version" => {
+                    if item.as_integer() != Some(7) {
+                        self.push(
+                            Severity::Warning,
+                            item.span().or_else(|| key.and_then(Key::span)),
+                            format!("config version is {}, this spinup expects 7", item.to_string().trim()),
+                            None,
+                        );
+                    }
+                }
+                "common" => {
+                    if let Some(common) = self.expect_table(item, key, "common") {
+                        self.check_keys(common, COMMON_KEYS, "common");
+                        self.check_packages(common, "common");
+                    }
+                }
+                "vars" => {
+                    if let Some(declared) = self.expect_table(item, key, "vars") {
+                        self.check_vars(declared);
+                    }
+                }
+                "profiles" | "hosts" => {
+                    let Some(overlays) = self.expect_table(item, key, name) else { continue };
+                    for (overlay_name, overlay) in overlays.iter() {
+                        let overlay_key = overlays.get_key_value(overlay_name).map(|(key, _)| key);
+                        let path = format!("{}.{}", name, overlay_name);
+                        if let Some(overlay) = self.expect_table(overlay, overlay_key, &path) {
+                            self.check_overlay(overlay, &path);
+                        }
+                    }
+                }
+                os_key => {
+                    let Some(os) = item.as_table_like() else {
+                        self.push(
+                            Severity::Error,
+                            key.and_then(Key::span),
+                            format!("unknown top-level key `{}`", os_key),
+                            suggest(os_key, ROOT_KEYS),
+                        );
+                        continue;
+                    };
+                    // Versioned sections such as [fedora-41] layer over [fedora]
+                    let base_key = platform::split_version(os_key).map_or(os_key, |(base, _)| base);
+                    if !KNOWN_OS_KEYS.contains(&base_key) {
+                        self.push(
+                            Severity::Warning,
+                            key.and_then(Key::span),
+                            format!("[{}] does not match any OS spinup can detect", os_key),
+                            suggest(os_key, KNOWN_OS_KEYS),
+                        );
+                    }
+                    let base = platform::split_version(os_key)
+                        .and_then(|(base, _)| root.get(base))
+                        .and_then(Item::as_table_like);
+                    self.check_os(os_key, os, base);
+                }
+            }
+        }
+    }
+
+    /// `base` is the section a versioned one like [fedora-41] layers over.
+    fn check_os(&mut self, os_key: &str, os: &dyn TableLike, base: Option<&dyn TableLike>) {
+        for (name, item) in os.iter() {
+            let key = os.get_key_value(name).map(|(key, _)| key);
+            let path = format!("{}.{}", os_key, name);
+            match name {
+                "description" => {}
+                "manager" => {
+                    let Some(managers) = self.expect_table(item, key, &path) else { continue };
+                    for (manager_name, manager) in managers.iter() {
+                        let manager_key = managers.get_key_value(manager_name).map(|(key, _)| key);
+                        let manager_path = format!("{}.{}", path, manager_name);
+                        if !KNOWN_MANAGERS.contains(&manager_name) {
+                            self.push(
+                                Severity::Warning,
+                                manager_key.and_then(Key::span),
+                                format!(
+                                    "unknown package manager `{}`, spinup will fall back to `sudo {} install`",
+                                    manager_name, manager_name
+                                ),
+                                suggest(manager_name, KNOWN_MANAGERS),
+                            );
+                        }
+                        if let Some(manager) = self.expect_table(manager, manager_key, &manager_path) {
+                            self.check_keys(manager, MANAGER_KEYS, &manager_path);
+                            self.check_packages(manager, &manager_path);
+                            self.check_depends_on(manager, os, base, &manager_path);
+                        }
+                    }
+                }
+                "tasks" => {
+                    let Some(tasks) = self.expect_table(item, key, &path) else { continue };
+                    for (task_name, task) in tasks.iter() {
+                        let task_key = tasks.get_key_value(task_name).map(|(key, _)| key);
+                        let task_path = format!("{}.{}", path, task_name);
+                        let Some(task) = self.expect_table(task, task_key, &task_path) else { continue };
+                        self.check_task(task, task_key, &task_path);
+                        self.check_depends_on(task, os, base, &task_path);
+                    }
+                }
+                "dotfiles" => {
+                    if let Some(dotfiles) = self.expect_table(item, key, &path) {
+                        self.check_keys(dotfiles, DOTFILES_KEYS, &path);
+                        self.check_packages(dotfiles, &path);
+                        self.check_placeholders(dotfiles, DOTFILES_VAR_KEYS, &path);
+                    }
+                }
+                v6 if V6_KEYS.contains(&v6) => self.push(
+                    Severity::Error,
+                    key.and_then(Key::span),
+                    format!("[{}] is a v6 section and is ignored by v7", path),
+                    Some(format!(
+                        "run `spinup migrate` to convert it, or move packages to [{}.manager.<name>] and scripts to [{}.tasks.<name>]",
+                        os_key, os_key
+                    )),
+                ),
+                unknown => self.push(
+                    Severity::Error,
+                    key.and_then(Key::span),
+                    format!("unknown key `{}` in [{}]", unknown, os_key),
+                    suggest(unknown, OS_KEYS),
+                ),
+            }
+        }
+    }
+
+    fn check_task(&mut self, task: &dyn TableLike, task_key: Option<&Key>, task_path: &str) {
+        self.check_keys(task, TASK_KEYS, task_path);
+        self.check_placeholders(task, TASK_VAR_KEYS, task_path);
+        if let Some(when) = task.get("when").and_then(Item::as_value) {
+            self.check_when(when, task_path);
+        }
+        if let Some(shell) = task.get("shell").and_then(Item::as_str)
+            && let [program] = shell.split_whitespace().collect::<Vec<_>>()[..]
+            && !takes_c_flag(program)
+        {
+            self.push(
+                Severity::Error,
+                task.get("shell").and_then(Item::span),
+                format!(
+                    "[{}] shell \"{}\" isn't one spinup passes `-c` to, so it would get the script as a file name",
+                    task_path, shell
+                ),
+                Some(format!(
+                    "use one of {}, or the full command line that takes the script, e.g. \"pwsh -NoProfile -Command\" or \"node -e\"",
+                    C_FLAG_SHELLS.join(", ")
+                )),
+            );
+        }
+        match task.get("script").and_then(Item::as_str) {
+            Some(script) if !script.trim().is_empty() => {}
+            Some(_) => self.push(
+                Severity::Error,
+                task.get("script").and_then(Item::span),
+                format!("[{}] has an empty script", task_path),
+                None,
+            ),
+            None if task.get("script").is_none() => self.push(
+                Severity::Error,
+                task_key.and_then(Key::span),
+                format!("[{}] is missing `script`", task_path),
+                None,
+            ),
+            None => {}
+        }
+    }
+
+    /// Profiles and host overlays change the OS sections they are applied to, so
+    /// `depends_on` is only checked once they are resolved.
+    fn check_overlay(&mut self, overlay: &dyn TableLike, path: &str) {
+        self.check_keys(overlay, PROFILE_KEYS, path);
+
+        if let Some(item) = overlay.get("manager") {
+            let key = overlay.get_key_value("manager").map(|(key, _)| key);
+            let manager_path = format!("{}.manager", path);
+            if let Some(managers) = self.expect_table(item, key, &manager_path) {
+                for (name, changes) in managers.iter() {
+                    let changes_key = managers.get_key_value(name).map(|(key, _)| key);
+                    let changes_path = format!("{}.{}", manager_path, name);
+                    if let Some(changes) = self.expect_table(changes, changes_key, &changes_path) {
+                        self.check_keys(changes, PROFILE_CHANGES_KEYS, &changes_path);
+                        self.check_packages(changes, &changes_path);
+                    }
+                }
+            }
+        }
+
+        if let Some(item) = overlay.get("tasks") {
+            let key = overlay.get_key_value("tasks").map(|(key, _)| key);
+            let tasks_path = format!("{}.tasks", path);
+            if let Some(tasks) = self.expect_table(item, key, &tasks_path) {
+                for (name, task) in tasks.iter() {
+                    let task_key = tasks.get_key_value(name).map(|(key, _)| key);
+                    let task_path = format!("{}.{}", tasks_path, name);
+                    if let Some(task) = self.expect_table(task, task_key, &task_path) {
+                        self.check_task(task, task_key, &task_path);
+                    }
+                }
+            }
+        }
+
+        if let Some(item) = overlay.get("dotfiles") {
+            let key = overlay.get_key_value("dotfiles").map(|(key, _)| key);
+            let dotfiles_path = format!("{}.dotfiles", path);
+            if let Some(dotfiles) = self.expect_table(item, key, &dotfiles_path) {
+                self.check_keys(dotfiles, PROFILE_CHANGES_KEYS, &dotfiles_path);
+                self.check_packages(dotfiles, &dotfiles_path);
+            }
+        }
+    }
+
+    /// Entries of `packages` are names or `{ name, when }` tables.
+    fn check_packages(&mut self, table: &dyn TableLike, path: &str) {
+        let Some(packages) = table.get("packages").and_then(Item::as_array) else { return };
+        for package in packages.iter() {
+            let Some(entry) = package.as_inline_table() else { continue };
+            for (name, value) in entry.iter() {
+                if !PACKAGE_KEYS.contains(&name) {
+                    self.push(
+                        Severity::Error,
+                        value.span(),
+                        format!("unknown key `{}` in a package of [{}]", name, path),
+                        suggest(name, PACKAGE_KEYS),
+                    );
+                }
+            }
+            match (entry.get("name").and_then(Value::as_str), entry.get("when")) {
+                (Some(_), Some(when)) => self.check_when(when, path),
+                (None, _) => self.push(
+                    Severity::Error,
+                    package.span(),
+                    format!("a package of [{}] is a table without `name`", path),
+                    Some("write `{ name = \"pkg\", when = \"...\" }`".to_string()),
+                ),
+                (Some(_), None) => self.push(
+                    Severity::Error,
+                    package.span(),
+                    format!("a package of [{}] is a table without `when`", path),
+                    Some("use a plain string when there is no condition".to_string()),
+                ),
+            }
+        }
+    }
+
+    fn check_when(&mut self, when: &Value, path: &str) {
+        let Some(source) = when.as_str() else {
+            self.push(Severity::Error, when.span(), format!("`when` in [{}] must be a string", path), None);
+            return;
+        };
+        if let Err(e) = Condition::parse(source) {
+            self.push(
+                Severity::Error,
+                when.span(),
+                format!("invalid `when` in [{}]: {}", path, e),
+                Some(format!("conditions test {} with ==, !=, <, >, !, && and ||", FACTS.join(", "))),
+            );
+        }
+    }
+
+    /// [vars] entries are strings that may use built-ins, but not each other.
+    fn check_vars(&mut self, declared: &dyn TableLike) {
+        for (name, item) in declared.iter() {
+            let key = declared.get_key_value(name).map(|(key, _)| key);
+            if vars::is_builtin(name) {
+                self.push(
+                    Severity::Error,
+                    key.and_then(Key::span),
+                    format!("`{}` in [vars] is a built-in variable and can't be redefined", name),
+                    Some(format!("built-ins are {}", BUILTINS.join(", "))),
+                );
+                continue;
+            }
+            let Some(value) = item.as_str() else {
+                self.push(Severity::Error, item.span(), format!("`{}` in [vars] must be a string", name), None);
+                continue;
+            };
+            for (_, used) in vars::placeholders(value) {
+                if !vars::is_builtin(used) {
+                    self.push(
+                        Severity::Error,
+                        item.span(),
+                        format!("`{}` in [vars] uses {{{{{}}}}}, but vars can only use built-in variables", name, used),
+                        Some(format!("built-ins are {}", BUILTINS.join(", "))),
+                    );
+                }
+            }
+        }
+    }
+
+    /// `{{name}}` placeholders in `fields` must be built-ins or declared in [vars].
+    fn check_placeholders(&mut self, table: &dyn TableLike, fields: &[&str], path: &str) {
+        for field in fields {
+            let Some(item) = table.get(field) else { continue };
+            let Some(text) = item.as_str() else { continue };
+            for (_, name) in vars::placeholders(text) {
+                if vars::is_builtin(name) || self.vars.iter().any(|declared| declared == name) {
+                    continue;
+                }
+                let mut candidates: Vec<&str> = self.vars.iter().map(String::as_str).collect();
+                candidates.extend(BUILTINS);
+                self.push(
+                    Severity::Error,
+                    item.span(),
+                    format!("`{}` in [{}] uses undefined variable {{{{{}}}}}", field, path, name),
+                    suggest(name, &candidates)
+                        .or_else(|| Some(format!("declare it in [vars], or use a built-in: {}", BUILTINS.join(", ")))),
+                );
+            }
+        }
+    }
+
+    fn check_keys(&mut self, table: &dyn TableLike, allowed: &[&str], path: &str) {
+        for (name, _) in table.iter() {
+            if !allowed.contains(&name) {
+                let key = table.get_key_value(name).map(|(key, _)| key);
+                self.push(
+                    Severity::Error,
+                    key.and_then(Key::span),
+                    format!("unknown key `{}` in [{}]", name, path),
+                    suggest(name, allowed),
+                );
+            }
+        }
+    }
+
+    fn check_depends_on(&mut self, table: &dyn TableLike, os: &dyn TableLike, base: Option<&dyn TableLike>, path: &str) {
+        let Some(item) = table.get("depends_on") else { return };
+        let Some(target) = item.as_str() else { return };
+
+        let has = |section: &str, name: &str| {
+            [Some(os), base].into_iter().flatten().any(|os| {
+                os.get(section)
+                    .and_then(Item::as_table_like)
+                    .is_some_and(|t| t.contains_key(name))
+            })
+        };
+        let found = if let Some(name) = target.strip_prefix("task:") {
+            has("tasks", name)
+        } else if let Some(name) = target.strip_prefix("manager:") {
+            has("manager", name)
+        } else {
+            has("tasks", target) || has("manager", target)
+        };
+
+        if !found {
+            let mut candidates: Vec<&str> = Vec::new();
+            for os in [Some(os), base].into_iter().flatten() {
+                for section in ["tasks", "manager"] {
+                    if let Some(t) = os.get(section).and_then(Item::as_table_like) {
+                        candidates.extend(t.iter().map(|(name, _)| name));
+                    }
+                }
+            }
+            self.push(
+                Severity::Error,
+                item.span(),
+                format!("[{}] depends on `{}`, which is not a task or manager in this OS section", path, target),
+                suggest(target, &candidates),
+            );
+        }
+    }
+
+    fn expect_table<'i>(&mut self, item: &'i Item, key: Option<&Key>, path: &str) -> Option<&'i dyn TableLike> {
+        let table = item.as_table_like();
+        if table.is_none() {
+            self.push(
+                Severity::Error,
+                item.span().or_else(|| key.and_then(Key::span)),
+                format!("`{}` must be a table", path),
+                None,
+            );
+        }
+        table
+    }
+}
+
+/// Converts a byte offset into a 1-based line and column.
+fn line_column(content: &str, offset: usize) -> (usize, usize) {
+    let offset = offset.min(content.len());
+    let before = &content[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().map(|l| l.chars().count()).unwrap_or(0) + 1;
+    (line, column)
+}
+
+fn suggest(name: &str, candidates: &[&str]) -> Option<String> {
+    candidates
+        .iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= 2)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| format!("did you mean `{}`?", candidate))
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            current.push((previous[j] + cost).min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(content: &str) -> Vec<(Severity, usize, usize, String)> {
+        validate_config(content)
+            .into_iter()
+            .map(|d| (d.severity, d.line, d.column, d.message))
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_config_has_no_diagnostics() {
+        let content = r#"version = 7
+
+[fedora.manager.dnf]
+packages = ["git"]
+depends_on = "repos"
+
+[fedora.tasks.repos]
+script = "echo repos"
+"#;
+        assert!(validate_config(content).is_empty());
+    }
+
+    #[test]
+    fn test_reports_every_problem_with_location() {
+        let content = r#"version = 7
+
+[fedora]
+description = "Fedora"
+
+[fedora.manger.dnf]
+packages = ["git"]
+
+[fedora.applications]
+git = "sudo dnf install -y git"
+
+[fedora.tasks.fonts]
+script = ""
+depends_on = "nerd"
+"#;
+        let diagnostics = validate_config(content);
+        let found = messages(content);
+
+        assert!(found.contains(&(Severity::Error, 6, 9, "unknown key `manger` in [fedora]".to_string())));
+        assert_eq!(diagnostics.iter().find(|d| d.line == 6).unwrap().help.as_deref(), Some("did you mean `manager`?"));
+        assert!(found.iter().any(|(_, line, _, m)| *line == 9 && m.contains("v6 section")));
+        assert!(found.iter().any(|(_, line, _, m)| *line == 13 && m.contains("empty script")));
+        assert!(found.iter().any(|(_, line, _, m)| *line == 14 && m.contains("depends on `nerd`")));
+        assert_eq!(found.iter().filter(|(_, _, _, m)| m.contains("nerd")).count(), 1);
+    }
+
+    #[test]
+    fn test_unknown_manager_is_a_warning() {
+        let content = "version = 7\n\n[fedora.manager.dfn]\npackages = [\"git\"]\n";
+        let diagnostics = validate_config(content);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(diagnostics[0].help.as_deref(), Some("did you mean `dnf`?"));
+    }
+
+    #[test]
+    fn test_undefined_variable_is_an_error() {
+        let content = "version = 7\n\n[vars]\nnvim_version = \"0.10\"\n\n[fedora.tasks.nvim]\nscript = \"get {{nvim_versoin}} {{arch}} {{.Names}}\"\n";
+        let diagnostics = validate_config(content);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (7, 10));
+        assert_eq!(diagnostics[0].help.as_deref(), Some("did you mean `nvim_version`?"));
+    }
+
+    #[test]
+    fn test_unknown_bare_shell_is_an_error() {
+        let content = "version = 7\n\n[fedora.tasks.a]\nscript = \"Get-Date\"\nshell = \"pwsh\"\n\n[fedora.tasks.b]\nscript = \"Get-Date\"\nshell = \"pwsh -Command\"\n\n[fedora.tasks.c]\nscript = \"echo\"\nshell = \"/bin/zsh\"\n";
+        let diagnostics = validate_config(content);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!((diagnostics[0].severity, diagnostics[0].line), (Severity::Error, 5));
+        assert!(diagnostics[0].message.contains("shell \"pwsh\""));
+    }
+}
@@ -0,0 +1,130 @@
+use crate::condition::{self, Facts};
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Variables every config can use without declaring them in [vars].
+pub const BUILTINS: &[&str] = &["home", "user", "arch", "os", "os_version", "hostname", "env.<NAME>"];
+
+/// Values for `{{name}}` placeholders: the built-ins plus the config's [vars] table.
+#[derive(Debug, Clone, Default)]
+pub struct Vars {
+    values: HashMap<String, String>,
+    env: HashMap<String, String>,
+}
+
+impl Vars {
+    /// Built-ins come from `facts`. A [vars] entry can use built-ins, but not other
+    /// entries, and can't replace a built-in.
+    pub fn new(declared: &HashMap<String, String>, facts: &Facts) -> Self {
+        let mut builtins = Vars {
+            values: HashMap::new(),
+            env: facts.env.clone(),
+        };
+        let mut set = |name: &str, value: Option<&String>| {
+            if let Some(value) = value {
+                builtins.values.insert(name.to_string(), value.clone());
+            }
+        };
+        set("home", facts.env.get("HOME"));
+        set("user", facts.env.get("USER").or_else(|| facts.env.get("LOGNAME")));
+        set("arch", Some(&facts.arch));
+        set("os", facts.os.first());
+        set("os_version", facts.os_version.as_ref());
+        set("hostname", facts.hostname.as_ref());
+
+        let mut vars = builtins.clone();
+        for (name, value) in declared {
+            if !is_builtin(name) {
+                vars.values.insert(name.clone(), builtins.interpolate(value));
+            }
+        }
+        vars
+    }
+
+    /// The variables of `declared` on this machine.
+    pub fn current(declared: &HashMap<String, String>) -> Self {
+        Vars::new(declared, condition::current())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        match name.strip_prefix("env.") {
+            Some(env) => self.env.get(env),
+            None => self.values.get(name),
+        }
+        .map(String::as_str)
+    }
+
+    /// Replaces every `{{name}}` with its value. Unknown names are left as they are,
+    /// so callers can refuse to run them (see `placeholders`).
+    pub fn interpolate(&self, text: &str) -> String {
+        let mut result = String::with_capacity(text.len());
+        let mut last = 0;
+        for (span, name) in placeholders(text) {
+            if let Some(value) = self.get(name) {
+                result.push_str(&text[last..span.start]);
+                result.push_str(value);
+                last = span.end;
+            }
+        }
+        result.push_str(&text[last..]);
+        result
+    }
+}
+
+/// Whether `name` is a built-in variable, including any `env.<NAME>`.
+pub fn is_builtin(name: &str) -> bool {
+    name.starts_with("env.") || BUILTINS.contains(&name)
+}
+
+/// The `{{name}}` placeholders in `text` with their byte ranges. Anything that isn't a
+/// plain name, like Go templates' `{{.Names}}`, is not a placeholder.
+pub fn placeholders(text: &str) -> Vec<(Range<usize>, &str)> {
+    let mut found = Vec::new();
+    let mut offset = 0;
+    while let Some(start) = text[offset..].find("{{").map(|i| offset + i) {
+        let Some(end) = text[start + 2..].find("}}").map(|i| start + 2 + i) else { break };
+        let name = text[start + 2..end].trim();
+        if is_name(name) {
+            found.push((start..end + 2, name));
+            offset = end + 2;
+        } else {
+            offset = start + 2;
+        }
+    }
+    found
+}
+
+fn is_name(name: &str) -> bool {
+    let name = name.strip_prefix("env.").unwrap_or(name);
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interpolates_builtins_and_declared_vars() {
+        let facts = Facts {
+            arch: "aarch64".to_string(),
+            os: vec!["fedora".to_string()],
+            env: HashMap::from([("HOME".to_string(), "/home/art".to_string())]),
+            ..Facts::default()
+        };
+        let declared = HashMap::from([
+            ("nvim_url".to_string(), "https://example.com/nvim-{{arch}}.tar.gz".to_string()),
+            ("arch".to_string(), "ignored".to_string()),
+        ]);
+        let vars = Vars::new(&declared, &facts);
+
+        assert_eq!(
+            vars.interpolate("curl -L {{ nvim_url }} -o {{home}}/nvim"),
+            "curl -L https://example.com/nvim-aarch64.tar.gz -o /home/art/nvim"
+        );
+        assert_eq!(vars.interpolate("{{os}} {{env.HOME}}"), "fedora /home/art");
+        // Unknown names stay for the caller to report, Go templates are not placeholders
+        assert_eq!(vars.interpolate("{{missing}} {{.Names}}"), "{{missing}} {{.Names}}");
+        assert_eq!(placeholders("{{missing}} {{.Names}} {{ json . }}").len(), 1);
+    }
+}